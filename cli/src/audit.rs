@@ -0,0 +1,438 @@
+//! local audit log of all writes, one json record per line.
+//!
+//! every backend returned by [`crate::config::EnvironmentConfig::backend`] is wrapped in an
+//! [`AuditedBackend`], so all write paths are recorded. records contain the keys that were
+//! written but never their values.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use home::home_dir;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+
+use crate::{
+    backend::{SecretBackend, SecretData, SecretMetadata},
+    config::MetadataSettings,
+    console::Console,
+    error::CliError,
+    permissions::{PermissionCheck, PermissionResult},
+    vault::now_date_string,
+};
+
+/// overrides the location of the audit log
+pub const AUDIT_LOG_VAR: &str = "TRESOR_AUDIT_LOG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOperation {
+    Write,
+    Patch,
+    Metadata,
+    Delete,
+}
+
+impl Display for AuditOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AuditOperation::Write => "write",
+            AuditOperation::Patch => "patch",
+            AuditOperation::Metadata => "metadata",
+            AuditOperation::Delete => "delete",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for AuditOperation {
+    type Err = CliError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "write" => Ok(AuditOperation::Write),
+            "patch" => Ok(AuditOperation::Patch),
+            "metadata" => Ok(AuditOperation::Metadata),
+            "delete" => Ok(AuditOperation::Delete),
+            _ => Err(CliError::CommandError(format!(
+                "unknown operation {value}, must be one of: write, patch, metadata, delete"
+            ))),
+        }
+    }
+}
+
+/// a single write to a secret
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub timestamp: String,
+    /// the os user running tresor
+    pub user: String,
+    /// accessor of the vault token, `None` for backends without tokens
+    pub token_accessor: Option<String>,
+    pub environment: String,
+    pub operation: AuditOperation,
+    pub mount: String,
+    pub path: String,
+    /// data keys for writes and patches, custom metadata keys for metadata
+    pub keys: Vec<String>,
+    /// version written by writes and patches
+    pub version: Option<u64>,
+    /// arguments with the values of `key=value` inputs removed
+    pub command: Vec<String>,
+}
+
+/// filters for `tresor audit show`, unset fields match every record
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub environment: Option<String>,
+    /// part of `mount/path`
+    pub path: Option<String>,
+    pub user: Option<String>,
+    pub operation: Option<AuditOperation>,
+    /// records at or after this timestamp, a prefix like `2024-05` is enough
+    pub since: Option<String>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.environment
+            .as_ref()
+            .is_none_or(|environment| &record.environment == environment)
+            && self.path.as_ref().is_none_or(|path| {
+                format!("{}/{}", record.mount, record.path).contains(path.as_str())
+            })
+            && self.user.as_ref().is_none_or(|user| &record.user == user)
+            && self
+                .operation
+                .is_none_or(|operation| record.operation == operation)
+            // rfc 3339 timestamps in utc sort like strings
+            && self
+                .since
+                .as_ref()
+                .is_none_or(|since| record.timestamp.as_str() >= since.as_str())
+    }
+}
+
+/// `TRESOR_AUDIT_LOG` or else `~/.config/tresor/audit.jsonl`
+pub fn audit_log_path() -> Result<PathBuf, CliError> {
+    if let Some(path) = std::env::var_os(AUDIT_LOG_VAR) {
+        return Ok(PathBuf::from(path));
+    }
+    let home = home_dir().ok_or(CliError::RuntimeError(
+        "Unable to get your home dir!".to_string(),
+    ))?;
+    Ok(home.join(".config/tresor/audit.jsonl"))
+}
+
+/// appends the record as a single line, the file is only readable by the user
+pub fn append_record(file: &Path, record: &AuditRecord) -> Result<(), CliError> {
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.append(true).create(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    options.open(file)?.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// all records of the log, empty if it doesn't exist yet
+pub fn read_records(file: &Path) -> Result<Vec<AuditRecord>, CliError> {
+    let content = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                CliError::RuntimeError(format!(
+                    "invalid audit record in {} line {}: {e}",
+                    file.display(),
+                    index + 1
+                ))
+            })
+        })
+        .collect()
+}
+
+/// the arguments of the process with the values of `key=value` inputs replaced, `key=@file`
+/// inputs and options are kept
+pub fn redacted_command(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    args.into_iter()
+        .map(|arg| match arg.split_once('=') {
+            Some((key, value)) if !arg.starts_with('-') && !value.starts_with('@') => {
+                format!("{key}=***")
+            }
+            _ => arg,
+        })
+        .collect()
+}
+
+fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".into())
+}
+
+fn sorted_keys<'a>(keys: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut keys: Vec<String> = keys.cloned().collect();
+    keys.sort();
+    keys
+}
+
+/// records every successful write of the wrapped backend in the audit log
+pub struct AuditedBackend {
+    inner: Box<dyn SecretBackend>,
+    environment: String,
+    log_file: Option<PathBuf>,
+    token_accessor: OnceCell<Option<String>>,
+}
+
+impl AuditedBackend {
+    pub fn new(environment: &str, inner: Box<dyn SecretBackend>) -> AuditedBackend {
+        AuditedBackend {
+            inner,
+            environment: environment.to_owned(),
+            log_file: audit_log_path().ok(),
+            token_accessor: OnceCell::new(),
+        }
+    }
+
+    async fn record(
+        &self,
+        operation: AuditOperation,
+        mount: &str,
+        path: &str,
+        keys: Vec<String>,
+        version: Option<u64>,
+    ) {
+        let token_accessor = self
+            .token_accessor
+            .get_or_init(|| async { self.inner.token_accessor().await.ok().flatten() })
+            .await
+            .clone();
+
+        let record = AuditRecord {
+            timestamp: now_date_string(),
+            user: current_user(),
+            token_accessor,
+            environment: self.environment.clone(),
+            operation,
+            mount: mount.to_owned(),
+            path: path.to_owned(),
+            keys,
+            version,
+            command: redacted_command(std::env::args()),
+        };
+
+        tracing::debug!(%operation, mount, path, "recording write in the audit log");
+
+        // the write already happened, so a failing log must not fail the command
+        let result = match &self.log_file {
+            Some(file) => append_record(file, &record),
+            None => Err(CliError::RuntimeError("no audit log location".into())),
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "{}",
+                Console::warning(format!("unable to write the audit log: {e}"))
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl SecretBackend for AuditedBackend {
+    async fn read(&self, mount: &str, path: &str) -> Result<(Option<SecretData>, u64), CliError> {
+        self.inner.read(mount, path).await
+    }
+
+    async fn read_version(
+        &self,
+        mount: &str,
+        path: &str,
+        version: u64,
+    ) -> Result<Option<SecretData>, CliError> {
+        self.inner.read_version(mount, path, version).await
+    }
+
+    async fn write(
+        &self,
+        mount: &str,
+        path: &str,
+        data: SecretData,
+        cas: Option<u64>,
+    ) -> Result<u64, CliError> {
+        let keys = sorted_keys(data.keys());
+        let version = self.inner.write(mount, path, data, cas).await?;
+        self.record(AuditOperation::Write, mount, path, keys, Some(version))
+            .await;
+        Ok(version)
+    }
+
+    async fn patch(
+        &self,
+        mount: &str,
+        path: &str,
+        data: SecretData,
+        cas: Option<u64>,
+    ) -> Result<u64, CliError> {
+        let keys = sorted_keys(data.keys());
+        let version = self.inner.patch(mount, path, data, cas).await?;
+        self.record(AuditOperation::Patch, mount, path, keys, Some(version))
+            .await;
+        Ok(version)
+    }
+
+    async fn list(&self, mount: &str, path: &str) -> Result<Vec<String>, CliError> {
+        self.inner.list(mount, path).await
+    }
+
+    async fn read_metadata(
+        &self,
+        mount: &str,
+        path: &str,
+    ) -> Result<Option<SecretMetadata>, CliError> {
+        self.inner.read_metadata(mount, path).await
+    }
+
+    async fn write_metadata(
+        &self,
+        mount: &str,
+        path: &str,
+        custom_metadata: HashMap<String, String>,
+        settings: &MetadataSettings,
+    ) -> Result<(), CliError> {
+        let keys = sorted_keys(custom_metadata.keys());
+        self.inner
+            .write_metadata(mount, path, custom_metadata, settings)
+            .await?;
+        self.record(AuditOperation::Metadata, mount, path, keys, None)
+            .await;
+        Ok(())
+    }
+
+    async fn delete(&self, mount: &str, path: &str) -> Result<(), CliError> {
+        self.inner.delete(mount, path).await?;
+        self.record(AuditOperation::Delete, mount, path, Vec::new(), None)
+            .await;
+        Ok(())
+    }
+
+    async fn current_version(&self, mount: &str, path: &str) -> Result<u64, CliError> {
+        self.inner.current_version(mount, path).await
+    }
+
+    async fn token_accessor(&self) -> Result<Option<String>, CliError> {
+        self.inner.token_accessor().await
+    }
+
+    async fn check_permissions(
+        &self,
+        checks: &[PermissionCheck],
+    ) -> Result<Option<Vec<PermissionResult>>, CliError> {
+        self.inner.check_permissions(checks).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::memory::MemoryBackend;
+
+    use super::*;
+
+    #[test]
+    fn test_redacted_command() {
+        let args = [
+            "tresor",
+            "set",
+            "prod",
+            "eu",
+            "a=secret",
+            "b=@file.txt",
+            "--x=y",
+        ];
+        assert_eq!(
+            redacted_command(args.iter().map(|arg| arg.to_string())),
+            vec![
+                "tresor",
+                "set",
+                "prod",
+                "eu",
+                "a=***",
+                "b=@file.txt",
+                "--x=y"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audited_writes() -> Result<(), CliError> {
+        let dir = tempfile::tempdir()?;
+        let log_file = dir.path().join("audit.jsonl");
+        let backend = AuditedBackend {
+            log_file: Some(log_file.clone()),
+            ..AuditedBackend::new("staging", Box::new(MemoryBackend::new()))
+        };
+
+        let data = HashMap::from([("b".to_string(), json!("1")), ("a".into(), json!("2"))]);
+        backend.write("kv2", "app", data, Some(0)).await?;
+        backend
+            .patch(
+                "kv2",
+                "app",
+                HashMap::from([("c".into(), json!("3"))]),
+                None,
+            )
+            .await?;
+        backend.delete("kv2", "other").await?;
+
+        let records = read_records(&log_file)?;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].keys, vec!["a", "b"]);
+        assert_eq!(records[0].version, Some(1));
+        assert_eq!(records[1].operation, AuditOperation::Patch);
+        assert_eq!(records[1].version, Some(2));
+        assert_eq!(records[2].token_accessor, None);
+
+        let content = std::fs::read_to_string(&log_file)?;
+        assert!(!content.contains("\"2\""), "{content}");
+
+        let filter = AuditFilter {
+            path: Some("kv2/app".into()),
+            operation: Some(AuditOperation::Patch),
+            since: Some(records[0].timestamp[..7].to_string()),
+            ..AuditFilter::default()
+        };
+        let matching: Vec<&AuditRecord> = records.iter().filter(|r| filter.matches(r)).collect();
+        assert_eq!(matching, vec![&records[1]]);
+        assert!(!AuditFilter {
+            environment: Some("production".into()),
+            ..AuditFilter::default()
+        }
+        .matches(&records[0]));
+        Ok(())
+    }
+}
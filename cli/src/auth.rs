@@ -0,0 +1,198 @@
+//! oidc login storing the token in the environment config
+
+use std::collections::HashMap;
+
+use actix_web::{dev::Server, get, web, App, HttpResponse, HttpServer};
+use once_cell::sync::Lazy;
+use reqwest::Method;
+use serde::Deserialize;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{get_env, write_token, Config, EnvironmentConfig},
+    console::Console,
+    error::CliError,
+    vault::{create_client, response_errors},
+};
+
+static AUTH_RESPONSE: Lazy<Mutex<Option<VaultAuthResponse>>> = Lazy::new(|| Mutex::new(None));
+static SHUTDOWN_SIGNAL: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct CallbackParams {
+    state: String,
+    code: String,
+    scope: String,
+    hd: Option<String>,
+    prompt: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct VaultAuthResponse {
+    pub auth: AuthInfo,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct AuthInfo {
+    pub client_token: String,
+    pub identity_policies: Vec<String>,
+    pub lease_duration: u64,
+}
+
+#[get("/oidc/callback")]
+async fn oidc_callback(
+    env: web::Data<EnvironmentConfig>,
+    params: web::Query<CallbackParams>,
+) -> Result<HttpResponse, CliError> {
+    let auth_response = vault_callback(env.get_ref().to_owned(), params.into_inner()).await?;
+    AUTH_RESPONSE.lock().await.replace(auth_response);
+    Ok(HttpResponse::Ok().body("authentication successful"))
+}
+
+async fn vault_callback(
+    env: EnvironmentConfig,
+    params: CallbackParams,
+) -> Result<VaultAuthResponse, CliError> {
+    let callback_path = format!("auth/{}/oidc/callback", env.auth_mount_or_default());
+    let url = format!("{}/v1/{callback_path}", env.address()?);
+
+    let http = env.http()?;
+    let mut query_params = HashMap::<String, String>::new();
+    query_params.insert("code".into(), params.code);
+    query_params.insert("state".into(), params.state);
+
+    let resp = http
+        .execute("GET", &callback_path, || {
+            http.request(Method::GET, &url)
+                .query(&query_params)
+                .header("X-Vault-Request", "true")
+                .send()
+        })
+        .await?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        return Err(CliError::AuthError(format!(
+            "vault returned an error in auth callback: {status}, {}",
+            response_errors(resp).await?.join(", ")
+        )));
+    }
+
+    let auth = resp.json::<VaultAuthResponse>().await?;
+    Ok(auth)
+}
+
+async fn start_callback_server(env: EnvironmentConfig) -> std::io::Result<Server> {
+    Ok(HttpServer::new(move || {
+        App::new()
+            .service(oidc_callback)
+            .app_data(web::Data::new(env.clone()))
+    })
+    .bind(("0.0.0.0", 8250))?
+    .run())
+}
+
+async fn execute_open_command(url: impl AsRef<str>, cmd: &str) {
+    let output = Command::new(cmd).args([url.as_ref()]).output().await;
+    match output {
+        Ok(_) => (),
+        Err(_) => {
+            println!("auth url: {}", url.as_ref());
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+async fn print_or_open_browser(url: String) {
+    execute_open_command(url, "open").await
+}
+
+#[cfg(target_os = "linux")]
+async fn print_or_open_browser(url: String) {
+    execute_open_command(url, "xdg-open").await
+}
+
+// And this function only gets compiled if the target OS is *not* linux
+#[cfg(all(not(target_os = "linux"), not(target_os = "macos")))]
+fn print_or_open_browser(url: String) {
+    println!("auth url: {}", url);
+}
+
+/// opens the oidc auth url and waits for the callback on port 8250, the received token is
+/// written to the config
+pub async fn login(
+    config: &Config,
+    environment: &str,
+    role: Option<String>,
+) -> Result<VaultAuthResponse, CliError> {
+    let env = get_env(config, environment)
+        .await?
+        .without_context(config)?;
+
+    let callback_url = "http://localhost:8250/oidc/callback";
+    let http = env.http()?;
+    let client = create_client(&env.address()?, None, &http)?;
+    let auth_mount = env.auth_mount_or_default();
+    let auth_url_response = http
+        .execute("POST", &format!("auth/{auth_mount}/oidc/auth_url"), || {
+            vaultrs::auth::oidc::auth(&client, &auth_mount, callback_url, role.clone())
+        })
+        .await?;
+
+    let server = start_callback_server(env.clone()).await?;
+    let handle = server.handle();
+    tokio::spawn(server);
+
+    print_or_open_browser(auth_url_response.auth_url).await;
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        *SHUTDOWN_SIGNAL.lock().await = true;
+    });
+
+    let mut tries = 0;
+    loop {
+        let auth_response: tokio::sync::MutexGuard<'_, Option<VaultAuthResponse>> =
+            AUTH_RESPONSE.lock().await;
+
+        let shutdown_signal = SHUTDOWN_SIGNAL.lock().await;
+        if *shutdown_signal {
+            handle.stop(true).await;
+            return Err(CliError::AuthError(Console::error("canceled")));
+        }
+
+        if let Some(auth) = auth_response.as_ref() {
+            println!("{}", Console::success("token received"));
+            let mut config = config.clone();
+            write_token(
+                &mut config,
+                &env,
+                &auth.auth.client_token,
+                auth.auth.lease_duration,
+            )
+            .await?;
+            return Ok(auth.clone());
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        tries += 1;
+
+        if (tries % 10) == 0 {
+            println!(
+                "{}",
+                Console::highlight(format!("waiting for callback for {tries} seconds"))
+            );
+        }
+
+        if tries > 60 {
+            println!("{}", Console::error("timeout waiting for callback"));
+            break;
+        }
+    }
+
+    handle.stop(true).await;
+    Err(CliError::AuthError(Console::error("authentication failed")))
+}
@@ -0,0 +1,153 @@
+//! storage independent access to versioned secrets
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config::MetadataSettings,
+    error::CliError,
+    permissions::{PermissionCheck, PermissionResult},
+    vault::join_path,
+};
+
+pub type SecretData = HashMap<String, Value>;
+
+/// metadata of a secret, settings are always set when read from a backend
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretMetadata {
+    pub current_version: u64,
+    pub created_time: String,
+    pub updated_time: String,
+    pub custom_metadata: HashMap<String, String>,
+    pub settings: MetadataSettings,
+    pub versions: Vec<SecretVersion>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretVersion {
+    pub version: u64,
+    pub created_time: String,
+    /// deleted or destroyed, the data can not be read anymore
+    pub deleted: bool,
+}
+
+impl SecretMetadata {
+    /// versions which can still be read, sorted ascending
+    pub fn readable_versions(&self) -> Vec<u64> {
+        let mut versions: Vec<u64> = self
+            .versions
+            .iter()
+            .filter(|version| !version.deleted)
+            .map(|version| version.version)
+            .collect();
+        versions.sort();
+        versions
+    }
+}
+
+/// a kv store with versioned secrets and check-and-set writes, like the vault kv2 engine.
+///
+/// `cas` is the version the secret must currently have for a write to succeed, 0 if it must
+/// not exist yet, a mismatch fails with [`CliError::CasConflictError`]
+#[async_trait]
+pub trait SecretBackend: Send + Sync {
+    /// data of the latest version together with the version number,
+    /// data is `None` if the secret does not exist or the latest version is deleted
+    async fn read(&self, mount: &str, path: &str) -> Result<(Option<SecretData>, u64), CliError>;
+
+    /// data of a specific version, `None` if it does not exist or is deleted
+    async fn read_version(
+        &self,
+        mount: &str,
+        path: &str,
+        version: u64,
+    ) -> Result<Option<SecretData>, CliError>;
+
+    /// writes a new version with the given data, returns the new version
+    async fn write(
+        &self,
+        mount: &str,
+        path: &str,
+        data: SecretData,
+        cas: Option<u64>,
+    ) -> Result<u64, CliError>;
+
+    /// writes a new version with the data merged into the latest one, returns the new version
+    async fn patch(
+        &self,
+        mount: &str,
+        path: &str,
+        data: SecretData,
+        cas: Option<u64>,
+    ) -> Result<u64, CliError>;
+
+    /// entries directly below the path, folders end with '/', empty if there are none
+    async fn list(&self, mount: &str, path: &str) -> Result<Vec<String>, CliError>;
+
+    /// metadata and settings of the secret, `None` if it does not exist
+    async fn read_metadata(
+        &self,
+        mount: &str,
+        path: &str,
+    ) -> Result<Option<SecretMetadata>, CliError>;
+
+    /// replaces the custom metadata, settings are only changed if they are set
+    async fn write_metadata(
+        &self,
+        mount: &str,
+        path: &str,
+        custom_metadata: HashMap<String, String>,
+        settings: &MetadataSettings,
+    ) -> Result<(), CliError>;
+
+    /// deletes the metadata and all versions of the secret
+    async fn delete(&self, mount: &str, path: &str) -> Result<(), CliError>;
+
+    /// the current version of the secret to be used for check-and-set, 0 if it does not exist
+    async fn current_version(&self, mount: &str, path: &str) -> Result<u64, CliError> {
+        Ok(self
+            .read_metadata(mount, path)
+            .await?
+            .map(|metadata| metadata.current_version)
+            .unwrap_or(0))
+    }
+
+    /// accessor of the token used for the requests, recorded in the audit log
+    async fn token_accessor(&self) -> Result<Option<String>, CliError> {
+        Ok(None)
+    }
+
+    /// the capabilities of the token for each check, `None` if the backend has no permissions
+    async fn check_permissions(
+        &self,
+        _checks: &[PermissionCheck],
+    ) -> Result<Option<Vec<PermissionResult>>, CliError> {
+        Ok(None)
+    }
+
+    /// lists all secrets below the path, returned paths are relative to the given path
+    async fn list_recursive(&self, mount: &str, path: &str) -> Result<Vec<String>, CliError> {
+        let mut secrets: Vec<String> = Vec::new();
+        let mut folders: Vec<String> = vec!["".into()];
+
+        while let Some(folder) = folders.pop() {
+            let entries = self.list(mount, &join_path(path, &folder)).await?;
+            for entry in entries {
+                let relative = format!("{folder}{entry}");
+                if entry.ends_with('/') {
+                    folders.push(relative);
+                } else {
+                    secrets.push(relative);
+                }
+            }
+        }
+
+        secrets.sort();
+        Ok(secrets)
+    }
+}
@@ -0,0 +1,66 @@
+use tresor::{
+    audit::{audit_log_path, read_records, AuditFilter, AuditRecord},
+    console::Console,
+    error::CliError,
+};
+
+use crate::AuditShowArgs;
+
+pub fn show_audit_log(show_args: &AuditShowArgs) -> Result<(), CliError> {
+    let filter = AuditFilter {
+        environment: show_args.environment.clone(),
+        path: show_args.path.clone(),
+        user: show_args.user.clone(),
+        operation: show_args.operation,
+        since: show_args.since.clone(),
+    };
+
+    let log_file = audit_log_path()?;
+    let records = read_records(&log_file)?;
+    let mut matching: Vec<&AuditRecord> = records
+        .iter()
+        .filter(|record| filter.matches(record))
+        .collect();
+    if let Some(limit) = show_args.limit {
+        matching.drain(..matching.len().saturating_sub(limit));
+    }
+
+    if show_args.json {
+        for record in matching {
+            println!("{}", serde_json::to_string(record)?);
+        }
+        return Ok(());
+    }
+
+    if matching.is_empty() {
+        println!(
+            "{}",
+            Console::warning(format!("no matching writes in {}", log_file.display()))
+        );
+        return Ok(());
+    }
+
+    for record in matching {
+        let version = record
+            .version
+            .map(|version| format!(" (version {version})"))
+            .unwrap_or_default();
+        println!(
+            "{} {} {} {}:{}/{}{version}",
+            Console::emph(&record.timestamp),
+            record.user,
+            Console::warning(record.operation),
+            record.environment,
+            record.mount,
+            Console::highlight(&record.path)
+        );
+        if !record.keys.is_empty() {
+            println!("  keys: {}", record.keys.join(", "));
+        }
+        if let Some(accessor) = &record.token_accessor {
+            println!("  token accessor: {accessor}");
+        }
+        println!("  command: {}", record.command.join(" "));
+    }
+    Ok(())
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+
+use dialoguer::Password;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tresor::{
+    config::{Config, MetadataSettings},
+    console::Console,
+    crypto::{decrypt, encrypt, EncryptionKey},
+    diff::diff_values,
+    error::CliError,
+    vault::{join_path, now_date_string},
+};
+
+use crate::{
+    commands::confirm::{confirm_protected, PlannedWrite},
+    BackupCommandArgs, BackupKeyArgs, ConflictPolicy, RestoreCommandArgs,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupArchive {
+    created_at: String,
+    environment: String,
+    mount: String,
+    path: String,
+    secrets: Vec<BackupSecret>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupSecret {
+    /// relative to the backup path
+    path: String,
+    data: HashMap<String, Value>,
+    custom_metadata: Option<HashMap<String, String>>,
+    settings: MetadataSettings,
+}
+
+pub async fn backup_secrets(
+    backup_args: &BackupCommandArgs,
+    config: &Config,
+) -> Result<(), CliError> {
+    let location = backup_args.location.resolve(config).await?;
+    let backend = location.env.backend()?;
+
+    // the location itself can be a secret as well as a folder
+    let mut relative_paths = vec!["".to_string()];
+    relative_paths.extend(
+        backend
+            .list_recursive(&location.mount, &location.path)
+            .await?,
+    );
+
+    let mut secrets: Vec<BackupSecret> = Vec::new();
+    for relative_path in relative_paths {
+        let path = join_path(&location.path, &relative_path);
+
+        let (data, _) = backend.read(&location.mount, &path).await?;
+        let (Some(data), Some(metadata)) =
+            (data, backend.read_metadata(&location.mount, &path).await?)
+        else {
+            continue;
+        };
+
+        println!(
+            "{} {}/{path}",
+            Console::highlight("exporting"),
+            location.mount
+        );
+
+        secrets.push(BackupSecret {
+            path: relative_path,
+            data,
+            custom_metadata: Some(metadata.custom_metadata),
+            settings: metadata.settings,
+        });
+    }
+
+    if secrets.is_empty() {
+        return Err(CliError::CommandError(format!(
+            "no secrets found at {location}"
+        )));
+    }
+
+    let archive = BackupArchive {
+        created_at: now_date_string(),
+        environment: location.env.name.clone(),
+        mount: location.mount.clone(),
+        path: location.path.clone(),
+        secrets,
+    };
+
+    let key = encryption_key(&backup_args.key, true).await?;
+    let encrypted = encrypt(&serde_json::to_vec(&archive)?, &key)?;
+    write_private_file(&backup_args.archive, &encrypted)?;
+
+    println!(
+        "{} {} secrets from {location} to {}",
+        Console::success("exported"),
+        archive.secrets.len(),
+        backup_args.archive.display()
+    );
+
+    Ok(())
+}
+
+pub async fn restore_secrets(
+    restore_args: &RestoreCommandArgs,
+    config: &Config,
+    yes: bool,
+) -> Result<(), CliError> {
+    let location = restore_args.location.resolve_for_write(config).await?;
+    let backend = location.env.backend()?;
+
+    let key = encryption_key(&restore_args.key, false).await?;
+    let archive: BackupArchive = serde_json::from_slice(&decrypt(
+        &tokio::fs::read(&restore_args.input).await?,
+        &key,
+    )?)?;
+
+    println!(
+        "restoring {} secrets exported from {}:{}/{} at {} into {location}",
+        archive.secrets.len(),
+        archive.environment,
+        archive.mount,
+        archive.path,
+        archive.created_at
+    );
+
+    // all versions are read upfront so the fail policy can reject the restore before any write
+    let mut current_versions: Vec<u64> = Vec::new();
+    for secret in &archive.secrets {
+        let path = join_path(&location.path, &secret.path);
+        current_versions.push(backend.current_version(&location.mount, &path).await?);
+    }
+
+    let conflicts: Vec<String> = archive
+        .secrets
+        .iter()
+        .zip(current_versions.iter())
+        .filter(|(_, version)| **version > 0)
+        .map(|(secret, _)| join_path(&location.path, &secret.path))
+        .collect();
+
+    if !conflicts.is_empty() && restore_args.conflict == ConflictPolicy::Fail {
+        return Err(CliError::CommandError(format!(
+            "secrets already exist at the target, use --conflict skip or overwrite:\n{}",
+            conflicts.join("\n")
+        )));
+    }
+
+    if !restore_args.dry_run && location.env.is_protected() {
+        let mut writes = Vec::new();
+        for (secret, version) in archive.secrets.iter().zip(current_versions.iter()) {
+            if *version > 0 && restore_args.conflict == ConflictPolicy::Skip {
+                continue;
+            }
+            let path = join_path(&location.path, &secret.path);
+            let (current, _) = backend.read(&location.mount, &path).await?;
+            writes.push(PlannedWrite {
+                target: format!("{}/{path}", location.mount),
+                changes: diff_values(&current.unwrap_or_default(), &secret.data),
+                note: None,
+            });
+        }
+        confirm_protected(&location.env, &writes, yes)?;
+    }
+
+    for (secret, version) in archive.secrets.iter().zip(current_versions) {
+        let path = join_path(&location.path, &secret.path);
+        let target = format!("{}/{path}", location.mount);
+
+        if version > 0 && restore_args.conflict == ConflictPolicy::Skip {
+            println!("{} {target}, already exists", Console::warning("skipping"));
+            continue;
+        }
+
+        if restore_args.dry_run {
+            println!(
+                "{} {target} with {} keys (current version: {version})",
+                Console::warning("would restore"),
+                secret.data.len()
+            );
+            continue;
+        }
+
+        backend
+            .write(&location.mount, &path, secret.data.clone(), Some(version))
+            .await?;
+        backend
+            .write_metadata(
+                &location.mount,
+                &path,
+                secret.custom_metadata.clone().unwrap_or_default(),
+                &secret.settings,
+            )
+            .await?;
+
+        println!("{} {target}", Console::success("restored"));
+    }
+
+    Ok(())
+}
+
+async fn encryption_key(
+    key_args: &BackupKeyArgs,
+    confirm: bool,
+) -> Result<EncryptionKey, CliError> {
+    if let Some(key_file) = &key_args.key_file {
+        return EncryptionKey::from_key_file(key_file).await;
+    }
+
+    if let Ok(passphrase) = std::env::var("TRESOR_BACKUP_PASSPHRASE") {
+        return Ok(EncryptionKey::Passphrase(passphrase));
+    }
+
+    let mut prompt = Password::new().with_prompt("archive passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("repeat passphrase", "passphrases do not match");
+    }
+    Ok(EncryptionKey::Passphrase(prompt.interact()?))
+}
+
+fn write_private_file(path: &std::path::Path, content: &[u8]) -> Result<(), CliError> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde_json::{json, Value};
+    use tresor::{backend::SecretBackend, error::CliError, memory::MemoryBackend};
+
+    use crate::{
+        commands::{
+            backup::{backup_secrets, restore_secrets},
+            test::{command, test_config},
+        },
+        Commands,
+    };
+
+    fn data(value: &str) -> HashMap<String, Value> {
+        [("key".to_string(), json!(value))].into()
+    }
+
+    #[tokio::test]
+    async fn test_backup_and_restore() -> Result<(), CliError> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("secrets.json");
+        let config = test_config(&file)?;
+        let backend = MemoryBackend::from_file(&file)?;
+        for path in ["app/x", "app/y"] {
+            backend.write("svc", path, data(path), Some(0)).await?;
+        }
+
+        let key = dir.path().join("key");
+        std::fs::write(&key, "ab".repeat(32))?;
+        let key = key.to_string_lossy().to_string();
+        let archive = dir.path().join("backup.bin");
+        let archive = archive.to_string_lossy().to_string();
+
+        let Commands::Backup(backup_args) =
+            command(&["backup", "local:app:svc:app", &archive, "--key-file", &key])
+        else {
+            unreachable!()
+        };
+        backup_secrets(&backup_args, &config).await?;
+
+        // the default fail policy rejects the restore before anything is written
+        backend
+            .write("svc", "copy/x", data("existing"), Some(0))
+            .await?;
+        let restore = |policy: &str| {
+            command(&[
+                "restore",
+                &archive,
+                "local:app:svc:copy",
+                "--key-file",
+                &key,
+                "--conflict",
+                policy,
+            ])
+        };
+        let Commands::Restore(restore_args) = restore("fail") else {
+            unreachable!()
+        };
+        let error = restore_secrets(&restore_args, &config, true)
+            .await
+            .unwrap_err();
+        assert!(error.message().contains("already exist"));
+        assert_eq!(backend.read("svc", "copy/y").await?, (None, 0));
+
+        // skip keeps the existing secret and restores the others
+        let Commands::Restore(restore_args) = restore("skip") else {
+            unreachable!()
+        };
+        restore_secrets(&restore_args, &config, true).await?;
+        assert_eq!(
+            backend.read("svc", "copy/x").await?,
+            (Some(data("existing")), 1)
+        );
+        assert_eq!(
+            backend.read("svc", "copy/y").await?,
+            (Some(data("app/y")), 1)
+        );
+
+        let Commands::Restore(restore_args) = restore("overwrite") else {
+            unreachable!()
+        };
+        restore_secrets(&restore_args, &config, true).await?;
+        assert_eq!(
+            backend.read("svc", "copy/x").await?,
+            (Some(data("app/x")), 2)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,493 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use dialoguer::{Confirm, Input};
+use serde_yaml::{Mapping, Value};
+use tresor::{
+    config::{get_env, Config},
+    config_file::{get_value, parse_key, parse_scalar, ConfigFile, KeySegment},
+    console::Console,
+    error::CliError,
+    loader::{config_files, load_config_value, ConfigSources, PROJECT_CONFIG_FILE},
+    location::{parse_variables, Location},
+    validate::{config_schema, validate_config},
+};
+
+use crate::{ConfigArgs, ConfigCommands, ConfigFileArgs};
+
+pub async fn config_command(
+    args: &ConfigArgs,
+    config: &Config,
+    sources: &ConfigSources,
+) -> Result<(), CliError> {
+    match args.resolved.as_deref() {
+        Some([environment, context]) => {
+            let variables = parse_variables(&args.variables.clone().unwrap_or_default());
+            show_resolved(config, environment, context, variables).await
+        }
+        _ => show_config(config, sources),
+    }
+}
+
+/// the config subcommands, which work on the files instead of the loaded config
+pub async fn config_file_command(
+    command: &ConfigCommands,
+    project_file: Option<PathBuf>,
+) -> Result<(), CliError> {
+    match command {
+        ConfigCommands::Validate { variables } => {
+            let variables = parse_variables(&variables.clone().unwrap_or_default());
+            validate(project_file, variables).await
+        }
+        ConfigCommands::Schema => {
+            println!("{}", serde_json::to_string_pretty(&config_schema()?)?);
+            Ok(())
+        }
+        ConfigCommands::Init(file) => {
+            let (target, files) = edited_file(project_file, file).await?;
+            init(&target, &files)
+        }
+        ConfigCommands::AddEnv {
+            name,
+            address,
+            auth_mount,
+            namespace,
+            file,
+        } => {
+            let (target, files) = edited_file(project_file, file).await?;
+            let mut config_file = ConfigFile::read(&target)?;
+            let environments = vec![KeySegment::Key("environments".into())];
+            if get_value(&config_file.value()?, &environments)
+                .and_then(|environments| named(environments, name))
+                .is_some()
+            {
+                return Err(CliError::CommandError(format!(
+                    "environment {name} already exists in {}",
+                    target.display()
+                )));
+            }
+
+            let mut env = Mapping::new();
+            env.insert("name".into(), name.as_str().into());
+            env.insert("vaultAddress".into(), address.as_str().into());
+            if let Some(auth_mount) = auth_mount {
+                env.insert("authMount".into(), auth_mount.as_str().into());
+            }
+            if let Some(namespace) = namespace {
+                env.insert("namespace".into(), namespace.as_str().into());
+            }
+            env.insert("contexts".into(), Value::Sequence(Vec::new()));
+
+            config_file.append(&environments, &Value::Mapping(env))?;
+            config_file.write_validated(&files)?;
+            println!(
+                "{} environment {name} to {}",
+                Console::success("added"),
+                target.display()
+            );
+            Ok(())
+        }
+        ConfigCommands::AddContext {
+            environment,
+            name,
+            extends,
+            namespace,
+            variables,
+            file,
+        } => {
+            let (target, files) = edited_file(project_file.clone(), file).await?;
+            let (merged, _) = load_config_value(project_file).await?;
+            let contexts = [
+                KeySegment::Key("environments".into()),
+                KeySegment::Item(environment.clone()),
+                KeySegment::Key("contexts".into()),
+            ];
+            if get_value(&merged, &contexts)
+                .and_then(|contexts| named(contexts, name))
+                .is_some()
+            {
+                return Err(CliError::CommandError(format!(
+                    "context {name} already exists in environment {environment}"
+                )));
+            }
+
+            let mut context = Mapping::new();
+            context.insert("name".into(), name.as_str().into());
+            if let Some(extends) = extends {
+                context.insert("extends".into(), extends.as_str().into());
+            }
+            if let Some(namespace) = namespace {
+                context.insert("namespace".into(), namespace.as_str().into());
+            }
+            let variables = parse_variables(&variables.clone().unwrap_or_default());
+            if !variables.is_empty() {
+                let variables: Mapping = variables
+                    .into_iter()
+                    .map(|(key, value)| (key.into(), value.into()))
+                    .collect();
+                context.insert("variables".into(), Value::Mapping(variables));
+            }
+
+            let mut config_file = ConfigFile::read(&target)?;
+            config_file.append(&contexts, &Value::Mapping(context))?;
+            config_file.write_validated(&files)?;
+            println!(
+                "{} context {name} to environment {environment} in {}",
+                Console::success("added"),
+                target.display()
+            );
+            Ok(())
+        }
+        ConfigCommands::Set { key, value, file } => {
+            let (target, files) = edited_file(project_file, file).await?;
+            let mut config_file = ConfigFile::read(&target)?;
+            config_file.set(&parse_key(key)?, &parse_scalar(value))?;
+            config_file.write_validated(&files)?;
+            println!(
+                "{} {key} in {}",
+                Console::success("updated"),
+                target.display()
+            );
+            Ok(())
+        }
+        ConfigCommands::Get { key } => {
+            let (merged, sources) = load_config_value(project_file).await?;
+            let value = get_value(&merged, &parse_key(key)?)
+                .ok_or_else(|| CliError::CommandError(format!("{key} is not set")))?;
+
+            match value {
+                Value::String(value) => println!("{value}"),
+                value => print!("{}", serde_yaml::to_string(value)?),
+            }
+
+            let mut files: Vec<String> = sources
+                .settings
+                .iter()
+                .filter(|(setting, _)| {
+                    setting.as_str() == key
+                        || setting.starts_with(&format!("{key}."))
+                        || setting.starts_with(&format!("{key}["))
+                })
+                .map(|(_, file)| file.display().to_string())
+                .collect();
+            files.sort();
+            files.dedup();
+            if !files.is_empty() {
+                eprintln!("from {}", Console::emph(files.join(", ")));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// the file to change and all config files in the order they are merged
+async fn edited_file(
+    project_file: Option<PathBuf>,
+    args: &ConfigFileArgs,
+) -> Result<(PathBuf, Vec<PathBuf>), CliError> {
+    let (global, project) = config_files(project_file).await?;
+    let project = match project {
+        None if args.project => Some(std::env::current_dir()?.join(PROJECT_CONFIG_FILE)),
+        project => project,
+    };
+
+    let target = match (&project, args.project) {
+        (Some(project), true) => project.clone(),
+        _ => global.clone(),
+    };
+    let mut files = vec![global];
+    files.extend(project);
+    Ok((target, files))
+}
+
+fn named<'a>(list: &'a Value, name: &str) -> Option<&'a Value> {
+    list.as_sequence()?
+        .iter()
+        .find(|item| item.get("name").and_then(Value::as_str) == Some(name))
+}
+
+/// asks for the basic settings and writes a new config
+fn init(target: &Path, files: &[PathBuf]) -> Result<(), CliError> {
+    let existing = ConfigFile::read(target)?.value()?;
+    let has_environments = existing
+        .get("environments")
+        .and_then(Value::as_sequence)
+        .is_some_and(|environments| !environments.is_empty());
+    if has_environments
+        && !Confirm::new()
+            .with_prompt(format!(
+                "{} already contains environments, replace it?",
+                target.display()
+            ))
+            .default(false)
+            .interact()?
+    {
+        return Err(CliError::CommandError("init aborted".into()));
+    }
+
+    let owner: String = Input::new()
+        .with_prompt("default owner of the secrets, stored in the metadata")
+        .interact_text()?;
+    let environment: String = Input::new()
+        .with_prompt("environment name")
+        .default("staging".into())
+        .interact_text()?;
+    let address: String = Input::new()
+        .with_prompt("vault address")
+        .default("http://localhost:8200".into())
+        .interact_text()?;
+    let auth_mount: String = Input::new()
+        .with_prompt("oidc auth mount, empty for the default")
+        .allow_empty(true)
+        .interact_text()?;
+    let context: String = Input::new()
+        .with_prompt("first context")
+        .default("default".into())
+        .interact_text()?;
+    let mount_template: String = Input::new()
+        .with_prompt("mount template")
+        .default("kv2/{{service}}".into())
+        .interact_text()?;
+    let path_template: String = Input::new()
+        .with_prompt("path template")
+        .default("{{environment}}/{{context}}/{{path}}".into())
+        .interact_text()?;
+
+    let login = environment.clone();
+    let mut env = Mapping::new();
+    env.insert("name".into(), environment.into());
+    env.insert("vaultAddress".into(), address.into());
+    if !auth_mount.is_empty() {
+        env.insert("authMount".into(), auth_mount.into());
+    }
+    let mut first_context = Mapping::new();
+    first_context.insert("name".into(), context.into());
+    env.insert(
+        "contexts".into(),
+        Value::Sequence(vec![Value::Mapping(first_context)]),
+    );
+
+    let mut config_file = ConfigFile::parse(target, "");
+    config_file.set(&parse_key("defaultOwner")?, &owner.into())?;
+    config_file.set(&parse_key("defaultMountTemplate")?, &"default".into())?;
+    config_file.set(&parse_key("defaultPathTemplate")?, &"default".into())?;
+    config_file.set(
+        &parse_key("mountTemplates.default")?,
+        &mount_template.into(),
+    )?;
+    config_file.set(&parse_key("pathTemplates.default")?, &path_template.into())?;
+    config_file.append(&parse_key("environments")?, &Value::Mapping(env))?;
+    config_file.write_validated(files)?;
+
+    println!(
+        "{} {}, login with 'tresor login {login}'",
+        Console::success("config written to"),
+        target.display()
+    );
+    Ok(())
+}
+
+async fn validate(
+    project_file: Option<PathBuf>,
+    variables: HashMap<String, String>,
+) -> Result<(), CliError> {
+    let (value, sources) = load_config_value(project_file).await?;
+
+    println!(
+        "validating {}",
+        Console::highlight(sources.global.display())
+    );
+    if let Some(project) = &sources.project {
+        println!("validating {}", Console::highlight(project.display()));
+    }
+
+    let issues = validate_config(&value, &variables)?;
+    if issues.is_empty() {
+        println!("{}", Console::success("config is valid"));
+        return Ok(());
+    }
+
+    for issue in &issues {
+        println!(
+            "  {}: {}",
+            Console::emph(&issue.location),
+            Console::error(&issue.message)
+        );
+    }
+    Err(CliError::ConfigError(format!(
+        "{} problems found in the config",
+        issues.len()
+    )))
+}
+
+fn show_config(config: &Config, sources: &ConfigSources) -> Result<(), CliError> {
+    let mut clean_config = config.clone();
+
+    clean_config.environments.iter_mut().for_each(|env| {
+        env.token = None;
+        env.token_valid_until = None
+    });
+
+    println!(
+        "global config: {}",
+        Console::highlight(sources.global.display())
+    );
+    if let Some(project) = &sources.project {
+        println!("project config: {}", Console::highlight(project.display()));
+    }
+    println!("\n{}", serde_yaml::to_string(&clean_config)?);
+
+    println!("sources:");
+    for (setting, file) in &sources.settings {
+        if setting.ends_with(".token") || setting.ends_with(".tokenValidUntil") {
+            continue;
+        }
+        println!("  {setting}: {}", Console::emph(file.display()));
+    }
+    Ok(())
+}
+
+/// prints the variables of the context with the source they are taken from
+async fn show_resolved(
+    config: &Config,
+    environment: &str,
+    context: &str,
+    variables: HashMap<String, String>,
+) -> Result<(), CliError> {
+    let env = get_env(config, environment).await?;
+    let context = env.get_context(context, config)?;
+    let layers = context.variable_layers(config, &env, None, None, Some(variables))?;
+
+    let precedence: Vec<String> = layers
+        .iter()
+        .rev()
+        .map(|(source, _)| source.to_string())
+        .collect();
+    println!(
+        "variables of {}/{}, precedence: {}",
+        env.name,
+        context.name,
+        precedence.join(" > ")
+    );
+
+    let mut resolved: Vec<(String, String, String)> = Vec::new();
+    for (source, variables) in &layers {
+        for (name, value) in variables {
+            resolved.retain(|(existing, _, _)| existing != name);
+            resolved.push((name.clone(), value.clone(), source.to_string()));
+        }
+    }
+    resolved.sort();
+
+    let width = resolved
+        .iter()
+        .map(|(name, value, _)| name.len() + value.len())
+        .max()
+        .unwrap_or_default();
+    for (name, value, source) in resolved {
+        let padding = " ".repeat(width - name.len() - value.len());
+        println!(
+            "  {}={value}{padding}  {}",
+            Console::highlight(&name),
+            Console::emph(source)
+        );
+    }
+
+    if let Some(namespace) = context.namespace(config, &env, &Location::default())? {
+        println!("namespace: {}", Console::highlight(namespace));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use tresor::error::CliError;
+
+    use crate::{
+        commands::{
+            config::config_file_command,
+            test::{command, test_home},
+        },
+        Commands, ConfigCommands,
+    };
+
+    fn config_command(args: &[&str]) -> ConfigCommands {
+        match command(args) {
+            Commands::Config(config_args) => config_args.command.unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_config_file_commands() -> Result<(), CliError> {
+        test_home();
+        let dir = tempfile::tempdir()?;
+        let project = dir.path().join(".tresor.yaml");
+        let run = |args: &[&str]| {
+            let command = config_command(args);
+            let project = project.clone();
+            async move { config_file_command(&command, Some(project)).await }
+        };
+        let read = |file: &std::path::Path| -> Result<Value, CliError> {
+            Ok(serde_yaml::from_str(&std::fs::read_to_string(file)?)?)
+        };
+
+        run(&["config", "set", "defaultOwner", "team", "--project"]).await?;
+        run(&["config", "get", "defaultOwner"]).await?;
+        assert_eq!(read(&project)?["defaultOwner"], Value::from("team"));
+
+        // invalid changes are not written
+        let error = run(&["config", "set", "unknownSetting", "x", "--project"])
+            .await
+            .unwrap_err();
+        assert_eq!(error.exit_code(), 4);
+        assert!(read(&project)?.get("unknownSetting").is_none());
+        assert!(run(&["config", "get", "unknownSetting"]).await.is_err());
+
+        // environments are added to the global config, contexts can be added by the project
+        let add_env = [
+            "config",
+            "add-env",
+            "staging",
+            "--address",
+            "http://localhost:8200",
+        ];
+        run(&add_env).await?;
+        assert!(run(&add_env)
+            .await
+            .unwrap_err()
+            .message()
+            .contains("already exists"));
+
+        let add_context = [
+            "config",
+            "add-context",
+            "staging",
+            "app",
+            "--variables",
+            "team=a",
+            "--project",
+        ];
+        run(&add_context).await?;
+        assert!(run(&add_context)
+            .await
+            .unwrap_err()
+            .message()
+            .contains("already exists"));
+
+        let global = read(&test_home().join(".config/tresor/config.yaml"))?;
+        assert_eq!(
+            global["environments"][0]["vaultAddress"],
+            Value::from("http://localhost:8200")
+        );
+        let env = &read(&project)?["environments"][0];
+        assert_eq!(env["name"], Value::from("staging"));
+        assert_eq!(env["contexts"][0]["name"], Value::from("app"));
+        assert_eq!(env["contexts"][0]["variables"]["team"], Value::from("a"));
+
+        Ok(())
+    }
+}
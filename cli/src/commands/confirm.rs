@@ -0,0 +1,108 @@
+use dialoguer::Input;
+use tresor::{
+    backend::{SecretBackend, SecretData},
+    config::EnvironmentConfig,
+    console::Console,
+    diff::{diff_values, patch_values, print_changes, Change},
+    error::CliError,
+    location::ResolvedLocation,
+};
+
+/// with `--yes` writes to a protected environment are only confirmed if this contains its name
+pub const CONFIRM_ENVIRONMENT_VAR: &str = "TRESOR_CONFIRM_ENVIRONMENT";
+
+/// the changes to a single secret, shown before writing to a protected environment
+pub struct PlannedWrite {
+    pub target: String,
+    pub changes: Vec<Change>,
+    /// a note for writes without data changes, like metadata or deleting a secret
+    pub note: Option<String>,
+}
+
+/// shows the masked changes and asks to type the name of the environment if it is protected.
+/// with `--yes` the name has to be in `TRESOR_CONFIRM_ENVIRONMENT` instead, for use in ci
+pub fn confirm_protected(
+    env: &EnvironmentConfig,
+    writes: &[PlannedWrite],
+    yes: bool,
+) -> Result<(), CliError> {
+    if !env.is_protected() {
+        return Ok(());
+    }
+
+    println!(
+        "{}",
+        Console::warning(format!(
+            "environment {} is protected, planned writes:",
+            env.name
+        ))
+    );
+    for write in writes {
+        match &write.note {
+            Some(note) => println!("{}: {note}", Console::highlight(&write.target)),
+            None => println!("{}:", Console::highlight(&write.target)),
+        }
+        if write.changes.is_empty() && write.note.is_none() {
+            println!("no data changes");
+        }
+        print_changes(&write.changes, false);
+    }
+
+    if yes {
+        return match std::env::var(CONFIRM_ENVIRONMENT_VAR) {
+            Ok(name) if name == env.name => Ok(()),
+            _ => Err(CliError::CommandError(format!(
+                "--yes for the protected environment {0} requires {CONFIRM_ENVIRONMENT_VAR}={0}",
+                env.name
+            ))),
+        };
+    }
+
+    let typed: String = Input::new()
+        .with_prompt(format!("type '{}' to confirm", env.name))
+        .allow_empty(true)
+        .interact_text()?;
+    if typed.trim() != env.name {
+        return Err(CliError::CommandError(format!(
+            "aborted, '{}' is not the name of the environment",
+            typed.trim()
+        )));
+    }
+    Ok(())
+}
+
+/// confirms writing the values, or patching them with `patch`, to the location. without values
+/// only the metadata is written
+pub async fn confirm_secret_write(
+    location: &ResolvedLocation,
+    backend: &dyn SecretBackend,
+    values: Option<&SecretData>,
+    patch: bool,
+    yes: bool,
+) -> Result<(), CliError> {
+    if !location.env.is_protected() {
+        return Ok(());
+    }
+
+    let write = match values {
+        Some(values) => {
+            let (current, _) = backend.read(&location.mount, &location.path).await?;
+            let current = current.unwrap_or_default();
+            let after = match patch {
+                true => patch_values(&current, values),
+                false => values.clone(),
+            };
+            PlannedWrite {
+                target: location.secret_path(),
+                changes: diff_values(&current, &after),
+                note: None,
+            }
+        }
+        None => PlannedWrite {
+            target: location.secret_path(),
+            changes: Vec::new(),
+            note: Some("metadata and settings".into()),
+        },
+    };
+    confirm_protected(&location.env, &[write], yes)
+}
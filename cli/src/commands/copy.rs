@@ -0,0 +1,403 @@
+use tresor::{
+    backend::SecretBackend,
+    config::{Config, MetadataSettings},
+    console::Console,
+    diff::diff_values,
+    error::CliError,
+    location::ResolvedLocation,
+    vault::join_path,
+};
+
+use crate::{
+    commands::confirm::{confirm_protected, PlannedWrite},
+    CopyCommandArgs,
+};
+
+struct Endpoint {
+    location: ResolvedLocation,
+    backend: Box<dyn SecretBackend>,
+}
+
+impl Endpoint {
+    fn create(location: ResolvedLocation) -> Result<Endpoint, CliError> {
+        Ok(Endpoint {
+            backend: location.env.backend()?,
+            location,
+        })
+    }
+
+    fn at(&self, relative: &str) -> ResolvedLocation {
+        ResolvedLocation {
+            path: join_path(&self.location.path, relative),
+            ..self.location.clone()
+        }
+    }
+}
+
+/// copies the secrets from source to target, with `delete_source` all readable versions are
+/// copied and the metadata and all versions of each source secret are deleted after they have
+/// been written successfully
+pub async fn copy_secrets(
+    copy_args: &CopyCommandArgs,
+    config: &Config,
+    delete_source: bool,
+    yes: bool,
+) -> Result<(), CliError> {
+    // a move deletes the source, so it is written to as well
+    let source = match delete_source {
+        true => copy_args.source.resolve_for_write(config).await?,
+        false => copy_args.source.resolve(config).await?,
+    };
+    let source = Endpoint::create(source)?;
+    let target = Endpoint::create(copy_args.target.resolve_for_write(config).await?)?;
+
+    if target.location.version.is_some() {
+        return Err(CliError::CommandError(
+            "a version can only be specified for the source".into(),
+        ));
+    }
+
+    if delete_source && source.location.version.is_some() {
+        return Err(CliError::CommandError(
+            "a single version can not be moved, the source is deleted with all its versions, \
+             use cp to copy it"
+                .into(),
+        ));
+    }
+
+    if copy_args.recursive && source.location.version.is_some() {
+        return Err(CliError::CommandError(
+            "a source version can not be combined with --recursive".into(),
+        ));
+    }
+
+    if source.location.is_same_secret(&target.location) {
+        return Err(CliError::CommandError(format!(
+            "source and target are the same: {}",
+            source.location
+        )));
+    }
+
+    let relative_paths = if copy_args.recursive {
+        source
+            .backend
+            .list_recursive(&source.location.mount, &source.location.path)
+            .await?
+    } else {
+        vec!["".into()]
+    };
+
+    if relative_paths.is_empty() {
+        println!(
+            "{}",
+            Console::warning(format!("no secrets found below {}", source.location))
+        );
+        return Ok(());
+    }
+
+    if !copy_args.overwrite {
+        check_targets(&target, &relative_paths).await?;
+    }
+
+    if !copy_args.dry_run {
+        confirm_copy(&source, &target, &relative_paths, delete_source, yes).await?;
+    }
+
+    for relative_path in relative_paths {
+        let from = source.at(&relative_path);
+        let to = target.at(&relative_path);
+
+        let copied = copy_secret(&source, &from, &target, &to, copy_args, delete_source).await?;
+
+        if delete_source && !copy_args.dry_run {
+            if copied == 0 {
+                return Err(CliError::NotFoundError(format!(
+                    "{from} has no readable version, it is not deleted"
+                )));
+            }
+            source.backend.delete(&from.mount, &from.path).await?;
+            println!("{} {from}", Console::success("deleted"));
+        }
+    }
+
+    Ok(())
+}
+
+/// fails if any of the targets already exists, before the first secret is written
+async fn check_targets(target: &Endpoint, relative_paths: &[String]) -> Result<(), CliError> {
+    let mut existing = Vec::new();
+    for relative_path in relative_paths {
+        let to = target.at(relative_path);
+        if target.backend.current_version(&to.mount, &to.path).await? > 0 {
+            existing.push(to.to_string());
+        }
+    }
+
+    match existing.as_slice() {
+        [] => Ok(()),
+        [to] => Err(CliError::CommandError(format!(
+            "target {to} already exists, use --overwrite to write a new version"
+        ))),
+        _ => Err(CliError::CommandError(format!(
+            "targets {} already exist, use --overwrite to write new versions",
+            existing.join(", ")
+        ))),
+    }
+}
+
+/// shows the changes to the target, and for a move the deleted sources, if either environment
+/// is protected and asks for confirmation once before anything is written
+async fn confirm_copy(
+    source: &Endpoint,
+    target: &Endpoint,
+    relative_paths: &[String],
+    delete_source: bool,
+    yes: bool,
+) -> Result<(), CliError> {
+    let target_protected = target.location.env.is_protected();
+    let source_protected = delete_source && source.location.env.is_protected();
+    if !target_protected && !source_protected {
+        return Ok(());
+    }
+
+    let mut writes = Vec::new();
+    let mut deletes = Vec::new();
+    for relative_path in relative_paths {
+        let from = source.at(relative_path);
+        let to = target.at(relative_path);
+
+        if target_protected {
+            let data = match from.version {
+                Some(version) => {
+                    source
+                        .backend
+                        .read_version(&from.mount, &from.path, version)
+                        .await?
+                }
+                None => source.backend.read(&from.mount, &from.path).await?.0,
+            };
+            let (current, _) = target.backend.read(&to.mount, &to.path).await?;
+            writes.push(PlannedWrite {
+                target: to.secret_path(),
+                changes: diff_values(&current.unwrap_or_default(), &data.unwrap_or_default()),
+                note: None,
+            });
+        }
+        if source_protected {
+            deletes.push(PlannedWrite {
+                target: from.secret_path(),
+                changes: Vec::new(),
+                note: Some("delete all versions and metadata".into()),
+            });
+        }
+    }
+
+    // a move within one environment is confirmed once
+    if target.location.env.name == source.location.env.name {
+        writes.append(&mut deletes);
+    }
+    confirm_protected(&target.location.env, &writes, yes)?;
+    if !deletes.is_empty() {
+        confirm_protected(&source.location.env, &deletes, yes)?;
+    }
+    Ok(())
+}
+
+/// copies the selected versions, all readable ones for a move, deleted versions are skipped
+/// unless selected explicitly. returns the number of copied versions
+async fn copy_secret(
+    source: &Endpoint,
+    from: &ResolvedLocation,
+    target: &Endpoint,
+    to: &ResolvedLocation,
+    copy_args: &CopyCommandArgs,
+    all_versions: bool,
+) -> Result<usize, CliError> {
+    let metadata = source
+        .backend
+        .read_metadata(&from.mount, &from.path)
+        .await
+        .map_err(|e| e.prefixed(&format!("unable to read source {from}")))?
+        .ok_or_else(|| CliError::NotFoundError(format!("source {from} does not exist")))?;
+
+    let versions: Vec<u64> = match (from.version, copy_args.all_versions || all_versions) {
+        (Some(version), _) => vec![version],
+        (None, true) => metadata.readable_versions(),
+        (None, false) => vec![metadata.current_version],
+    };
+
+    let target_version = target.backend.current_version(&to.mount, &to.path).await?;
+
+    if copy_args.dry_run {
+        println!(
+            "{} {from} -> {to}, versions: {versions:?}",
+            Console::warning("would copy")
+        );
+        return Ok(versions.len());
+    }
+
+    // check-and-set, each write is based on the version written before
+    let mut cas = target_version;
+    let mut copied = 0;
+    for version in versions {
+        let data = source
+            .backend
+            .read_version(&from.mount, &from.path, version)
+            .await
+            .map_err(|e| {
+                e.prefixed(&format!(
+                    "unable to read version {version} of source {from}"
+                ))
+            })?;
+        let data = match (data, from.version) {
+            (Some(data), _) => data,
+            (None, Some(_)) => {
+                return Err(CliError::NotFoundError(format!(
+                    "version {version} of source {from} is deleted"
+                )))
+            }
+            (None, None) => {
+                println!(
+                    "{} {from} (version {version}), it is deleted",
+                    Console::warning("skipping")
+                );
+                continue;
+            }
+        };
+
+        cas = target
+            .backend
+            .write(&to.mount, &to.path, data, Some(cas))
+            .await?;
+
+        println!(
+            "{} {from} (version {version}) -> {to} (version {cas})",
+            Console::success("copied")
+        );
+        copied += 1;
+    }
+
+    if copied > 0 && !metadata.custom_metadata.is_empty() {
+        target
+            .backend
+            .write_metadata(
+                &to.mount,
+                &to.path,
+                metadata.custom_metadata,
+                &MetadataSettings::default(),
+            )
+            .await?;
+        println!("{} for {to}", Console::success("copied metadata"));
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde_json::{json, Value};
+    use tresor::{
+        backend::SecretBackend, config::MetadataSettings, error::CliError, memory::MemoryBackend,
+    };
+
+    use crate::{
+        commands::{
+            copy::copy_secrets,
+            test::{command, test_config},
+        },
+        Commands, CopyCommandArgs,
+    };
+
+    fn copy_args(args: &[&str]) -> CopyCommandArgs {
+        match command(args) {
+            Commands::Cp(copy_args) | Commands::Mv(copy_args) => copy_args,
+            _ => unreachable!(),
+        }
+    }
+
+    fn data(value: &str) -> HashMap<String, Value> {
+        [("key".to_string(), json!(value))].into()
+    }
+
+    #[tokio::test]
+    async fn test_copy_recursive() -> Result<(), CliError> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("secrets.json");
+        let config = test_config(&file)?;
+        let backend = MemoryBackend::from_file(&file)?;
+        for path in ["a/x", "a/y"] {
+            backend.write("svc", path, data(path), Some(0)).await?;
+        }
+        let metadata: HashMap<String, String> = [("owner".into(), "team".into())].into();
+        backend
+            .write_metadata("svc", "a/x", metadata.clone(), &MetadataSettings::default())
+            .await?;
+
+        let args = copy_args(&["cp", "-r", "local:app:svc:a", "local:app:svc:b"]);
+        copy_secrets(&args, &config, false, true).await?;
+        for path in ["x", "y"] {
+            let (value, version) = backend.read("svc", &format!("b/{path}")).await?;
+            assert_eq!(value, Some(data(&format!("a/{path}"))));
+            assert_eq!(version, 1);
+        }
+        let copied = backend.read_metadata("svc", "b/x").await?.unwrap();
+        assert_eq!(copied.custom_metadata, metadata);
+
+        // one existing target aborts the copy before anything is written
+        backend
+            .write("svc", "c/y", data("existing"), Some(0))
+            .await?;
+        let args = copy_args(&["cp", "-r", "local:app:svc:a", "local:app:svc:c"]);
+        let error = copy_secrets(&args, &config, false, true).await.unwrap_err();
+        assert!(error.message().contains("already exists"));
+        assert_eq!(backend.read("svc", "c/x").await?, (None, 0));
+        assert_eq!(backend.read("svc", "c/y").await?.0, Some(data("existing")));
+
+        let args = copy_args(&[
+            "cp",
+            "-r",
+            "--overwrite",
+            "local:app:svc:a",
+            "local:app:svc:c",
+        ]);
+        copy_secrets(&args, &config, false, true).await?;
+        assert_eq!(backend.read("svc", "c/y").await?, (Some(data("a/y")), 2));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_move() -> Result<(), CliError> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("secrets.json");
+        let config = test_config(&file)?;
+        let backend = MemoryBackend::from_file(&file)?;
+        backend
+            .write("svc", "source", data("first"), Some(0))
+            .await?;
+        backend
+            .write("svc", "source", data("second"), Some(1))
+            .await?;
+
+        let args = copy_args(&["mv", "local:app:svc:source@1", "local:app:svc:target"]);
+        let error = copy_secrets(&args, &config, true, true).await.unwrap_err();
+        assert!(error.message().contains("single version can not be moved"));
+
+        // all versions are moved and the source is deleted
+        let args = copy_args(&["mv", "local:app:svc:source", "local:app:svc:target"]);
+        copy_secrets(&args, &config, true, true).await?;
+        assert_eq!(
+            backend.read("svc", "target").await?,
+            (Some(data("second")), 2)
+        );
+        assert_eq!(
+            backend.read_version("svc", "target", 1).await?,
+            Some(data("first"))
+        );
+        assert!(backend.read_metadata("svc", "source").await?.is_none());
+
+        Ok(())
+    }
+}
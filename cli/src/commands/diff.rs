@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use tresor::{
+    backend::SecretMetadata,
+    config::Config,
+    console::Console,
+    diff::{diff_values, Change},
+    error::CliError,
+    location::ResolvedLocation,
+};
+
+use crate::DiffCommandArgs;
+
+/// compares data and metadata of both locations, returns true if differences were found
+pub async fn diff_locations(
+    diff_args: &DiffCommandArgs,
+    config: &Config,
+) -> Result<bool, CliError> {
+    let left = diff_args.left.resolve(config).await?;
+    let right = diff_args.right.resolve(config).await?;
+
+    println!(
+        "comparing {} with {}:",
+        Console::highlight(&left),
+        Console::highlight(&right)
+    );
+
+    let (left_data, left_metadata) = read_location(&left).await?;
+    let (right_data, right_metadata) = read_location(&right).await?;
+
+    if left_data.is_none() && right_data.is_none() {
+        return Err(CliError::NotFoundError(format!(
+            "neither {left} nor {right} exists"
+        )));
+    }
+
+    // a missing secret is a difference of its own, its data is compared as empty
+    let mut differences = 0;
+    for (location, data) in [(&left, &left_data), (&right, &right_data)] {
+        if data.is_none() {
+            differences += 1;
+            println!("{}", Console::warning(format!("{location} does not exist")));
+        }
+    }
+
+    let data_changes = diff_values(
+        &left_data.unwrap_or_default(),
+        &right_data.unwrap_or_default(),
+    );
+    differences += data_changes.len();
+    println!("data:");
+    print_section(&data_changes, diff_args.show_values);
+
+    if !diff_args.ignore_metadata {
+        let metadata_changes = diff_values(
+            &custom_metadata_values(&left_metadata),
+            &custom_metadata_values(&right_metadata),
+        );
+        differences += metadata_changes.len();
+        println!("metadata:");
+        print_section(&metadata_changes, true);
+
+        let settings_changes = diff_values(
+            &settings_values(&left_metadata),
+            &settings_values(&right_metadata),
+        );
+        differences += settings_changes.len();
+        println!("settings:");
+        print_section(&settings_changes, true);
+    }
+
+    if differences == 0 {
+        println!("{}", Console::success("no differences"));
+    } else {
+        println!(
+            "{}",
+            Console::warning(format!("{differences} difference(s) found"))
+        );
+    }
+
+    Ok(differences > 0)
+}
+
+fn print_section(changes: &[Change], show_values: bool) {
+    if changes.is_empty() {
+        println!("  {}", Console::emph("no differences"));
+    }
+    for change in changes {
+        println!("  {}", change.to_masked_string(show_values))
+    }
+}
+
+/// the data and metadata of the location, no data if the secret does not exist
+async fn read_location(
+    location: &ResolvedLocation,
+) -> Result<(Option<HashMap<String, Value>>, Option<SecretMetadata>), CliError> {
+    let backend = location.env.backend()?;
+
+    let data = match location.version {
+        Some(version) => backend
+            .read_version(&location.mount, &location.path, version)
+            .await
+            .map_err(|e| e.prefixed(&format!("unable to read {location}")))?
+            .ok_or_else(|| {
+                CliError::NotFoundError(format!("version {version} of {location} is deleted"))
+            })
+            .map(Some)?,
+        None => backend.read(&location.mount, &location.path).await?.0,
+    };
+
+    let metadata = backend
+        .read_metadata(&location.mount, &location.path)
+        .await?;
+
+    Ok((data, metadata))
+}
+
+fn custom_metadata_values(metadata: &Option<SecretMetadata>) -> HashMap<String, Value> {
+    metadata
+        .as_ref()
+        .map(|metadata| metadata.custom_metadata.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(key, value)| (key, Value::String(value)))
+        .collect()
+}
+
+fn settings_values(metadata: &Option<SecretMetadata>) -> HashMap<String, Value> {
+    match metadata {
+        Some(metadata) => HashMap::from([
+            ("max_versions".into(), json!(metadata.settings.max_versions)),
+            ("cas_required".into(), json!(metadata.settings.cas_required)),
+            (
+                "delete_version_after".into(),
+                json!(metadata.settings.delete_version_after),
+            ),
+        ]),
+        None => HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+    use tresor::{backend::SecretBackend, error::CliError, memory::MemoryBackend};
+
+    use crate::{
+        commands::{
+            diff::diff_locations,
+            test::{command, test_config},
+        },
+        Commands, DiffCommandArgs,
+    };
+
+    fn diff_args(args: &[&str]) -> DiffCommandArgs {
+        match command(args) {
+            Commands::Diff(diff_args) => diff_args,
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diff_locations() -> Result<(), CliError> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("secrets.json");
+        let config = test_config(&file)?;
+        let backend = MemoryBackend::from_file(&file)?;
+        let data: HashMap<_, _> = [("key".to_string(), json!("value"))].into();
+        backend.write("svc", "left", data.clone(), Some(0)).await?;
+        backend.write("svc", "right", data, Some(0)).await?;
+
+        let args = diff_args(&["diff", "local:app:svc:left", "local:app:svc:right"]);
+        assert!(!diff_locations(&args, &config).await?);
+
+        backend
+            .patch(
+                "svc",
+                "right",
+                [("key".into(), json!("other"))].into(),
+                Some(1),
+            )
+            .await?;
+        assert!(diff_locations(&args, &config).await?);
+
+        // a missing side is a difference, even against an empty secret
+        backend
+            .write("svc", "empty", HashMap::new(), Some(0))
+            .await?;
+        let args = diff_args(&["diff", "local:app:svc:empty", "local:app:svc:missing"]);
+        assert!(diff_locations(&args, &config).await?);
+
+        let args = diff_args(&["diff", "local:app:svc:missing", "local:app:svc:other"]);
+        let error = diff_locations(&args, &config).await.unwrap_err();
+        assert_eq!(error.exit_code(), 6);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::Path,
+};
+
+use dialoguer::Confirm;
+use serde_json::Value;
+use tokio::process::Command;
+
+use tresor::{
+    config::Config,
+    console::Console,
+    diff::{diff_values, print_changes},
+    error::CliError,
+};
+
+use crate::{
+    commands::{
+        confirm::{confirm_protected, PlannedWrite},
+        metadata::set_metadata_from_args,
+    },
+    EditCommandArgs, EditFormat,
+};
+
+pub async fn edit_secret(
+    edit_args: &EditCommandArgs,
+    config: &Config,
+    yes: bool,
+) -> Result<(), CliError> {
+    let location = edit_args
+        .context
+        .location()
+        .resolve_for_write(config)
+        .await?;
+    let (mount, path) = (&location.mount, &location.path);
+    let backend = location.env.backend()?;
+
+    let (current, version) = backend.read(mount, path).await?;
+
+    if current.is_none() {
+        println!(
+            "{}",
+            Console::warning(format!(
+                "no value found at {mount}/{path}, this will be a create operation"
+            ))
+        );
+    }
+    let current = current.unwrap_or_default();
+
+    let mut content = format_values(&current, &edit_args.format)?;
+    let edited = loop {
+        content = edit_in_editor(&content, &edit_args.format).await?;
+
+        match parse_values(&content, &edit_args.format) {
+            Ok(values) => break values,
+            Err(e) => {
+                println!("{}", Console::error(format!("invalid input: {e}")));
+                if !Confirm::new()
+                    .with_prompt("edit again?")
+                    .default(true)
+                    .interact()?
+                {
+                    return Err(CliError::CommandError("edit aborted".into()));
+                }
+            }
+        }
+    };
+
+    let changes = diff_values(&current, &edited);
+    if changes.is_empty() {
+        println!("{}", Console::warning("no changes"));
+        return Ok(());
+    }
+
+    if location.env.is_protected() {
+        let write = PlannedWrite {
+            target: format!("{} (version {version})", location.secret_path()),
+            changes,
+            note: None,
+        };
+        confirm_protected(&location.env, &[write], yes)?;
+    } else {
+        println!("changes for {mount}/{path} (version {version}):");
+        print_changes(&changes, edit_args.show_values);
+
+        if !yes
+            && !Confirm::new()
+                .with_prompt("apply changes?")
+                .default(false)
+                .interact()?
+        {
+            return Err(CliError::CommandError("edit aborted".into()));
+        }
+    }
+
+    let written_version = backend.write(mount, path, edited, Some(version)).await?;
+
+    println!(
+        "{} {mount}/{path}, version: {}",
+        Console::success("updated data"),
+        written_version
+    );
+
+    set_metadata_from_args(backend.as_ref(), &edit_args.metadata, config, mount, path).await?;
+
+    println!("{}", Console::success("metadata updated"));
+
+    Ok(())
+}
+
+fn format_values(values: &HashMap<String, Value>, format: &EditFormat) -> Result<String, CliError> {
+    // sorted so the editor content is stable
+    let sorted: BTreeMap<&String, &Value> = values.iter().collect();
+    match format {
+        EditFormat::Yaml => Ok(serde_yaml::to_string(&sorted)?),
+        EditFormat::Json => Ok(serde_json::to_string_pretty(&sorted)?),
+    }
+}
+
+fn parse_values(content: &str, format: &EditFormat) -> Result<HashMap<String, Value>, CliError> {
+    match format {
+        EditFormat::Yaml => Ok(serde_yaml::from_str(content)?),
+        EditFormat::Json => Ok(serde_json::from_str(content)?),
+    }
+}
+
+// the secret is written to a private temp dir, which also receives swap files of the editor,
+// all files are overwritten before the dir is removed
+async fn edit_in_editor(content: &str, format: &EditFormat) -> Result<String, CliError> {
+    let temp_dir = tempfile::Builder::new().prefix("tresor-").tempdir()?;
+    let file_path = temp_dir.path().join(match format {
+        EditFormat::Yaml => "secret.yaml",
+        EditFormat::Json => "secret.json",
+    });
+
+    tokio::fs::write(&file_path, content).await?;
+
+    let edited = match run_editor(&file_path).await {
+        Ok(()) => tokio::fs::read_to_string(&file_path)
+            .await
+            .map_err(CliError::from),
+        Err(e) => Err(e),
+    };
+
+    wipe_dir(temp_dir.path());
+    temp_dir.close()?;
+
+    edited
+}
+
+async fn run_editor(file_path: &Path) -> Result<(), CliError> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+
+    // editors like 'code --wait' need their arguments
+    let mut editor_parts = editor.split_whitespace();
+    let program = editor_parts.next().unwrap_or("vi");
+
+    let status = Command::new(program)
+        .args(editor_parts)
+        .arg(file_path)
+        .status()
+        .await?;
+
+    if !status.success() {
+        return Err(CliError::CommandError(format!(
+            "editor '{editor}' exited with {status}"
+        )));
+    }
+    Ok(())
+}
+
+fn wipe_dir(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        if let Ok(mut file) = std::fs::OpenOptions::new().write(true).open(&path) {
+            let _ = file.write_all(&vec![0u8; metadata.len() as usize]);
+            let _ = file.sync_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use serde_json::{json, Value};
+    use tresor::{backend::SecretBackend, error::CliError, memory::MemoryBackend};
+
+    use crate::{
+        commands::{
+            edit::edit_secret,
+            test::{command, test_config},
+        },
+        Commands,
+    };
+
+    fn data(value: &str) -> HashMap<String, Value> {
+        [("key".to_string(), json!(value))].into()
+    }
+
+    #[tokio::test]
+    async fn test_edit_secret() -> Result<(), CliError> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("secrets.json");
+        let config = test_config(&file)?;
+        let backend = MemoryBackend::from_file(&file)?;
+        backend.write("svc", "app", data("before"), Some(0)).await?;
+
+        // the editor takes a moment, so the secret can be changed while it is open
+        let editor = dir.path().join("editor.sh");
+        std::fs::write(&editor, "sleep 1\nprintf 'key: edited\\n' > \"$1\"\n")?;
+        std::env::set_var("VISUAL", format!("sh {}", editor.display()));
+
+        let Commands::Edit(edit_args) =
+            command(&["edit", "local", "app", "-s", "svc", "-p", "app"])
+        else {
+            unreachable!()
+        };
+        edit_secret(&edit_args, &config, true).await?;
+        assert_eq!(backend.read("svc", "app").await?, (Some(data("edited")), 2));
+
+        // the write of the edit is based on the version read before the editor was opened
+        backend.write("svc", "app", data("before"), Some(2)).await?;
+        let concurrent_write = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            backend.write("svc", "app", data("other"), Some(3)).await
+        };
+        let (edited, written) =
+            tokio::join!(edit_secret(&edit_args, &config, true), concurrent_write);
+        written?;
+        assert_eq!(edited.unwrap_err().exit_code(), 9);
+        assert_eq!(backend.read("svc", "app").await?, (Some(data("other")), 4));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use tresor::{
+    backend::SecretBackend, config::Config, console::Console, error::CliError,
+    vault::now_date_string,
+};
+
+use crate::MetadataArgs;
+
+/// replaces the custom metadata with the owner and rotation from the args or their defaults
+pub async fn set_metadata_from_args(
+    backend: &dyn SecretBackend,
+    metadata: &MetadataArgs,
+    config: &Config,
+    mount: &str,
+    path: &str,
+) -> Result<(), CliError> {
+    let mut custom_metadata: HashMap<String, String> = HashMap::new();
+
+    custom_metadata.insert(
+        "owner".into(),
+        metadata
+            .metadata_owner
+            .clone()
+            .unwrap_or(config.default_owner.to_string()),
+    );
+
+    if metadata.metadata_rotation.unwrap_or(false) {
+        custom_metadata.insert(
+            "maxTTL".into(),
+            metadata.metadata_max_ttl.clone().unwrap_or("90d".into()),
+        );
+        custom_metadata.insert("mustRotate".into(), "true".into());
+        custom_metadata.insert(
+            "lastRotation".into(),
+            metadata
+                .metadata_rotation_date
+                .clone()
+                .unwrap_or_else(now_date_string),
+        );
+    } else {
+        println!(
+            "{}",
+            Console::warning("not setting rotation metadata (see command options)")
+        )
+    }
+
+    let settings = config
+        .metadata_settings_or_default()
+        .merge(&metadata.settings());
+
+    backend
+        .write_metadata(mount, path, custom_metadata, &settings)
+        .await
+}
+
+/// changes only the custom metadata and settings given in the args, everything else of the
+/// secret is kept. a secret without metadata gets the defaults like with a write
+pub async fn merge_metadata_from_args(
+    backend: &dyn SecretBackend,
+    metadata: &MetadataArgs,
+    config: &Config,
+    mount: &str,
+    path: &str,
+) -> Result<(), CliError> {
+    let Some(current) = backend.read_metadata(mount, path).await? else {
+        return set_metadata_from_args(backend, metadata, config, mount, path).await;
+    };
+    let mut custom_metadata = current.custom_metadata;
+
+    if let Some(owner) = &metadata.metadata_owner {
+        custom_metadata.insert("owner".into(), owner.clone());
+    }
+    if let Some(rotation) = metadata.metadata_rotation {
+        custom_metadata.insert("mustRotate".into(), rotation.to_string());
+        if rotation && !custom_metadata.contains_key("maxTTL") {
+            custom_metadata.insert("maxTTL".into(), "90d".into());
+        }
+        if rotation && metadata.metadata_rotation_date.is_none() {
+            custom_metadata.insert("lastRotation".into(), now_date_string());
+        }
+    }
+    if let Some(max_ttl) = &metadata.metadata_max_ttl {
+        custom_metadata.insert("maxTTL".into(), max_ttl.clone());
+    }
+    if let Some(date) = &metadata.metadata_rotation_date {
+        custom_metadata.insert("lastRotation".into(), date.clone());
+    }
+
+    backend
+        .write_metadata(mount, path, custom_metadata, &metadata.settings())
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tresor::{
+        backend::SecretBackend,
+        config::{Config, MetadataSettings},
+        error::CliError,
+        memory::MemoryBackend,
+    };
+
+    use crate::{commands::metadata::merge_metadata_from_args, MetadataArgs};
+
+    #[tokio::test]
+    async fn test_merge_metadata() -> Result<(), CliError> {
+        let backend = MemoryBackend::new();
+        let existing: HashMap<String, String> = [
+            ("owner", "team-a"),
+            ("mustRotate", "true"),
+            ("lastRotation", "2024-01-01T00:00:00.000Z"),
+            ("maxTTL", "30d"),
+            ("syncedBy", "tresor"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        backend.write("kv", "app", HashMap::new(), Some(0)).await?;
+        backend
+            .write_metadata("kv", "app", existing.clone(), &MetadataSettings::default())
+            .await?;
+
+        // only the max versions are given, the custom metadata is kept
+        let args = MetadataArgs {
+            metadata_max_versions: Some(5),
+            ..MetadataArgs::default()
+        };
+        merge_metadata_from_args(&backend, &args, &Config::default(), "kv", "app").await?;
+        let metadata = backend.read_metadata("kv", "app").await?.unwrap();
+        assert_eq!(metadata.custom_metadata, existing);
+        assert_eq!(metadata.settings.max_versions, Some(5));
+
+        let args = MetadataArgs {
+            metadata_owner: Some("team-b".into()),
+            ..MetadataArgs::default()
+        };
+        merge_metadata_from_args(&backend, &args, &Config::default(), "kv", "app").await?;
+        let metadata = backend.read_metadata("kv", "app").await?.unwrap();
+        assert_eq!(metadata.custom_metadata["owner"], "team-b");
+        assert_eq!(metadata.custom_metadata["maxTTL"], "30d");
+        assert_eq!(metadata.settings.max_versions, Some(5));
+
+        Ok(())
+    }
+}
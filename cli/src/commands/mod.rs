@@ -0,0 +1,65 @@
+pub mod audit;
+pub mod backup;
+pub mod config;
+pub mod confirm;
+pub mod copy;
+pub mod diff;
+pub mod edit;
+pub mod metadata;
+pub mod sync;
+
+#[cfg(test)]
+pub mod test {
+    use std::path::{Path, PathBuf};
+
+    use clap::Parser;
+    use once_cell::sync::Lazy;
+    use tempfile::TempDir;
+    use tresor::{config::Config, error::CliError};
+
+    use crate::{Commands, TresorArgs};
+
+    /// the home of all command tests, so the global config and the audit log are never the real
+    /// ones. set once, as the environment is shared by all tests
+    static HOME: Lazy<TempDir> = Lazy::new(|| {
+        let home = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", home.path());
+        std::env::remove_var(tresor::audit::AUDIT_LOG_VAR);
+        home
+    });
+
+    pub fn test_home() -> PathBuf {
+        HOME.path().to_path_buf()
+    }
+
+    /// a config with the environment `local` and its context `app`, keeping the secrets in the
+    /// file. the mount is the service and the path is the given one
+    pub fn test_config(secrets_file: &Path) -> Result<Config, CliError> {
+        test_home();
+        Ok(serde_yaml::from_str(&format!(
+            r#"
+defaultOwner: me
+defaultMountTemplate: default
+defaultPathTemplate: default
+mountTemplates:
+  default: "{{{{service}}}}"
+pathTemplates:
+  default: "{{{{path}}}}"
+environments:
+  - name: local
+    vaultAddress: http://localhost:1
+    secretsFile: {}
+    contexts:
+      - name: app
+"#,
+            secrets_file.display()
+        ))?)
+    }
+
+    /// the command parsed from the arguments, without the program name
+    pub(crate) fn command(args: &[&str]) -> Commands {
+        TresorArgs::try_parse_from(["tresor"].iter().chain(args))
+            .unwrap()
+            .command
+    }
+}
@@ -0,0 +1,166 @@
+use serde_json::Value;
+use tresor::{
+    config::{get_env, get_env_for_write, Config},
+    console::{mask_value, Console},
+    diff::Change,
+    error::CliError,
+    sync::{sync_mappings, MappingResult, MappingStatus, SyncOptions, SyncReport},
+};
+
+use crate::{
+    commands::confirm::{confirm_protected, PlannedWrite},
+    SyncCommandArgs,
+};
+
+pub async fn sync_command(
+    sync_args: &SyncCommandArgs,
+    config: &Config,
+    yes: bool,
+) -> Result<(), CliError> {
+    println!(
+        "syncing environment {}, apply: {}",
+        Console::highlight(&sync_args.context.env.environment),
+        Console::warning(sync_args.apply)
+    );
+
+    let options = SyncOptions {
+        location: sync_args.context.location(),
+        apply: sync_args.apply,
+        metadata_only: sync_args.metadata_only,
+        parallelism: sync_args.parallelism,
+    };
+
+    // the exact names of a protected environment are checked before anything is previewed
+    let env = if options.apply {
+        get_env_for_write(
+            config,
+            &options.location.environment,
+            &options.location.context,
+        )?
+    } else {
+        get_env(config, &options.location.environment).await?
+    };
+    if options.apply && env.is_protected() {
+        let preview = sync_mappings(
+            &SyncOptions {
+                apply: false,
+                ..options.clone()
+            },
+            config,
+        )
+        .await?;
+        if let Some(error) = preview.error {
+            return Err(error);
+        }
+        confirm_protected(&env, &planned_writes(&preview, sync_args), yes)?;
+    }
+
+    let report = sync_mappings(&options, config).await?;
+
+    let mut current_context: Option<&str> = None;
+    for result in &report.results {
+        if current_context != Some(&result.context) {
+            println!(
+                "syncing mappings for context {}",
+                Console::highlight(&result.context)
+            );
+            current_context = Some(&result.context);
+        }
+        print_result(result, sync_args);
+    }
+
+    // the mappings synced before an error are printed above, so it is clear what was written
+    match report.error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// the writes a sync would do, one per updated mapping
+fn planned_writes(report: &SyncReport, sync_args: &SyncCommandArgs) -> Vec<PlannedWrite> {
+    report
+        .results
+        .iter()
+        .filter(|result| result.status == MappingStatus::WouldUpdate)
+        .map(|result| {
+            let target = result.target.clone().unwrap_or_default();
+            let key = target.rsplit('#').next().unwrap_or_default().to_string();
+            let after = Value::String(result.value.clone().unwrap_or_default());
+            let changes = match (&result.previous, sync_args.metadata_only) {
+                (_, true) => vec![],
+                (None, false) => vec![Change::Added { key, value: after }],
+                (Some(previous), false)
+                    if *previous == result.value.clone().unwrap_or_default() =>
+                {
+                    vec![]
+                }
+                (Some(previous), false) => vec![Change::Changed {
+                    key,
+                    before: Value::String(previous.clone()),
+                    after,
+                }],
+            };
+            PlannedWrite {
+                target: format!("{} {target}", result.context),
+                note: changes.is_empty().then(|| "metadata".into()),
+                changes,
+            }
+        })
+        .collect()
+}
+
+fn print_result(result: &MappingResult, sync_args: &SyncCommandArgs) {
+    let mapping = &result.mapping;
+    let target = result.target.clone().unwrap_or_default();
+    let message = format!(
+        "{target}, with value: {} from source: {}",
+        mask_value(
+            &result.value.clone().unwrap_or_default(),
+            sync_args.show_values
+        ),
+        result.source.clone().unwrap_or("config value".into())
+    );
+
+    if result.created {
+        println!(
+            "{}",
+            Console::warning("no value found at target, this will be a create operation")
+        );
+    }
+
+    match result.status {
+        MappingStatus::Skipped => println!(
+            "{}",
+            Console::highlight(format!(
+                "skipping mapping: {mapping}, 'when' expression is false"
+            ))
+        ),
+        MappingStatus::NoSourceValue => println!("no source value found for {mapping}"),
+        MappingStatus::WouldUpdate => println!(
+            "{} (metadata only: {}) {message}",
+            Console::warning("would update"),
+            sync_args.metadata_only
+        ),
+        MappingStatus::Updated => {
+            if sync_args.metadata_only {
+                println!("{}", Console::warning("not setting data, only metadata"))
+            } else {
+                println!("{} {message}", Console::success("updated data"))
+            }
+
+            println!(
+                "{} for {target} with {:?}",
+                Console::success("updated metadata"),
+                result.metadata
+            );
+
+            if !result.metadata_settings.is_empty() {
+                println!(
+                    "{} for {target} with {:?}",
+                    Console::success("updated settings"),
+                    result.metadata_settings
+                )
+            }
+        }
+    }
+}
@@ -1,65 +1,167 @@
+//! config model, loaded from `~/.config/tresor/config.yaml`
+
 use std::{collections::HashMap, fmt::Display, path::PathBuf};
 
 use home::home_dir;
 
 use minijinja::Environment;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tokio::{fs::File, io::AsyncWriteExt};
 use vaultrs::client::VaultClient;
 
 use crate::{
+    audit::AuditedBackend,
+    backend::SecretBackend,
     console::Console,
     error::CliError,
+    http::{HttpClient, HttpSettings, TlsSettings},
+    loader::{load_config, read_yaml},
+    location::Location,
+    memory::MemoryBackend,
     template::track_context,
-    vault::{create_client, now_date_string, Vault},
-    VaultContextArgs,
+    vault::{create_client, now_date_string, KvVersion, Vault},
 };
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[schemars(deny_unknown_fields)]
 pub struct ContextConfig {
     pub name: String,
     pub variables: Option<HashMap<String, String>>,
+    /// vault enterprise namespace, overrides the one of the environment, can be a template
+    pub namespace: Option<String>,
+    /// name of a context in the same environment whose variables and namespace are inherited
+    pub extends: Option<String>,
+}
+
+/// where a template variable comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableSource {
+    /// `context`, `environment`, `now`, `service` and `path`
+    BuiltIn,
+    Global,
+    Environment(String),
+    Context(String),
+    /// the `--variables` of the command or the query of a location
+    Command,
+}
+
+/// variables defined by one source
+pub type VariableLayer = (VariableSource, HashMap<String, String>);
+
+impl Display for VariableSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BuiltIn => write!(f, "built-in"),
+            Self::Global => write!(f, "global"),
+            Self::Environment(name) => write!(f, "environment {name}"),
+            Self::Context(name) => write!(f, "context {name}"),
+            Self::Command => write!(f, "--variables"),
+        }
+    }
 }
 
 impl ContextConfig {
-    fn variables_map(
+    /// the contexts this one extends, the closest parent first
+    pub fn parents(&self, env: &EnvironmentConfig) -> Result<Vec<ContextConfig>, CliError> {
+        let mut parents: Vec<ContextConfig> = Vec::new();
+        let mut current = self.clone();
+
+        while let Some(parent_name) = current.extends.clone() {
+            if parent_name == self.name || parents.iter().any(|p| p.name == parent_name) {
+                return Err(CliError::CommandError(format!(
+                    "context {} extends itself via {}",
+                    self.name, current.name
+                )));
+            }
+            current = env
+                .contexts
+                .iter()
+                .find(|context| context.name == parent_name)
+                .cloned()
+                .ok_or_else(|| {
+                    CliError::CommandError(format!(
+                        "context {} extends {parent_name} which doesn't exist in {}",
+                        current.name, env.name
+                    ))
+                })?;
+            parents.push(current.clone());
+        }
+        Ok(parents)
+    }
+
+    /// the variables of all sources from the lowest to the highest precedence:
+    /// built-in < global < environment < parent contexts < context < command
+    pub fn variable_layers(
         &self,
-        env: &str,
+        config: &Config,
+        env: &EnvironmentConfig,
         path: Option<String>,
         service: Option<String>,
         variables: Option<HashMap<String, String>>,
-    ) -> HashMap<String, String> {
-        let mut replacement_values: HashMap<String, String> = variables.unwrap_or_default();
-        replacement_values.insert("context".into(), self.name.clone());
-        replacement_values.insert("environment".into(), env.to_string());
-        replacement_values.insert("now".into(), now_date_string());
-
+    ) -> Result<Vec<VariableLayer>, CliError> {
+        let mut built_in: HashMap<String, String> = HashMap::new();
+        built_in.insert("context".into(), self.name.clone());
+        built_in.insert("environment".into(), env.name.clone());
+        built_in.insert("now".into(), now_date_string());
         service.iter().for_each(|service_name| {
-            replacement_values.insert("service".into(), service_name.to_string());
+            built_in.insert("service".into(), service_name.to_string());
         });
-
         path.iter().for_each(|path_name| {
-            replacement_values.insert("path".into(), path_name.to_string());
+            built_in.insert("path".into(), path_name.to_string());
         });
 
-        for (key, value) in self.variables.clone().unwrap_or_default() {
-            replacement_values.insert(key, value);
+        let mut layers = vec![
+            (VariableSource::BuiltIn, built_in),
+            (
+                VariableSource::Global,
+                config.variables.clone().unwrap_or_default(),
+            ),
+            (
+                VariableSource::Environment(env.name.clone()),
+                env.variables.clone().unwrap_or_default(),
+            ),
+        ];
+        for context in self.parents(env)?.iter().rev().chain([self]) {
+            layers.push((
+                VariableSource::Context(context.name.clone()),
+                context.variables.clone().unwrap_or_default(),
+            ));
         }
-        replacement_values
+        layers.push((VariableSource::Command, variables.unwrap_or_default()));
+        Ok(layers)
+    }
+
+    /// all variables available in the templates, see [`ContextConfig::variable_layers`]
+    pub fn variables_map(
+        &self,
+        config: &Config,
+        env: &EnvironmentConfig,
+        path: Option<String>,
+        service: Option<String>,
+        variables: Option<HashMap<String, String>>,
+    ) -> Result<HashMap<String, String>, CliError> {
+        Ok(self
+            .variable_layers(config, env, path, service, variables)?
+            .into_iter()
+            .flat_map(|(_, variables)| variables)
+            .collect())
     }
 
     pub fn eval_with_variables(
         &self,
         expression: &str,
-        environment_name: &str,
+        config: &Config,
+        environment: &EnvironmentConfig,
         path: Option<String>,
         service: Option<String>,
         variables: Option<HashMap<String, String>>,
     ) -> Result<bool, CliError> {
+        let replacement_values =
+            self.variables_map(config, environment, path, service, variables)?;
         let env = Environment::new();
         let expression = env.compile_expression(expression)?;
-        let replacement_values = self.variables_map(environment_name, path, service, variables);
 
         let result = expression.eval(replacement_values)?;
         Ok(result.is_true())
@@ -68,45 +170,29 @@ impl ContextConfig {
     pub fn replace_variables(
         &self,
         template: &str,
-        env: &str,
+        config: &Config,
+        env: &EnvironmentConfig,
         path: Option<String>,
         service: Option<String>,
         additional_variables: Option<HashMap<String, String>>,
     ) -> Result<String, CliError> {
-        let replacement_values = self.variables_map(env, path, service, additional_variables);
-        let (variables, undefined) = track_context(replacement_values.into());
-
-        let env = Environment::new();
-        let rendered = env.render_str(template, variables)?;
-
-        if rendered.contains("{") || rendered.contains("}") {
-            return Err(CliError::TemplateError(format!(
-                "curly braces found after template replace, this is considered an error: {rendered}, template: {template}"
-            )));
-        }
-
-        let all_undefined = undefined.lock().unwrap().clone();
-
-        if !all_undefined.is_empty() {
-            return Err(CliError::TemplateError(format!(
-                "found undefined values: {all_undefined:?} in template {template}, you might need to specify them in the command options (e.g. --service, --path)"
-            )));
-        }
-
-        Ok(rendered)
+        let replacement_values =
+            self.variables_map(config, env, path, service, additional_variables)?;
+        render_template(template, replacement_values)
     }
 
+    /// renders the mount and path templates selected by the location
     pub fn mount_and_path(
         &self,
         env: &EnvironmentConfig,
-        context_args: &VaultContextArgs,
+        location: &Location,
         config: &Config,
     ) -> Result<(String, String), CliError> {
-        let found_mount_template = config.mount_template(context_args.mount_template.clone());
-        let found_path_template = config.path_template(context_args.path_template.clone());
+        let found_mount_template = config.mount_template(location.mount_template.clone());
+        let found_path_template = config.path_template(location.path_template.clone());
 
         let mount_template = match (
-            context_args.mount_template.clone(),
+            location.mount_template.clone(),
             found_mount_template.clone(),
         ) {
             (_, Some(template)) => template,
@@ -119,10 +205,7 @@ impl ContextConfig {
             }
         };
 
-        let path_template = match (
-            context_args.path_template.clone(),
-            found_path_template.clone(),
-        ) {
+        let path_template = match (location.path_template.clone(), found_path_template.clone()) {
             (_, Some(template)) => template,
             (arg, None) => {
                 return Err(CliError::CommandError(format!(
@@ -135,25 +218,110 @@ impl ContextConfig {
 
         let mount = self.replace_variables(
             &mount_template,
-            &env.name,
-            context_args.path.clone(),
-            context_args.service.clone(),
-            context_args.variables_as_map(),
+            config,
+            env,
+            location.path.clone(),
+            location.service.clone(),
+            Some(location.variables.clone()),
         )?;
         let path = self.replace_variables(
             &path_template,
-            &env.name,
-            context_args.path.clone(),
-            context_args.service.clone(),
-            context_args.variables_as_map(),
+            config,
+            env,
+            location.path.clone(),
+            location.service.clone(),
+            Some(location.variables.clone()),
         )?;
 
         Ok((mount, path))
     }
+
+    /// the kv versions set on mount templates, by the mount they render to in this context.
+    /// templates which can't be rendered here can't be used by the context either
+    pub fn kv_versions(
+        &self,
+        config: &Config,
+        env: &EnvironmentConfig,
+        location: &Location,
+    ) -> HashMap<String, KvVersion> {
+        config
+            .mount_templates
+            .iter()
+            .flatten()
+            .filter_map(|(_, template)| {
+                let version = template.kv_version()?;
+                let mount = self
+                    .replace_variables(
+                        template.template(),
+                        config,
+                        env,
+                        location.path.clone(),
+                        location.service.clone(),
+                        Some(location.variables.clone()),
+                    )
+                    .ok()?;
+                Some((mount, version))
+            })
+            .collect()
+    }
+
+    /// renders the namespace of the context, its closest parent with one or the environment
+    pub fn namespace(
+        &self,
+        config: &Config,
+        env: &EnvironmentConfig,
+        location: &Location,
+    ) -> Result<Option<String>, CliError> {
+        let parents = self.parents(env)?;
+        let template = [self]
+            .into_iter()
+            .chain(parents.iter())
+            .find_map(|context| context.namespace.as_ref())
+            .or(env.namespace.as_ref());
+
+        template
+            .map(|template| {
+                self.replace_variables(
+                    template,
+                    config,
+                    env,
+                    location.path.clone(),
+                    location.service.clone(),
+                    Some(location.variables.clone()),
+                )
+            })
+            .transpose()
+    }
+}
+
+/// renders the template with the values, undefined values and curly braces left after
+/// rendering are errors
+fn render_template(template: &str, values: HashMap<String, String>) -> Result<String, CliError> {
+    let (variables, undefined) = track_context(values.into());
+
+    let env = Environment::new();
+    let rendered = env.render_str(template, variables)?;
+
+    if rendered.contains("{") || rendered.contains("}") {
+        return Err(CliError::TemplateError(format!(
+            "curly braces found after template replace, this is considered an error: {rendered}, template: {template}"
+        )));
+    }
+
+    let all_undefined = undefined.lock().unwrap().clone();
+
+    if !all_undefined.is_empty() {
+        return Err(CliError::TemplateError(format!(
+            "found undefined values: {all_undefined:?} in template {template}, you might need to specify them in the command options (e.g. --service, --path)"
+        )));
+    }
+
+    Ok(rendered)
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[schemars(deny_unknown_fields)]
 pub struct EnvironmentConfig {
     pub name: String,
     pub vault_address: String,
@@ -161,10 +329,41 @@ pub struct EnvironmentConfig {
     pub token_valid_until: Option<u64>,
     pub contexts: Vec<ContextConfig>,
     pub auth_mount: Option<String>,
+    /// variables for the templates of all contexts, contexts can override them
+    pub variables: Option<HashMap<String, String>>,
+    /// vault enterprise namespace sent with every request, can be a template
+    pub namespace: Option<String>,
+    /// keep the secrets in this local json file instead of vault, for tests and local development
+    pub secrets_file: Option<String>,
+    /// pem file with the ca certificates to verify vault with, falls back to `VAULT_CACERT`
+    pub ca_cert: Option<String>,
+    /// directory with pem ca certificates, falls back to `VAULT_CAPATH`
+    pub ca_path: Option<String>,
+    /// pem client certificate for tls auth, falls back to `VAULT_CLIENT_CERT`
+    pub client_cert: Option<String>,
+    /// pem key of the client certificate, falls back to `VAULT_CLIENT_KEY`
+    pub client_key: Option<String>,
+    /// name the server certificate is verified against, falls back to `VAULT_TLS_SERVER_NAME`
+    pub tls_server_name: Option<String>,
+    /// disables tls verification, only for local development, falls back to `VAULT_SKIP_VERIFY`
+    pub insecure_skip_verify: Option<bool>,
+    /// timeouts, retries, proxy and concurrency of the requests
+    pub http: Option<HttpSettings>,
+    /// writes require the exact environment and context names, show the changes and have to be
+    /// confirmed by typing the environment name
+    pub protected: Option<bool>,
+    /// all writes are refused
+    pub read_only: Option<bool>,
+    /// kv versions set on mount templates by rendered mount, set for a context so they are
+    /// never detected
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub kv_versions: HashMap<String, KvVersion>,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[schemars(deny_unknown_fields)]
 pub struct ValueRef {
     pub key: String,
     pub mount: String,
@@ -177,14 +376,20 @@ impl Display for ValueRef {
     }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[schemars(deny_unknown_fields)]
 pub struct ValueMapping {
     pub source: Option<ValueRef>,
     pub value: Option<String>,
     pub target: ValueRef,
     pub when: Option<String>,
     pub metadata: Option<HashMap<String, String>>,
+    pub metadata_settings: Option<MetadataSettings>,
+    /// `file:line` the mapping is defined at, set when the config files are loaded
+    #[serde(rename = "_origin", default, skip_serializing)]
+    #[schemars(skip)]
+    pub origin: Option<String>,
 }
 
 impl Display for ValueMapping {
@@ -197,12 +402,40 @@ impl Display for ValueMapping {
                 .map(|value_ref| value_ref.to_string())
                 .or(self.value.clone())
                 .unwrap_or("-".into()),
-            self.target.to_string(),
+            self.target,
             self.when.clone().unwrap_or("-".into())
         )
     }
 }
 
+/// kv2 secret settings which are stored alongside the custom metadata
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(deny_unknown_fields)]
+pub struct MetadataSettings {
+    pub max_versions: Option<u64>,
+    pub cas_required: Option<bool>,
+    pub delete_version_after: Option<String>,
+}
+
+impl MetadataSettings {
+    /// values set in `other` take precedence
+    pub fn merge(&self, other: &MetadataSettings) -> MetadataSettings {
+        MetadataSettings {
+            max_versions: other.max_versions.or(self.max_versions),
+            cas_required: other.cas_required.or(self.cas_required),
+            delete_version_after: other
+                .delete_version_after
+                .clone()
+                .or(self.delete_version_after.clone()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &MetadataSettings::default()
+    }
+}
+
 impl EnvironmentConfig {
     pub fn auth_mount_or_default(&self) -> String {
         self.auth_mount
@@ -220,74 +453,283 @@ impl EnvironmentConfig {
         }
     }
 
+    /// the environment with the namespace and kv versions of the context rendered for the
+    /// location, the backend of the result sends the namespace with every request
+    pub fn for_context(
+        &self,
+        context: &ContextConfig,
+        location: &Location,
+        config: &Config,
+    ) -> Result<EnvironmentConfig, CliError> {
+        Ok(EnvironmentConfig {
+            namespace: context.namespace(config, self, location)?,
+            kv_versions: context.kv_versions(config, self, location),
+            ..self.clone()
+        })
+    }
+
+    /// the environment with its own namespace rendered, for requests outside of a context like
+    /// the login. only the global and environment variables, `environment` and `now` are set
+    pub fn without_context(&self, config: &Config) -> Result<EnvironmentConfig, CliError> {
+        let Some(template) = &self.namespace else {
+            return Ok(self.clone());
+        };
+        let mut values: HashMap<String, String> = HashMap::new();
+        values.insert("environment".into(), self.name.clone());
+        values.insert("now".into(), now_date_string());
+        values.extend(config.variables.clone().unwrap_or_default());
+        values.extend(self.variables.clone().unwrap_or_default());
+
+        Ok(EnvironmentConfig {
+            namespace: Some(render_template(template, values)?),
+            ..self.clone()
+        })
+    }
+
+    pub fn tls_settings(&self) -> TlsSettings {
+        TlsSettings::for_environment(self)
+    }
+
+    /// the address requests are sent to, differs from the vault address with a tls server name
+    pub fn address(&self) -> Result<String, CliError> {
+        self.tls_settings().request_address(&self.vault_address)
+    }
+
+    /// a new http client with the settings of the environment, used for all requests to vault
+    pub fn http(&self) -> Result<HttpClient, CliError> {
+        HttpClient::for_environment(self)
+    }
+
     pub fn vault_client(&self) -> Result<VaultClient, CliError> {
-        create_client(&self.vault_address, Some(self.valid_token()?))
+        create_client(&self.address()?, Some(self.valid_token()?), &self.http()?)
     }
 
     pub fn vault(&self) -> Result<Vault, CliError> {
-        Ok(Vault::create(&self.vault_address, &self.valid_token()?))
+        self.vault_with(&self.http()?)
     }
 
-    pub fn get_context(&self, context_name: &str) -> Result<ContextConfig, CliError> {
-        let context = self
-            .contexts
-            .clone()
-            .into_iter()
-            .find_map(|context| {
-                let context_lowercase = context.name.to_string().to_lowercase();
-                if context_lowercase == context_name {
-                    Some(context)
-                } else if context_lowercase.contains(context_name) {
-                    println!(
+    /// vault using a shared http client, with the namespace of this environment
+    pub fn vault_with(&self, http: &HttpClient) -> Result<Vault, CliError> {
+        Vault::create(
+            &self.address()?,
+            &self.valid_token()?,
+            http.with_namespace(self.namespace.clone()),
+        )
+        .map(|vault| vault.with_kv_versions(self.kv_versions.clone()))
+    }
+
+    /// the backend all secret operations of the environment go through, writes are recorded
+    /// in the audit log
+    pub fn backend(&self) -> Result<Box<dyn SecretBackend>, CliError> {
+        let backend: Box<dyn SecretBackend> = match &self.secrets_file {
+            Some(file) => Box::new(MemoryBackend::from_file(file)?),
+            None => Box::new(self.vault()?),
+        };
+        Ok(Box::new(AuditedBackend::new(&self.name, backend)))
+    }
+
+    /// like [`EnvironmentConfig::backend`] but sharing the http client and its limits
+    pub fn backend_with(&self, http: &HttpClient) -> Result<Box<dyn SecretBackend>, CliError> {
+        let backend: Box<dyn SecretBackend> = match &self.secrets_file {
+            Some(file) => Box::new(MemoryBackend::from_file(file)?),
+            None => Box::new(self.vault_with(http)?),
+        };
+        Ok(Box::new(AuditedBackend::new(&self.name, backend)))
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.protected.unwrap_or(false)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.unwrap_or(false)
+    }
+
+    pub fn get_context(
+        &self,
+        context_name: &str,
+        config: &Config,
+    ) -> Result<ContextConfig, CliError> {
+        Ok(self.find_context(context_name, config)?.0)
+    }
+
+    /// the context matching the name and how it matched, see [`find_by_name`]
+    pub fn find_context(
+        &self,
+        context_name: &str,
+        config: &Config,
+    ) -> Result<(ContextConfig, NameMatch), CliError> {
+        find_by_name(
+            &self.contexts,
+            |context| &context.name,
+            context_name,
+            config.is_strict_matching(),
+        )
+        .map_err(|error| error.prefixed(&format!("context in environment {}", self.name)))
+    }
+}
+
+/// how a name given on the command line matched the configured one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameMatch {
+    Exact,
+    Prefix,
+    Substring,
+}
+
+impl NameMatch {
+    fn matches(&self, candidate: &str, name: &str) -> bool {
+        match self {
+            NameMatch::Exact => candidate == name,
+            NameMatch::Prefix => candidate.starts_with(name),
+            NameMatch::Substring => candidate.contains(name),
+        }
+    }
+}
+
+/// finds the item by name, ranked exact > unique prefix > unique substring, the fuzzy matches
+/// ignore the case. errors if several items match on the same rank or with `strict` if the name
+/// doesn't match exactly
+pub fn find_by_name<T: Clone>(
+    items: &[T],
+    name_of: impl Fn(&T) -> &str,
+    name: &str,
+    strict: bool,
+) -> Result<(T, NameMatch), CliError> {
+    if let Some(item) = items.iter().find(|item| name_of(item) == name) {
+        return Ok((item.clone(), NameMatch::Exact));
+    }
+
+    let names = || {
+        items
+            .iter()
+            .map(|item| name_of(item).to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    if !strict {
+        let lowercase = name.to_lowercase();
+        for rank in [NameMatch::Prefix, NameMatch::Substring] {
+            let found: Vec<&T> = items
+                .iter()
+                .filter(|item| rank.matches(&name_of(item).to_lowercase(), &lowercase))
+                .collect();
+
+            match found.as_slice() {
+                [] => continue,
+                [item] => {
+                    eprintln!(
                         "{}",
                         Console::warning(format!(
-                            "found context '{}' via partial match of '{}'",
-                            context.name, context_name
+                            "using '{}' via partial match of '{name}'",
+                            name_of(item)
                         ))
                     );
-                    Some(context)
-                } else {
-                    None
+                    return Ok(((*item).clone(), rank));
                 }
-            })
-            .ok_or(CliError::CommandError(format!(
-                "context {} not found in environment {}, must be part of:\n{}",
-                context_name,
-                self.name,
-                self.contexts
-                    .iter()
-                    .map(|context| context.name.to_string())
-                    .collect::<Vec<String>>()
-                    .join("\n")
-            )))?;
-        Ok(context)
+                _ => {
+                    return Err(CliError::CommandError(format!(
+                        "'{name}' is ambiguous, matches: {}",
+                        found
+                            .iter()
+                            .map(|item| name_of(item).to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )))
+                }
+            }
+        }
+    }
+
+    Err(CliError::CommandError(format!(
+        "'{name}' not found{}, must be one of: {}",
+        if strict { " (strict matching)" } else { "" },
+        names()
+    )))
+}
+
+/// a mount template, either just the template or with settings of the mount
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(untagged)]
+pub enum MountTemplate {
+    Template(String),
+    Settings(MountTemplateSettings),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[schemars(deny_unknown_fields)]
+pub struct MountTemplateSettings {
+    pub template: String,
+    /// version of the kv secrets engine, detected from the mount if not set
+    #[schemars(range(min = 1, max = 2))]
+    pub kv_version: Option<u8>,
+}
+
+impl MountTemplate {
+    pub fn template(&self) -> &str {
+        match self {
+            MountTemplate::Template(template) => template,
+            MountTemplate::Settings(settings) => &settings.template,
+        }
+    }
+
+    pub fn kv_version(&self) -> Option<KvVersion> {
+        match self {
+            MountTemplate::Template(_) => None,
+            MountTemplate::Settings(settings) => match settings.kv_version? {
+                1 => Some(KvVersion::V1),
+                _ => Some(KvVersion::V2),
+            },
+        }
+    }
+}
+
+impl From<&str> for MountTemplate {
+    fn from(template: &str) -> Self {
+        MountTemplate::Template(template.to_string())
     }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[schemars(deny_unknown_fields)]
 pub struct Config {
     pub default_owner: String,
+    /// variables for the templates of all environments, environments and contexts can override them
+    pub variables: Option<HashMap<String, String>>,
     pub default_mount_template: Option<String>,
     pub default_path_template: Option<String>,
     pub default_metadata: Option<HashMap<String, String>>,
-    pub mount_templates: Option<HashMap<String, String>>,
+    pub default_metadata_settings: Option<MetadataSettings>,
+    pub mount_templates: Option<HashMap<String, MountTemplate>>,
     pub path_templates: Option<HashMap<String, String>>,
     pub environments: Vec<EnvironmentConfig>,
     pub mappings: Option<Vec<ValueMapping>>,
+    /// globs of files adding mappings, templates and contexts, relative to the including file
+    pub include: Option<Vec<String>>,
+    /// environments and contexts have to be given by their exact names, no prefix or substring matches
+    pub strict_matching: Option<bool>,
 }
 
 impl Config {
+    pub fn is_strict_matching(&self) -> bool {
+        self.strict_matching.unwrap_or(false)
+    }
+
     pub fn mount_template(&self, name: Option<String>) -> Option<String> {
-        match name.or(self.default_mount_template.clone()) {
-            Some(key) => self
-                .mount_templates
-                .clone()
-                .unwrap_or_default()
-                .get(&key)
-                .cloned(),
-            None => None,
-        }
+        self.find_mount_template(name)
+            .map(|template| template.template().to_string())
+    }
+
+    fn find_mount_template(&self, name: Option<String>) -> Option<&MountTemplate> {
+        let key = name.or(self.default_mount_template.clone())?;
+        self.mount_templates.as_ref()?.get(&key)
+    }
+
+    pub fn metadata_settings_or_default(&self) -> MetadataSettings {
+        self.default_metadata_settings.clone().unwrap_or_default()
     }
 
     pub fn path_template(&self, name: Option<String>) -> Option<String> {
@@ -312,87 +754,143 @@ pub async fn config_file_path() -> Result<PathBuf, CliError> {
     Ok(config_dir.join("config.yaml"))
 }
 
-// load a json config from the user home directory .config/tresor/config.json
-// create an empty config and the config folder if they don't exist
+/// loads the global config from `~/.config/tresor/config.yaml` merged with the project config,
+/// `TRESOR_CONFIG` or else the closest `.tresor.yaml`, the global config is created if it
+/// doesn't exist
 pub async fn load_or_create_config() -> Result<Config, CliError> {
-    let config_file = config_file_path().await?;
+    let project_file = std::env::var_os("TRESOR_CONFIG").map(PathBuf::from);
+    Ok(load_config(project_file).await?.config)
+}
 
-    if !config_file.exists() {
-        println!(
-            "# no existing config found, creating default in {}",
-            config_file.display()
-        );
+pub async fn get_env(config: &Config, name: &str) -> Result<EnvironmentConfig, CliError> {
+    Ok(find_env(config, name)?.0)
+}
+
+/// the environment matching the name and how it matched, see [`find_by_name`]
+pub fn find_env(config: &Config, name: &str) -> Result<(EnvironmentConfig, NameMatch), CliError> {
+    find_by_name(
+        &config.environments,
+        |env| &env.name,
+        name,
+        config.is_strict_matching(),
+    )
+    .map_err(|error| error.prefixed("environment"))
+}
+
+/// like [`get_env`] for commands writing to the environment, a protected environment and its
+/// context must be given by their exact names, a read only environment is an error
+pub fn get_env_for_write(
+    config: &Config,
+    name: &str,
+    context_name: &str,
+) -> Result<EnvironmentConfig, CliError> {
+    let (env, env_match) = find_env(config, name)?;
+    if env.is_read_only() {
+        return Err(CliError::CommandError(format!(
+            "environment {} is read only",
+            env.name
+        )));
+    }
+    if !env.is_protected() {
+        return Ok(env);
     }
 
-    let config = match tokio::fs::read(&config_file).await {
-        Ok(data) => serde_yaml::from_slice(&data)?,
-        Err(_) => {
-            let default_config = Config::default();
-            // write default config to file
-            let data = serde_yaml::to_string(&default_config)?;
-            tokio::fs::write(&config_file, data).await?;
-            default_config
-        }
+    let context_match = match context_name {
+        "*" => NameMatch::Exact,
+        context_name => env.find_context(context_name, config)?.1,
     };
-    Ok(config)
-}
 
-pub async fn get_env(config: &Config, name: &str) -> Result<EnvironmentConfig, CliError> {
-    let env = config
-        .environments
-        .clone()
-        .into_iter()
-        .find_map(|env| {
-            if env.name == name {
-                Some(env)
-            } else if env.name.starts_with(&name.to_string()) {
-                Some(env)
-            } else {
-                None
-            }
-        })
-        .ok_or(CliError::CommandError(format!(
-            "Environment {} not found",
-            name
-        )))?;
+    if env_match != NameMatch::Exact || context_match != NameMatch::Exact {
+        return Err(CliError::CommandError(format!(
+            "environment {} is protected, writes require the exact environment and context names",
+            env.name
+        )));
+    }
     Ok(env)
 }
 
+/// sets the token of the environment in the config and writes it to the global config only,
+/// so settings of the project config are never copied into it
 pub async fn write_token(
     config: &mut Config,
     target_config: &EnvironmentConfig,
     token: &str,
     token_duration: u64,
 ) -> Result<Config, CliError> {
-    let envs: Vec<EnvironmentConfig> = config
+    let valid_until = chrono::Utc::now().timestamp() as u64 + token_duration;
+    config
         .environments
-        .clone()
         .iter_mut()
-        .map(|env| {
-            if env.name == target_config.name {
-                env.token = Some(token.to_string());
-                env.token_valid_until =
-                    Some(chrono::Utc::now().timestamp() as u64 + token_duration);
-                env.to_owned()
-            } else {
-                env.to_owned()
-            }
-        })
-        .collect();
-    config.environments = envs;
+        .filter(|env| env.name == target_config.name)
+        .for_each(|env| {
+            env.token = Some(token.to_string());
+            env.token_valid_until = Some(valid_until);
+        });
 
-    let mut config_file = File::create(config_file_path().await?).await?;
+    let global_file = config_file_path().await?;
+    let mut global = if global_file.exists() {
+        read_yaml(&global_file).await?
+    } else {
+        serde_yaml::to_value(Config::default())?
+    };
+    set_token(&mut global, &target_config.name, token, valid_until)?;
+
+    let mut config_file = File::create(global_file).await?;
     config_file
-        .write_all(serde_yaml::to_string(&config)?.as_bytes())
+        .write_all(serde_yaml::to_string(&global)?.as_bytes())
         .await?;
     Ok(config.to_owned())
 }
 
+/// sets the token of the environment in the raw config, the environment is added with only
+/// its name if it is defined in the project config
+fn set_token(
+    config: &mut serde_yaml::Value,
+    environment: &str,
+    token: &str,
+    valid_until: u64,
+) -> Result<(), CliError> {
+    let invalid = || CliError::ConfigError("invalid global config: no environments list".into());
+
+    let config = config.as_mapping_mut().ok_or_else(invalid)?;
+    let environments = config
+        .entry("environments".into())
+        .or_insert_with(|| serde_yaml::Value::Sequence(Vec::new()))
+        .as_sequence_mut()
+        .ok_or_else(invalid)?;
+
+    let position = environments
+        .iter()
+        .position(|env| env.get("name").and_then(|name| name.as_str()) == Some(environment));
+    let env = match position {
+        Some(position) => &mut environments[position],
+        None => {
+            let mut env = serde_yaml::Mapping::new();
+            env.insert("name".into(), environment.into());
+            environments.push(env.into());
+            environments.last_mut().unwrap()
+        }
+    };
+
+    let env = env.as_mapping_mut().ok_or_else(invalid)?;
+    env.insert("token".into(), token.into());
+    env.insert("tokenValidUntil".into(), valid_until.into());
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
 
-    use crate::{config::ContextConfig, error::CliError};
+    use crate::{
+        config::{
+            find_env, get_env_for_write, set_token, Config, ContextConfig, EnvironmentConfig,
+            MountTemplate, MountTemplateSettings, NameMatch,
+        },
+        error::CliError,
+        location::Location,
+        vault::KvVersion,
+    };
 
     #[tokio::test]
     async fn test_replacements() -> Result<(), CliError> {
@@ -405,13 +903,20 @@ mod test {
         let context = ContextConfig {
             name: "test".into(),
             variables: Some(context_variables),
+            ..ContextConfig::default()
+        };
+        let config = Config::default();
+        let env = EnvironmentConfig {
+            name: "env".into(),
+            ..EnvironmentConfig::default()
         };
 
         // missing path and service
         assert!(context
             .replace_variables(
                 "{{var1}}/{{environment}}/{{service}}/{{path}}",
-                "env",
+                &config,
+                &env,
                 None,
                 None,
                 Some(additional_variables.clone())
@@ -421,7 +926,8 @@ mod test {
         assert_eq!(
             context.replace_variables(
                 "{{var1}}/{{environment}}/{{service}}/{{path}}/{{var2}}",
-                "env",
+                &config,
+                &env,
                 Some("test-path".into()),
                 Some("test-service".into()),
                 Some(additional_variables.clone())
@@ -431,7 +937,8 @@ mod test {
 
         match context.replace_variables(
             "{{var}}",
-            "env",
+            &config,
+            &env,
             None,
             None,
             Some(additional_variables.clone()),
@@ -446,7 +953,8 @@ mod test {
 
         match context.replace_variables(
             "{var}}",
-            "env",
+            &config,
+            &env,
             None,
             None,
             Some(additional_variables.clone()),
@@ -461,4 +969,246 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_namespace() -> Result<(), CliError> {
+        let env = EnvironmentConfig {
+            name: "staging".into(),
+            namespace: Some("bu/{{environment}}".into()),
+            ..EnvironmentConfig::default()
+        };
+        let context = ContextConfig {
+            name: "team".into(),
+            ..ContextConfig::default()
+        };
+        let location = Location::default();
+
+        assert_eq!(
+            env.for_context(&context, &location, &Config::default())?
+                .namespace,
+            Some("bu/staging".into())
+        );
+        assert_eq!(
+            env.without_context(&Config::default())?.namespace,
+            Some("bu/staging".into())
+        );
+        let by_context = EnvironmentConfig {
+            namespace: Some("bu/{{context}}".into()),
+            ..env.clone()
+        };
+        assert_eq!(
+            by_context
+                .without_context(&Config::default())
+                .unwrap_err()
+                .exit_code(),
+            5
+        );
+
+        let context = ContextConfig {
+            namespace: Some("{{unit}}/{{context}}".into()),
+            variables: Some(HashMap::from([("unit".into(), "payments".into())])),
+            ..context
+        };
+        assert_eq!(
+            env.for_context(&context, &location, &Config::default())?
+                .namespace,
+            Some("payments/team".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_kv_versions() -> Result<(), CliError> {
+        let config = Config {
+            mount_templates: Some(HashMap::from([
+                ("team".into(), MountTemplate::from("{{context}}-secrets")),
+                (
+                    "legacy".into(),
+                    MountTemplate::Settings(MountTemplateSettings {
+                        template: "{{context}}-legacy".into(),
+                        kv_version: Some(1),
+                    }),
+                ),
+            ])),
+            ..Config::default()
+        };
+        let env = EnvironmentConfig {
+            name: "staging".into(),
+            ..EnvironmentConfig::default()
+        };
+        let location = Location::default();
+
+        // each context has its own mounts, the version is kept per rendered mount
+        for name in ["payments", "billing"] {
+            let context = ContextConfig {
+                name: name.into(),
+                ..ContextConfig::default()
+            };
+            assert_eq!(
+                env.for_context(&context, &location, &config)?.kv_versions,
+                HashMap::from([(format!("{name}-legacy"), KvVersion::V1)])
+            );
+        }
+        assert!(env.kv_versions.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_token() -> Result<(), CliError> {
+        let mut global: serde_yaml::Value = serde_yaml::from_str(
+            "defaultOwner: me\nenvironments:\n  - name: staging\n    vaultAddress: https://vault",
+        )?;
+
+        set_token(&mut global, "staging", "token-1", 10)?;
+        set_token(&mut global, "production", "token-2", 20)?;
+
+        let environments = global["environments"].as_sequence().unwrap();
+        assert_eq!(environments.len(), 2);
+        assert_eq!(environments[0]["token"], "token-1");
+        assert_eq!(environments[0]["vaultAddress"], "https://vault");
+        assert_eq!(environments[1]["name"], "production");
+        assert_eq!(environments[1]["tokenValidUntil"], 20);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_variable_precedence() -> Result<(), CliError> {
+        let variables = |pairs: &[(&str, &str)]| {
+            Some(
+                pairs
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<HashMap<String, String>>(),
+            )
+        };
+
+        let config = Config {
+            variables: variables(&[("region", "global"), ("team", "global")]),
+            ..Config::default()
+        };
+        let env = EnvironmentConfig {
+            name: "staging".into(),
+            variables: variables(&[("region", "eu-west-1"), ("cluster", "env")]),
+            contexts: vec![
+                ContextConfig {
+                    name: "base".into(),
+                    variables: variables(&[("cluster", "base"), ("owner", "base")]),
+                    namespace: Some("{{team}}".into()),
+                    ..ContextConfig::default()
+                },
+                ContextConfig {
+                    name: "prod".into(),
+                    variables: variables(&[("owner", "prod")]),
+                    extends: Some("base".into()),
+                    ..ContextConfig::default()
+                },
+            ],
+            ..EnvironmentConfig::default()
+        };
+
+        let context = env.get_context("prod", &config)?;
+        let map =
+            context.variables_map(&config, &env, None, None, variables(&[("team", "command")]))?;
+        assert_eq!(map["region"], "eu-west-1");
+        assert_eq!(map["cluster"], "base");
+        assert_eq!(map["owner"], "prod");
+        assert_eq!(map["team"], "command");
+        assert_eq!(map["context"], "prod");
+
+        // the namespace is inherited from the parent
+        assert_eq!(
+            env.for_context(&context, &Location::default(), &config)?
+                .namespace,
+            Some("global".into())
+        );
+
+        let cyclic = ContextConfig {
+            name: "base".into(),
+            extends: Some("prod".into()),
+            ..ContextConfig::default()
+        };
+        let env = EnvironmentConfig {
+            contexts: vec![cyclic, context.clone()],
+            ..env
+        };
+        assert!(context.parents(&env).is_err());
+
+        Ok(())
+    }
+
+    fn matching_config() -> Config {
+        let contexts = ["prod-eu", "prod", "staging-us", "test-us"]
+            .iter()
+            .map(|name| ContextConfig {
+                name: name.to_string(),
+                ..ContextConfig::default()
+            })
+            .collect::<Vec<ContextConfig>>();
+        let env = |name: &str, protected: bool| EnvironmentConfig {
+            name: name.into(),
+            contexts: contexts.clone(),
+            protected: Some(protected),
+            ..EnvironmentConfig::default()
+        };
+        Config {
+            environments: vec![
+                env("staging", false),
+                env("production", true),
+                env("prod-old", false),
+            ],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_name_matching() -> Result<(), CliError> {
+        let mut config = matching_config();
+        let (env, rank) = find_env(&config, "staging")?;
+        assert_eq!((env.name.as_str(), rank), ("staging", NameMatch::Exact));
+        assert_eq!(find_env(&config, "STA")?.1, NameMatch::Prefix);
+        assert_eq!(find_env(&config, "uction")?.1, NameMatch::Substring);
+
+        let error = find_env(&config, "prod").unwrap_err().to_string();
+        assert!(error.contains("ambiguous"), "{error}");
+        assert!(error.contains("production, prod-old"), "{error}");
+
+        // exact wins over the prefix of another context
+        let (context, rank) = env.find_context("prod", &config)?;
+        assert_eq!((context.name.as_str(), rank), ("prod", NameMatch::Exact));
+        assert_eq!(env.find_context("Prod-E", &config)?.1, NameMatch::Prefix);
+        assert!(env.find_context("-us", &config).is_err());
+        assert!(env.find_context("missing", &config).is_err());
+
+        config.strict_matching = Some(true);
+        assert!(find_env(&config, "sta").is_err());
+        assert!(env.find_context("prod-e", &config).is_err());
+        assert_eq!(env.find_context("prod-eu", &config)?.1, NameMatch::Exact);
+        Ok(())
+    }
+
+    #[test]
+    fn test_protected_writes_require_exact_names() -> Result<(), CliError> {
+        let config = matching_config();
+        assert!(get_env_for_write(&config, "production", "prod").is_ok());
+        assert!(get_env_for_write(&config, "production", "*").is_ok());
+        assert!(get_env_for_write(&config, "product", "prod").is_err());
+        assert!(get_env_for_write(&config, "production", "prod-e").is_err());
+        assert!(get_env_for_write(&config, "stag", "prod-e").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only_refuses_writes() -> Result<(), CliError> {
+        let mut config = matching_config();
+        config.environments[0].read_only = Some(true);
+        let error = get_env_for_write(&config, "staging", "prod")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("read only"), "{error}");
+        assert!(find_env(&config, "staging").is_ok());
+        Ok(())
+    }
 }
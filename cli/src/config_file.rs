@@ -0,0 +1,687 @@
+//! edits a config file in place.
+//!
+//! only the lines of the changed settings are touched, so comments, order and formatting of
+//! everything else are kept. settings are addressed like in the sources of `tresor config`:
+//! `environments[staging].contexts[prod].variables.foo`, entries of lists by their name.
+//! the edited parts have to be in block style, which is what `tresor` writes.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::Value;
+
+use crate::{
+    error::CliError,
+    loader::load_texts,
+    validate::{validate_config, ConfigIssue},
+};
+
+/// a part of a setting path
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySegment {
+    Key(String),
+    /// the entry of a list with this name
+    Item(String),
+}
+
+/// parses `environments[staging].vaultAddress` into its segments
+pub fn parse_key(key: &str) -> Result<Vec<KeySegment>, CliError> {
+    let invalid = || {
+        CliError::CommandError(format!(
+            "invalid key '{key}', example: environments[staging].contexts[prod].variables.foo"
+        ))
+    };
+
+    let mut segments: Vec<KeySegment> = Vec::new();
+    let mut rest = key;
+    while !rest.is_empty() {
+        if let Some(item) = rest.strip_prefix('[') {
+            let (name, after) = item.split_once(']').ok_or_else(invalid)?;
+            if name.is_empty() || !matches!(segments.last(), Some(KeySegment::Key(_))) {
+                return Err(invalid());
+            }
+            segments.push(KeySegment::Item(name.to_string()));
+            rest = after;
+        } else {
+            let part = match segments.is_empty() {
+                true => rest,
+                false => rest.strip_prefix('.').ok_or_else(invalid)?,
+            };
+            let end = part.find(['.', '[']).unwrap_or(part.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            segments.push(KeySegment::Key(part[..end].to_string()));
+            rest = &part[end..];
+        }
+    }
+
+    match segments.is_empty() {
+        true => Err(invalid()),
+        false => Ok(segments),
+    }
+}
+
+/// the value at the key within a parsed config
+pub fn get_value<'a>(value: &'a Value, key: &[KeySegment]) -> Option<&'a Value> {
+    key.iter().try_fold(value, |value, segment| match segment {
+        KeySegment::Key(key) => value.get(key.as_str()),
+        KeySegment::Item(name) => value
+            .as_sequence()?
+            .iter()
+            .find(|item| item.get("name").and_then(Value::as_str) == Some(name)),
+    })
+}
+
+/// a value given on the command line, booleans, numbers and null are taken as such,
+/// everything else is a string
+pub fn parse_scalar(text: &str) -> Value {
+    match serde_yaml::from_str::<Value>(text) {
+        Ok(value @ (Value::Bool(_) | Value::Number(_) | Value::Null)) => value,
+        _ => Value::String(text.to_string()),
+    }
+}
+
+/// lines of a mapping or a list, `indent` is the one of its entries
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    start: usize,
+    end: usize,
+    indent: usize,
+    /// the first line starts with the `- ` of a list entry
+    item: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    lines: Vec<String>,
+}
+
+impl ConfigFile {
+    /// reads the file, a missing file is empty
+    pub fn read(path: &Path) -> Result<Self, CliError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(CliError::RuntimeError(format!(
+                    "unable to read config {}: {e}",
+                    path.display()
+                )))
+            }
+        };
+        Ok(Self::parse(path, &text))
+    }
+
+    pub fn parse(path: &Path, text: &str) -> Self {
+        ConfigFile {
+            path: path.to_path_buf(),
+            lines: text.lines().map(String::from).collect(),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self.lines.is_empty() {
+            true => String::new(),
+            false => format!("{}\n", self.lines.join("\n")),
+        }
+    }
+
+    pub fn value(&self) -> Result<Value, CliError> {
+        Ok(serde_yaml::from_str(&self.text())?)
+    }
+
+    /// sets a single value, missing maps and named list entries on the way are added
+    pub fn set(&mut self, key: &[KeySegment], value: &Value) -> Result<(), CliError> {
+        let Some((KeySegment::Key(name), parents)) = key.split_last() else {
+            return Err(CliError::CommandError(
+                "only values can be set, not list entries".into(),
+            ));
+        };
+        let rendered = render_scalar(value)?;
+        let block = self.descend(parents)?;
+
+        let Some(line) = self.find_key(&block, name) else {
+            self.insert(&block, &[format!("{}: {rendered}", render_key(name)?)]);
+            return Ok(());
+        };
+
+        let (indent, text) = self.entry(line, &block).unwrap_or_default();
+        let value_block = self.value_block(line, indent);
+        let (_, rest) = split_key(text).unwrap_or_default();
+        let (current, _) = split_comment(rest);
+        if self.has_content(&value_block) || is_flow(current.trim()) {
+            return Err(CliError::CommandError(format!(
+                "{name} is a map or list, only single values can be set"
+            )));
+        }
+
+        let prefix = &self.lines[line][..self.lines[line].len() - text.len()];
+        let (key_text, rest) = split_key(text).unwrap_or_default();
+        let (_, comment) = split_comment(rest);
+        self.lines[line] = format!("{prefix}{key_text}: {rendered}{comment}");
+        Ok(())
+    }
+
+    /// appends an entry to the list at the key, the list is added if it is missing
+    pub fn append(&mut self, key: &[KeySegment], item: &Value) -> Result<(), CliError> {
+        let block = self.descend(key)?;
+        if let Some(first) = (block.start..block.end).find(|line| is_content(&self.lines[*line])) {
+            if !self.lines[first].trim_start().starts_with('-') {
+                return Err(CliError::CommandError(format!(
+                    "{} is not a list",
+                    key_display(key)
+                )));
+            }
+        }
+
+        let rendered = serde_yaml::to_string(item)?;
+        let lines: Vec<String> = rendered
+            .lines()
+            .enumerate()
+            .map(|(index, line)| match index {
+                0 => format!("- {line}"),
+                _ => format!("  {line}"),
+            })
+            .collect();
+        self.insert(&block, &lines);
+        Ok(())
+    }
+
+    /// writes the file unless the change adds problems to the config. `files` are all config
+    /// files in the order they are merged, problems which already exist don't prevent the write
+    pub fn write_validated(&self, files: &[PathBuf]) -> Result<(), CliError> {
+        let mut current: Vec<(PathBuf, String)> = Vec::new();
+        for file in files {
+            current.push((file.clone(), ConfigFile::read(file)?.text()));
+        }
+        let before: Vec<ConfigIssue> = load_texts(&current)
+            .and_then(|(value, _)| validate_config(&value, &HashMap::new()))
+            .unwrap_or_default();
+
+        let changed: Vec<(PathBuf, String)> = current
+            .into_iter()
+            .map(|(file, text)| match file == self.path {
+                true => (file, self.text()),
+                false => (file, text),
+            })
+            .collect();
+        let (value, _) = load_texts(&changed)?;
+
+        let added: Vec<String> = validate_config(&value, &HashMap::new())?
+            .into_iter()
+            .filter(|issue| !before.contains(issue))
+            .map(|issue| format!("  {}: {}", issue.location, issue.message))
+            .collect();
+        if !added.is_empty() {
+            return Err(CliError::ConfigError(format!(
+                "{} not changed, the change would add problems to the config:\n{}",
+                self.path.display(),
+                added.join("\n")
+            )));
+        }
+
+        std::fs::write(&self.path, self.text())?;
+        Ok(())
+    }
+
+    /// the block of the value at the key, missing keys and list entries are added
+    fn descend(&mut self, key: &[KeySegment]) -> Result<Block, CliError> {
+        let mut block = self.root();
+        for (index, segment) in key.iter().enumerate() {
+            self.check_block_style(&block, &key[..index])?;
+            block = match segment {
+                KeySegment::Key(name) => {
+                    let line = match self.find_key(&block, name) {
+                        Some(line) => line,
+                        None => self.insert(&block, &[format!("{}:", render_key(name)?)]),
+                    };
+                    self.open_value(line, &block, &key[..=index])?
+                }
+                KeySegment::Item(name) => match self.find_item(&block, name) {
+                    Some(item) => item,
+                    None => {
+                        let line = self.insert(
+                            &block,
+                            &[format!("- name: {}", render_scalar(&name.as_str().into())?)],
+                        );
+                        self.item_block(line, self.lines.len())
+                    }
+                },
+            };
+        }
+        self.check_block_style(&block, key)?;
+        Ok(block)
+    }
+
+    /// fails if entries of the block are in flow style like `- {name: prod}`, their keys and
+    /// list entries can't be found by line
+    fn check_block_style(&self, block: &Block, key: &[KeySegment]) -> Result<(), CliError> {
+        let flow = (block.start..block.end).any(|line| {
+            self.entry(line, block).is_some_and(|(indent, text)| {
+                let text = text.strip_prefix('-').unwrap_or(text).trim_start();
+                indent == block.indent && is_flow(text)
+            })
+        });
+        match (flow, key.is_empty()) {
+            (false, _) => Ok(()),
+            (true, true) => Err(CliError::CommandError(
+                "the config is not in block style".into(),
+            )),
+            (true, false) => Err(CliError::CommandError(format!(
+                "{} is not a map or list in block style",
+                key_display(key)
+            ))),
+        }
+    }
+
+    fn root(&self) -> Block {
+        let end = self.content_end(0, self.lines.len());
+        let indent = (0..end)
+            .find(|line| is_content(&self.lines[*line]))
+            .map(|line| indent_of(&self.lines[line]))
+            .unwrap_or_default();
+        Block {
+            start: 0,
+            end,
+            indent,
+            item: false,
+        }
+    }
+
+    /// the indent and the text of the line as part of the block, for the first line of a list
+    /// entry that is the text after the `- `
+    fn entry<'a>(&'a self, line: usize, block: &Block) -> Option<(usize, &'a str)> {
+        let text = &self.lines[line];
+        if !is_content(text) {
+            return None;
+        }
+        let trimmed = text.trim_start();
+        let indent = text.len() - trimmed.len();
+        if block.item && line == block.start {
+            let after = trimmed.strip_prefix('-')?;
+            let content = after.trim_start();
+            return match content.is_empty() {
+                true => None,
+                false => Some((indent + 1 + after.len() - content.len(), content)),
+            };
+        }
+        Some((indent, trimmed))
+    }
+
+    fn find_key(&self, block: &Block, key: &str) -> Option<usize> {
+        (block.start..block.end).find(|line| {
+            self.entry(*line, block).is_some_and(|(indent, text)| {
+                indent == block.indent
+                    && !text.starts_with('-')
+                    && split_key(text).is_some_and(|(found, _)| unquote(found) == key)
+            })
+        })
+    }
+
+    fn find_item(&self, block: &Block, name: &str) -> Option<Block> {
+        let starts: Vec<usize> = (block.start..block.end)
+            .filter(|line| {
+                let text = &self.lines[*line];
+                is_content(text)
+                    && indent_of(text) == block.indent
+                    && text.trim_start().starts_with('-')
+            })
+            .collect();
+
+        starts.iter().enumerate().find_map(|(index, start)| {
+            let end = starts.get(index + 1).copied().unwrap_or(block.end);
+            let item = self.item_block(*start, end);
+            let line = self.find_key(&item, "name")?;
+            let (_, text) = self.entry(line, &item)?;
+            let (_, rest) = split_key(text)?;
+            let (value, _) = split_comment(rest);
+            let value: Value = serde_yaml::from_str(value.trim()).ok()?;
+            (value.as_str() == Some(name)).then_some(item)
+        })
+    }
+
+    /// the list entry starting at the line, ending before `end` at the latest
+    fn item_block(&self, start: usize, end: usize) -> Block {
+        let dash_indent = indent_of(&self.lines[start]);
+        let end = (start + 1..end)
+            .find(|line| {
+                let text = &self.lines[*line];
+                is_content(text) && indent_of(text) <= dash_indent
+            })
+            .unwrap_or(end);
+        let end = self.content_end(start, end);
+
+        let mut block = Block {
+            start,
+            end,
+            indent: dash_indent + 2,
+            item: true,
+        };
+        block.indent = (start..end)
+            .find_map(|line| self.entry(line, &block).map(|(indent, _)| indent))
+            .unwrap_or(dash_indent + 2);
+        block
+    }
+
+    /// the lines of the value of the key at the line, lists may have the indent of the key
+    fn value_block(&self, line: usize, key_indent: usize) -> Block {
+        let end = (line + 1..self.lines.len())
+            .find(|index| {
+                let text = &self.lines[*index];
+                let indent = indent_of(text);
+                is_content(text)
+                    && (indent < key_indent
+                        || (indent == key_indent && !text.trim_start().starts_with('-')))
+            })
+            .unwrap_or(self.lines.len());
+        let end = self.content_end(line + 1, end);
+        let indent = (line + 1..end)
+            .find(|index| is_content(&self.lines[*index]))
+            .map(|index| indent_of(&self.lines[index]))
+            .unwrap_or(key_indent + 2);
+        Block {
+            start: line + 1,
+            end,
+            indent,
+            item: false,
+        }
+    }
+
+    /// the value block of the key, an empty inline value like `null` or `{}` is removed
+    fn open_value(
+        &mut self,
+        line: usize,
+        block: &Block,
+        key: &[KeySegment],
+    ) -> Result<Block, CliError> {
+        let (indent, text) = self.entry(line, block).unwrap_or_default();
+        let prefix_len = self.lines[line].len() - text.len();
+        let (key_text, rest) = split_key(text).unwrap_or_default();
+        let (value, comment) = split_comment(rest);
+
+        match value.trim() {
+            "" => {}
+            "null" | "~" | "{}" | "[]" => {
+                self.lines[line] =
+                    format!("{}{key_text}:{comment}", &self.lines[line][..prefix_len]);
+            }
+            _ => {
+                return Err(CliError::CommandError(format!(
+                    "{} is not a map or list in block style",
+                    key_display(key)
+                )))
+            }
+        }
+        Ok(self.value_block(line, indent))
+    }
+
+    /// inserts the lines with the indent of the block after its last entry, returns the index
+    /// of the first inserted line
+    fn insert(&mut self, block: &Block, lines: &[String]) -> usize {
+        let indent = " ".repeat(block.indent);
+        for (offset, line) in lines.iter().enumerate() {
+            self.lines
+                .insert(block.end + offset, format!("{indent}{line}"));
+        }
+        block.end
+    }
+
+    fn has_content(&self, block: &Block) -> bool {
+        (block.start..block.end).any(|line| is_content(&self.lines[line]))
+    }
+
+    /// the end of the range without trailing blank and comment lines
+    fn content_end(&self, start: usize, end: usize) -> usize {
+        (start..end)
+            .rev()
+            .find(|line| is_content(&self.lines[*line]))
+            .map(|line| line + 1)
+            .unwrap_or(start)
+    }
+}
+
+fn is_content(line: &str) -> bool {
+    let trimmed = line.trim();
+    !trimmed.is_empty() && !trimmed.starts_with('#') && trimmed != "---"
+}
+
+/// a map or list in flow style like `{foo: bar}` or `[a, b]`
+fn is_flow(value: &str) -> bool {
+    value.starts_with('{') || value.starts_with('[')
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// splits `key: value` into the key and the rest after the colon
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    let end = match text.chars().next()? {
+        quote @ ('\'' | '"') => text[1..].find(quote)? + 2,
+        _ => text
+            .match_indices(':')
+            .map(|(index, _)| index)
+            .find(|index| text[index + 1..].is_empty() || text[index + 1..].starts_with(' '))?,
+    };
+    let rest = text[end..].strip_prefix(':')?;
+    Some((&text[..end], rest))
+}
+
+fn unquote(key: &str) -> &str {
+    key.trim_matches(|c| c == '\'' || c == '"')
+}
+
+/// splits the value from a trailing comment, the comment keeps its leading whitespace
+fn split_comment(rest: &str) -> (&str, &str) {
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+    for (index, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '#') if previous.is_whitespace() => {
+                let start = rest[..index].trim_end().len();
+                return (&rest[..start], &rest[start..]);
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    (rest, "")
+}
+
+fn render_scalar(value: &Value) -> Result<String, CliError> {
+    if matches!(value, Value::Mapping(_) | Value::Sequence(_)) {
+        return Err(CliError::CommandError(
+            "only single values can be set".into(),
+        ));
+    }
+    let rendered = serde_yaml::to_string(value)?;
+    let rendered = rendered.trim_end();
+    match rendered.contains('\n') {
+        true => Err(CliError::CommandError(
+            "values with multiple lines can not be set".into(),
+        )),
+        false => Ok(rendered.to_string()),
+    }
+}
+
+/// the key quoted if it would not be read back as the same string
+fn render_key(key: &str) -> Result<String, CliError> {
+    render_scalar(&Value::String(key.to_string()))
+}
+
+fn key_display(key: &[KeySegment]) -> String {
+    key.iter()
+        .enumerate()
+        .map(|(index, segment)| match segment {
+            KeySegment::Key(key) if index == 0 => key.clone(),
+            KeySegment::Key(key) => format!(".{key}"),
+            KeySegment::Item(name) => format!("[{name}]"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use serde_yaml::Value;
+
+    use crate::{
+        config_file::{parse_key, parse_scalar, ConfigFile, KeySegment},
+        error::CliError,
+    };
+
+    const CONFIG: &str = r#"# global settings
+defaultOwner: me # the owner
+mountTemplates: null
+environments:
+- name: staging
+  vaultAddress: http://localhost:8200
+  contexts:
+    # first context
+    - name: "prod"
+      variables:
+        foo: bar
+
+# the end
+"#;
+
+    #[test]
+    fn test_parse_key() -> Result<(), CliError> {
+        assert_eq!(
+            parse_key("environments[staging].contexts[eu.prod].variables.foo")?,
+            vec![
+                KeySegment::Key("environments".into()),
+                KeySegment::Item("staging".into()),
+                KeySegment::Key("contexts".into()),
+                KeySegment::Item("eu.prod".into()),
+                KeySegment::Key("variables".into()),
+                KeySegment::Key("foo".into()),
+            ]
+        );
+        for invalid in ["", "[staging]", "a..b", "a[]", "a[b", "a[b]c", ".a"] {
+            assert!(parse_key(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(parse_scalar("10"), Value::from(10));
+        assert_eq!(parse_scalar("true"), Value::Bool(true));
+        assert_eq!(parse_scalar("{{path}}"), Value::from("{{path}}"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_keeps_comments() -> Result<(), CliError> {
+        let mut file = ConfigFile::parse(Path::new("config.yaml"), CONFIG);
+        file.set(&parse_key("defaultOwner")?, &"team # a".into())?;
+        file.set(
+            &parse_key("mountTemplates.default")?,
+            &"kv2/{{service}}".into(),
+        )?;
+        file.set(
+            &parse_key("environments[staging].contexts[prod].variables.foo")?,
+            &"baz".into(),
+        )?;
+        file.set(
+            &parse_key("environments[staging].contexts[dev].variables.foo")?,
+            &1.into(),
+        )?;
+        file.set(
+            &parse_key("environments[production].protected")?,
+            &true.into(),
+        )?;
+        file.append(
+            &parse_key("environments[staging].contexts")?,
+            &serde_yaml::from_str("{name: qa, extends: prod}")?,
+        )?;
+
+        assert_eq!(
+            file.text(),
+            r#"# global settings
+defaultOwner: 'team # a' # the owner
+mountTemplates:
+  default: kv2/{{service}}
+environments:
+- name: staging
+  vaultAddress: http://localhost:8200
+  contexts:
+    # first context
+    - name: "prod"
+      variables:
+        foo: baz
+    - name: dev
+      variables:
+        foo: 1
+    - name: qa
+      extends: prod
+- name: production
+  protected: true
+
+# the end
+"#
+        );
+
+        assert!(file.set(&parse_key("environments")?, &"x".into()).is_err());
+        assert!(file
+            .append(&parse_key("defaultOwner")?, &"x".into())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_quotes_keys() -> Result<(), CliError> {
+        let mut file = ConfigFile::parse(Path::new("config.yaml"), "variables:\n  '10': ten\n");
+        file.set(&parse_key("variables.10")?, &"zehn".into())?;
+        file.set(&parse_key("variables.true")?, &"yes".into())?;
+        file.set(&parse_key("pathTemplates.a: b")?, &"{{path}}".into())?;
+
+        assert_eq!(
+            file.text(),
+            "variables:\n  '10': zehn\n  'true': yes\npathTemplates:\n  'a: b': '{{path}}'\n"
+        );
+        let value = file.value()?;
+        assert_eq!(value["variables"]["true"], Value::from("yes"));
+        assert_eq!(value["pathTemplates"]["a: b"], Value::from("{{path}}"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_rejects_flow_style() -> Result<(), CliError> {
+        let text = r#"environments:
+- name: staging
+  contexts:
+    - {name: prod1, variables: {foo: bar}}
+  variables: {region: eu}
+"#;
+        let mut file = ConfigFile::parse(Path::new("config.yaml"), text);
+
+        for key in [
+            "environments[staging].contexts[prod1].variables.region",
+            "environments[staging].contexts[prod2].variables.region",
+            "environments[staging].variables.region",
+        ] {
+            let error = file.set(&parse_key(key)?, &"eu".into()).unwrap_err();
+            assert!(error.message().ends_with("in block style"), "{key}");
+        }
+        assert!(file
+            .set(&parse_key("environments[staging].variables")?, &"eu".into())
+            .is_err());
+        assert!(file
+            .append(
+                &parse_key("environments[staging].contexts")?,
+                &serde_yaml::from_str("{name: qa}")?
+            )
+            .is_err());
+        assert_eq!(file.text(), text);
+
+        let mut file = ConfigFile::parse(Path::new("config.yaml"), "{defaultOwner: me}\n");
+        assert!(file
+            .set(&parse_key("defaultOwner")?, &"you".into())
+            .is_err());
+        Ok(())
+    }
+}
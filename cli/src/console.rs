@@ -1,3 +1,5 @@
+//! terminal styling and masking of secret values
+
 use std::fmt::Display;
 
 use console::Style;
@@ -33,6 +35,26 @@ impl Console {
     }
 }
 
+/// only the first characters of a value are shown unless `show_values` is set
+pub fn mask_value(value: &str, show_values: bool) -> String {
+    if show_values {
+        Console::emph(value)
+    } else {
+        format!(
+            "{}XXXX",
+            Console::highlight(value.chars().take(4).collect::<String>())
+        )
+    }
+}
+
+/// strings are used as they are, other values as json
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
 pub fn json_to_table_string(value: &Value, truncate_first_col: bool) -> Result<String, CliError> {
     let mut table = json_to_table::json_to_table(&serde_json::to_value(value)?).into_table();
 
@@ -0,0 +1,195 @@
+//! AES-256-GCM encryption of backup archives
+
+use std::num::NonZeroU32;
+
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+
+use crate::error::CliError;
+
+// archive layout: magic | key kind | pbkdf2 iterations (u32 be) | salt | nonce | ciphertext + tag
+// the header before the ciphertext is authenticated as associated data
+const MAGIC: &[u8; 8] = b"TRESOR01";
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + SALT_LEN + NONCE_LEN;
+const KEY_LEN: usize = 32;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+const KIND_KEY: u8 = 0;
+const KIND_PASSPHRASE: u8 = 1;
+
+pub enum EncryptionKey {
+    /// 32 byte key used directly for AES-256-GCM
+    Key(Vec<u8>),
+    /// passphrase to derive the key with PBKDF2-HMAC-SHA256
+    Passphrase(String),
+}
+
+impl EncryptionKey {
+    /// reads a key file containing either the raw 32 bytes or 64 hex characters
+    pub async fn from_key_file(path: &std::path::Path) -> Result<EncryptionKey, CliError> {
+        let content = tokio::fs::read(path).await?;
+
+        if content.len() == KEY_LEN {
+            return Ok(EncryptionKey::Key(content));
+        }
+
+        let hex = String::from_utf8_lossy(&content).trim().to_string();
+        match decode_hex(&hex) {
+            Some(key) if key.len() == KEY_LEN => Ok(EncryptionKey::Key(key)),
+            _ => Err(CliError::CommandError(format!(
+                "key file {} must contain {KEY_LEN} raw bytes or {} hex characters",
+                path.display(),
+                KEY_LEN * 2
+            ))),
+        }
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            EncryptionKey::Key(_) => KIND_KEY,
+            EncryptionKey::Passphrase(_) => KIND_PASSPHRASE,
+        }
+    }
+
+    fn derive(&self, salt: &[u8], iterations: u32) -> Result<LessSafeKey, CliError> {
+        let mut key = [0u8; KEY_LEN];
+        match self {
+            EncryptionKey::Key(bytes) => key.copy_from_slice(bytes),
+            EncryptionKey::Passphrase(passphrase) => {
+                let iterations = NonZeroU32::new(iterations).ok_or(CliError::CommandError(
+                    "invalid key derivation iterations in archive".into(),
+                ))?;
+                pbkdf2::derive(
+                    pbkdf2::PBKDF2_HMAC_SHA256,
+                    iterations,
+                    salt,
+                    passphrase.as_bytes(),
+                    &mut key,
+                )
+            }
+        }
+
+        let unbound = UnboundKey::new(&AES_256_GCM, &key)?;
+        Ok(LessSafeKey::new(unbound))
+    }
+}
+
+pub fn encrypt(plaintext: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, CliError> {
+    encrypt_with_iterations(plaintext, key, PBKDF2_ITERATIONS)
+}
+
+fn encrypt_with_iterations(
+    plaintext: &[u8],
+    key: &EncryptionKey,
+    iterations: u32,
+) -> Result<Vec<u8>, CliError> {
+    let random = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    random.fill(&mut salt)?;
+    random.fill(&mut nonce)?;
+
+    let mut header: Vec<u8> = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(key.kind());
+    header.extend_from_slice(&iterations.to_be_bytes());
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&nonce);
+
+    let mut in_out = plaintext.to_vec();
+    key.derive(&salt, iterations)?.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(&header),
+        &mut in_out,
+    )?;
+
+    header.extend_from_slice(&in_out);
+    Ok(header)
+}
+
+pub fn decrypt(archive: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, CliError> {
+    if archive.len() < HEADER_LEN || &archive[..MAGIC.len()] != MAGIC {
+        return Err(CliError::CommandError("not a tresor backup archive".into()));
+    }
+
+    let (header, ciphertext) = archive.split_at(HEADER_LEN);
+    let kind = header[MAGIC.len()];
+    if kind != key.kind() {
+        return Err(CliError::CommandError(match kind {
+            KIND_KEY => "archive was encrypted with a key file, use --key-file".into(),
+            _ => "archive was encrypted with a passphrase".into(),
+        }));
+    }
+
+    let iterations_start = MAGIC.len() + 1;
+    let salt_start = iterations_start + 4;
+    let nonce_start = salt_start + SALT_LEN;
+
+    let mut iterations = [0u8; 4];
+    iterations.copy_from_slice(&header[iterations_start..salt_start]);
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&header[nonce_start..HEADER_LEN]);
+
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = key
+        .derive(
+            &header[salt_start..nonce_start],
+            u32::from_be_bytes(iterations),
+        )?
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(header),
+            &mut in_out,
+        )
+        .map_err(|_| {
+            CliError::CommandError(
+                "unable to decrypt archive, wrong passphrase or key, or the archive is corrupted"
+                    .into(),
+            )
+        })?;
+
+    Ok(plaintext.to_vec())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        crypto::{decrypt, encrypt_with_iterations, EncryptionKey},
+        error::CliError,
+    };
+
+    #[test]
+    fn test_encrypt_decrypt() -> Result<(), CliError> {
+        let plaintext = b"secret backup";
+
+        let passphrase = EncryptionKey::Passphrase("passphrase".into());
+        let archive = encrypt_with_iterations(plaintext, &passphrase, 1000)?;
+        assert_eq!(decrypt(&archive, &passphrase)?, plaintext);
+        assert!(decrypt(&archive, &EncryptionKey::Passphrase("wrong".into())).is_err());
+
+        let key = EncryptionKey::Key(vec![7u8; 32]);
+        let mut archive = encrypt_with_iterations(plaintext, &key, 1000)?;
+        assert_eq!(decrypt(&archive, &key)?, plaintext);
+        assert!(decrypt(&archive, &passphrase).is_err());
+
+        // tampering with the authenticated header must be detected
+        archive[9] ^= 1;
+        assert!(decrypt(&archive, &key).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! key based changes between two sets of values
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use serde_json::Value;
+
+use crate::console::{mask_value, value_to_string, Console};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added {
+        key: String,
+        value: Value,
+    },
+    Removed {
+        key: String,
+        value: Value,
+    },
+    Changed {
+        key: String,
+        before: Value,
+        after: Value,
+    },
+}
+
+impl Change {
+    pub fn to_masked_string(&self, show_values: bool) -> String {
+        match self {
+            Change::Added { key, value } => format!(
+                "{} {key}: {}",
+                Console::success("+"),
+                mask_value(&value_to_string(value), show_values)
+            ),
+            Change::Removed { key, value } => format!(
+                "{} {key}: {}",
+                Console::error("-"),
+                mask_value(&value_to_string(value), show_values)
+            ),
+            Change::Changed { key, before, after } => format!(
+                "{} {key}: {} -> {}",
+                Console::warning("~"),
+                mask_value(&value_to_string(before), show_values),
+                mask_value(&value_to_string(after), show_values)
+            ),
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_masked_string(false))
+    }
+}
+
+/// changes needed to get from `before` to `after`, sorted by key
+pub fn diff_values(before: &HashMap<String, Value>, after: &HashMap<String, Value>) -> Vec<Change> {
+    let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+
+    keys.into_iter()
+        .filter_map(|key| match (before.get(key), after.get(key)) {
+            (None, Some(value)) => Some(Change::Added {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            (Some(value), None) => Some(Change::Removed {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            (Some(before), Some(after)) if before != after => Some(Change::Changed {
+                key: key.clone(),
+                before: before.clone(),
+                after: after.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// the values after a patch, see [`merge_patch`]
+pub fn patch_values(
+    before: &HashMap<String, Value>,
+    patch: &HashMap<String, Value>,
+) -> HashMap<String, Value> {
+    let mut patched = before.clone();
+    for (key, value) in patch {
+        if value.is_null() {
+            patched.remove(key);
+        } else {
+            merge_patch(
+                patched.entry(key.clone()).or_insert(Value::Null),
+                value.clone(),
+            );
+        }
+    }
+    patched
+}
+
+/// json merge patch (RFC 7386), null removes a key
+pub fn merge_patch(target: &mut Value, patch: Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    merge_patch(target.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+pub fn print_changes(changes: &[Change], show_values: bool) {
+    for change in changes {
+        println!("{}", change.to_masked_string(show_values))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde_json::{json, Value};
+
+    use super::{diff_values, Change};
+
+    #[test]
+    fn test_diff_values() {
+        let before: HashMap<String, Value> = HashMap::from([
+            ("removed".into(), json!("old")),
+            ("changed".into(), json!(1)),
+            ("same".into(), json!({"nested": "same"})),
+        ]);
+        let after: HashMap<String, Value> = HashMap::from([
+            ("added".into(), json!("new")),
+            ("changed".into(), json!(2)),
+            ("same".into(), json!({"nested": "same"})),
+        ]);
+
+        assert_eq!(
+            diff_values(&before, &after),
+            vec![
+                Change::Added {
+                    key: "added".into(),
+                    value: json!("new")
+                },
+                Change::Changed {
+                    key: "changed".into(),
+                    before: json!(1),
+                    after: json!(2)
+                },
+                Change::Removed {
+                    key: "removed".into(),
+                    value: json!("old")
+                },
+            ]
+        );
+        assert!(diff_values(&before, &before).is_empty());
+    }
+}
@@ -1,65 +1,232 @@
+//! error type used across the library and cli
+
 use std::fmt;
 
 use actix_web::ResponseError;
+use rustify::errors::ClientError as RestError;
 use serde::{Deserialize, Serialize};
 use vaultrs::{
     api::kv2::requests::SetSecretMetadataRequestBuilderError,
     client::VaultClientSettingsBuilderError, error::ClientError,
 };
 
-use crate::console::Console;
+use crate::{
+    console::Console,
+    logging::{redact, redact_literals},
+};
 
 #[derive(Serialize, Deserialize, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum CliError {
     RuntimeError(String),
-    VaultError(String),
+    /// a request vault answered with an error, `status` is `None` if there was no response
+    VaultError {
+        status: Option<u16>,
+        errors: Vec<String>,
+        reason: String,
+    },
     TemplateError(String),
     CommandError(String),
+    /// the config files can't be read or are invalid
+    ConfigError(String),
+    /// a secret, file or other resource does not exist
+    NotFoundError(String),
+    /// vault could not be reached, including timeouts
+    NetworkError(String),
     AuthError(String),
+    CasConflictError(String),
+    /// the token lacks capabilities for an operation, found before sending it
+    PermissionError(String),
+}
+
+/// what went wrong, independent of the message, each kind has its own exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    General,
+    Usage,
+    Config,
+    Template,
+    NotFound,
+    Forbidden,
+    AuthExpired,
+    Conflict,
+    Network,
+    Vault,
+}
+
+impl ErrorKind {
+    /// the process exit code, 1 is left for `tresor diff` finding differences
+    /// and 2 is also used by clap for invalid arguments
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Usage => 2,
+            ErrorKind::General => 3,
+            ErrorKind::Config => 4,
+            ErrorKind::Template => 5,
+            ErrorKind::NotFound => 6,
+            ErrorKind::Forbidden => 7,
+            ErrorKind::AuthExpired => 8,
+            ErrorKind::Conflict => 9,
+            ErrorKind::Network => 10,
+            ErrorKind::Vault => 11,
+        }
+    }
 }
 
 impl std::error::Error for CliError {}
 
+impl CliError {
+    /// the same error with the reason prefixed, like the location it occurred at
+    pub fn prefixed(self, prefix: &str) -> CliError {
+        let prefix = |reason: String| format!("{prefix}: {reason}");
+        match self {
+            Self::RuntimeError(reason) => Self::RuntimeError(prefix(reason)),
+            Self::VaultError {
+                status,
+                errors,
+                reason,
+            } => Self::VaultError {
+                status,
+                errors,
+                reason: prefix(reason),
+            },
+            Self::TemplateError(reason) => Self::TemplateError(prefix(reason)),
+            Self::CommandError(reason) => Self::CommandError(prefix(reason)),
+            Self::ConfigError(reason) => Self::ConfigError(prefix(reason)),
+            Self::NotFoundError(reason) => Self::NotFoundError(prefix(reason)),
+            Self::NetworkError(reason) => Self::NetworkError(prefix(reason)),
+            Self::AuthError(reason) => Self::AuthError(prefix(reason)),
+            Self::CasConflictError(reason) => Self::CasConflictError(prefix(reason)),
+            Self::PermissionError(reason) => Self::PermissionError(prefix(reason)),
+        }
+    }
+
+    /// an error vault returned for a request
+    pub fn vault(status: u16, errors: Vec<String>) -> CliError {
+        CliError::VaultError {
+            status: Some(status),
+            reason: format!("Vault returned an error: {status}, {}", errors.join(", ")),
+            errors,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::RuntimeError(_) => ErrorKind::General,
+            Self::VaultError { status, errors, .. } => match status {
+                Some(401) => ErrorKind::AuthExpired,
+                Some(403) if errors.iter().any(|error| error.contains("invalid token")) => {
+                    ErrorKind::AuthExpired
+                }
+                Some(403) => ErrorKind::Forbidden,
+                Some(404) => ErrorKind::NotFound,
+                Some(409) | Some(412) => ErrorKind::Conflict,
+                _ => ErrorKind::Vault,
+            },
+            Self::TemplateError(_) => ErrorKind::Template,
+            Self::CommandError(_) => ErrorKind::Usage,
+            Self::ConfigError(_) => ErrorKind::Config,
+            Self::NotFoundError(_) => ErrorKind::NotFound,
+            Self::NetworkError(_) => ErrorKind::Network,
+            Self::AuthError(_) => ErrorKind::AuthExpired,
+            Self::CasConflictError(_) => ErrorKind::Conflict,
+            Self::PermissionError(_) => ErrorKind::Forbidden,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.kind().exit_code()
+    }
+
+    /// the redacted reason without styling
+    pub fn message(&self) -> String {
+        let reason = match self {
+            Self::RuntimeError(reason)
+            | Self::TemplateError(reason)
+            | Self::CommandError(reason)
+            | Self::ConfigError(reason)
+            | Self::NotFoundError(reason)
+            | Self::NetworkError(reason)
+            | Self::AuthError(reason)
+            | Self::CasConflictError(reason)
+            | Self::PermissionError(reason) => reason,
+            Self::VaultError { reason, .. } => reason,
+        };
+        redact(&console::strip_ansi_codes(reason))
+    }
+
+    /// the error for `--output json`
+    pub fn to_json(&self) -> serde_json::Value {
+        let (status, errors) = match self {
+            Self::VaultError { status, errors, .. } => (*status, errors.clone()),
+            _ => (None, Vec::new()),
+        };
+        serde_json::json!({
+            "error": {
+                "kind": self.kind(),
+                "exitCode": self.exit_code(),
+                "message": self.message(),
+                "status": status,
+                "errors": errors.iter().map(|error| redact(error)).collect::<Vec<String>>(),
+            }
+        })
+    }
+}
+
 impl fmt::Debug for CliError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self)
     }
 }
 
+/// reasons pass [`redact`], so tokens never end up in error messages
 impl fmt::Display for CliError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::RuntimeError(reason) => {
-                write!(
-                    f,
-                    "{}: {}",
-                    Console::emph("general error"),
-                    Console::error(reason)
-                )
-            }
-            Self::VaultError(reason) => write!(f, "vault error: {}", Console::error(reason)),
-            Self::TemplateError(reason) => write!(f, "template error: {}", Console::error(reason)),
-            Self::CommandError(reason) => write!(f, "command error: {}", Console::error(reason)),
-            Self::AuthError(reason) => write!(f, "auth error: {}", Console::error(reason)),
-        }
+        let label = match self {
+            Self::RuntimeError(_) => Console::emph("general error"),
+            Self::VaultError { .. } => "vault error".into(),
+            Self::TemplateError(_) => "template error".into(),
+            Self::CommandError(_) => "command error".into(),
+            Self::ConfigError(_) => "config error".into(),
+            Self::NotFoundError(_) => "not found".into(),
+            Self::NetworkError(_) => "network error".into(),
+            Self::AuthError(_) => "auth error".into(),
+            Self::CasConflictError(_) => "check-and-set conflict".into(),
+            Self::PermissionError(_) => "permission denied".into(),
+        };
+        write!(f, "{label}: {}", Console::error(self.message()))
     }
 }
 
 impl From<std::io::Error> for CliError {
     fn from(error: std::io::Error) -> Self {
-        Self::VaultError(error.to_string())
+        match error.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFoundError(error.to_string()),
+            _ => Self::RuntimeError(error.to_string()),
+        }
     }
 }
 
+// serde errors quote the values they failed to parse, which can be secret data
 impl From<serde_json::Error> for CliError {
     fn from(error: serde_json::Error) -> Self {
-        Self::RuntimeError(error.to_string())
+        Self::RuntimeError(redact_literals(&error.to_string()))
     }
 }
 
 impl From<ClientError> for CliError {
     fn from(error: ClientError) -> Self {
-        Self::RuntimeError(error.to_string())
+        match error {
+            ClientError::APIError { code, errors } => Self::vault(code, errors),
+            ClientError::RestClientError {
+                source: RestError::ServerResponseError { code, .. },
+            } => Self::vault(code, Vec::new()),
+            ClientError::RestClientError {
+                source: RestError::RequestError { .. } | RestError::ResponseError { .. },
+            } => Self::NetworkError(error.to_string()),
+            error => Self::RuntimeError(error.to_string()),
+        }
     }
 }
 
@@ -71,13 +238,19 @@ impl From<VaultClientSettingsBuilderError> for CliError {
 
 impl From<reqwest::Error> for CliError {
     fn from(error: reqwest::Error) -> Self {
-        Self::RuntimeError(error.to_string())
+        match error.status() {
+            Some(status) => Self::vault(status.as_u16(), Vec::new()),
+            None if error.is_connect() || error.is_timeout() || error.is_request() => {
+                Self::NetworkError(error.to_string())
+            }
+            None => Self::RuntimeError(error.to_string()),
+        }
     }
 }
 
 impl From<serde_yaml::Error> for CliError {
     fn from(error: serde_yaml::Error) -> Self {
-        Self::RuntimeError(error.to_string())
+        Self::RuntimeError(redact_literals(&error.to_string()))
     }
 }
 
@@ -89,8 +262,67 @@ impl From<SetSecretMetadataRequestBuilderError> for CliError {
 
 impl From<minijinja::Error> for CliError {
     fn from(error: minijinja::Error) -> Self {
+        Self::TemplateError(error.to_string())
+    }
+}
+
+impl From<dialoguer::Error> for CliError {
+    fn from(error: dialoguer::Error) -> Self {
         Self::RuntimeError(error.to_string())
     }
 }
 
+impl From<ring::error::Unspecified> for CliError {
+    fn from(_: ring::error::Unspecified) -> Self {
+        Self::RuntimeError("cryptographic operation failed".into())
+    }
+}
+
 impl ResponseError for CliError {}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_error_kinds() {
+        let forbidden = CliError::from(ClientError::APIError {
+            code: 403,
+            errors: vec!["permission denied".into()],
+        });
+        assert_eq!(forbidden.kind(), ErrorKind::Forbidden);
+        assert_eq!(forbidden.exit_code(), 7);
+
+        let expired = CliError::vault(403, vec!["invalid token".into()]);
+        assert_eq!(expired.kind(), ErrorKind::AuthExpired);
+        assert_eq!(CliError::vault(404, Vec::new()).kind(), ErrorKind::NotFound);
+        assert_eq!(CliError::vault(500, Vec::new()).kind(), ErrorKind::Vault);
+
+        let missing = std::io::Error::new(std::io::ErrorKind::NotFound, "missing.json");
+        assert_eq!(CliError::from(missing).kind(), ErrorKind::NotFound);
+
+        let template = minijinja::Environment::new()
+            .render_str("{{ path", ())
+            .unwrap_err();
+        assert_eq!(CliError::from(template).exit_code(), 5);
+
+        let error = CliError::AuthError(format!(
+            "token {} expired",
+            Console::error("hvs.abcdefghijklmnopqrstuvwxyz")
+        ));
+        assert_eq!(
+            error.to_json(),
+            json!({
+                "error": {
+                    "kind": "auth-expired",
+                    "exitCode": 8,
+                    "message": "token [redacted] expired",
+                    "status": null,
+                    "errors": [],
+                }
+            })
+        );
+    }
+}
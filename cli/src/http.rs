@@ -0,0 +1,507 @@
+//! http client used for all requests to vault, with the tls, timeout, retry and proxy settings
+//! of an environment
+
+use std::{
+    future::Future,
+    net::ToSocketAddrs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use reqwest::{
+    Certificate, Client, ClientBuilder, Identity, Method, Proxy, RequestBuilder, Response, Url,
+};
+use rustify::errors::ClientError as RestError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use vaultrs::error::ClientError;
+
+use crate::{config::EnvironmentConfig, console::Console, error::CliError};
+
+/// tls settings of an environment, unset values fall back to the variables of the vault cli:
+/// `VAULT_CACERT`, `VAULT_CAPATH`, `VAULT_CLIENT_CERT`, `VAULT_CLIENT_KEY`,
+/// `VAULT_TLS_SERVER_NAME` and `VAULT_SKIP_VERIFY`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsSettings {
+    pub ca_cert: Option<String>,
+    pub ca_path: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub tls_server_name: Option<String>,
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsSettings {
+    pub fn for_environment(env: &EnvironmentConfig) -> TlsSettings {
+        Self::with_fallback(env, |name| std::env::var(name).ok())
+    }
+
+    fn with_fallback(
+        env: &EnvironmentConfig,
+        variable: impl Fn(&str) -> Option<String>,
+    ) -> TlsSettings {
+        let skip_verify_variable = variable("VAULT_SKIP_VERIFY")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "t" | "true"));
+
+        TlsSettings {
+            ca_cert: env.ca_cert.clone().or_else(|| variable("VAULT_CACERT")),
+            ca_path: env.ca_path.clone().or_else(|| variable("VAULT_CAPATH")),
+            client_cert: env
+                .client_cert
+                .clone()
+                .or_else(|| variable("VAULT_CLIENT_CERT")),
+            client_key: env
+                .client_key
+                .clone()
+                .or_else(|| variable("VAULT_CLIENT_KEY")),
+            tls_server_name: env
+                .tls_server_name
+                .clone()
+                .or_else(|| variable("VAULT_TLS_SERVER_NAME")),
+            insecure_skip_verify: env
+                .insecure_skip_verify
+                .or(skip_verify_variable)
+                .unwrap_or(false),
+        }
+    }
+
+    /// the address requests are sent to, with a server name the host is replaced by it and
+    /// resolved to the original host by the client, so the certificate is verified against it
+    pub fn request_address(&self, vault_address: &str) -> Result<String, CliError> {
+        match &self.tls_server_name {
+            Some(server_name) => {
+                let mut url = parse_address(vault_address)?;
+                url.set_host(Some(server_name)).map_err(|e| {
+                    CliError::CommandError(format!("invalid tls server name {server_name}: {e}"))
+                })?;
+                Ok(url.to_string().trim_end_matches('/').to_string())
+            }
+            None => Ok(vault_address.to_string()),
+        }
+    }
+
+    /// applies the settings to the builder of a client for the given vault address
+    pub fn configure(
+        &self,
+        builder: ClientBuilder,
+        vault_address: &str,
+    ) -> Result<ClientBuilder, CliError> {
+        let mut builder = builder.use_rustls_tls();
+
+        for certificate in self.ca_certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                let mut pem = read_file(cert, "client certificate")?;
+                pem.extend(read_file(key, "client key")?);
+                builder = builder.identity(Identity::from_pem(&pem).map_err(|e| {
+                    CliError::CommandError(format!("invalid client certificate {cert}: {e}"))
+                })?);
+            }
+            (None, None) => (),
+            _ => {
+                return Err(CliError::CommandError(
+                    "clientCert and clientKey must be set together".into(),
+                ))
+            }
+        }
+
+        if let Some(server_name) = &self.tls_server_name {
+            let url = parse_address(vault_address)?;
+            let host = url.host_str().unwrap_or_default();
+            let port = url.port_or_known_default().unwrap_or(8200);
+            let address = (host, port)
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| CliError::CommandError(format!("unable to resolve {host}")))?;
+            builder = builder.resolve(server_name, address);
+        }
+
+        if self.insecure_skip_verify {
+            eprintln!(
+                "{}",
+                Console::warning(
+                    "tls verification is disabled (insecureSkipVerify), do not use this in production"
+                )
+            );
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        Ok(builder)
+    }
+
+    fn ca_certificates(&self) -> Result<Vec<Certificate>, CliError> {
+        let mut files: Vec<String> = self.ca_cert.iter().cloned().collect();
+
+        if let Some(ca_path) = &self.ca_path {
+            let mut entries: Vec<String> = std::fs::read_dir(ca_path)
+                .map_err(|e| {
+                    CliError::CommandError(format!("unable to read caPath {ca_path}: {e}"))
+                })?
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .map(|path| path.display().to_string())
+                .collect();
+            entries.sort();
+            files.extend(entries);
+        }
+
+        let mut certificates: Vec<Certificate> = Vec::new();
+        for file in files {
+            let pem = read_file(&file, "ca certificate")?;
+            let bundle = Certificate::from_pem_bundle(&pem).map_err(|e| {
+                CliError::CommandError(format!("invalid ca certificate {file}: {e}"))
+            })?;
+            if bundle.is_empty() {
+                return Err(CliError::CommandError(format!(
+                    "no certificate found in {file}"
+                )));
+            }
+            certificates.extend(bundle);
+        }
+        Ok(certificates)
+    }
+}
+
+/// timeouts, retries, proxy and concurrency of the requests to vault, unset values use the defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(deny_unknown_fields)]
+pub struct HttpSettings {
+    /// seconds to wait for a connection, defaults to 10
+    pub connect_timeout_seconds: Option<u64>,
+    /// seconds a request may take including the response, defaults to 60
+    pub timeout_seconds: Option<u64>,
+    /// retries after connection errors, timeouts and 5xx or 429 responses, defaults to 3.
+    /// writes are only retried after connection errors and 503 or 429 responses
+    pub max_retries: Option<u32>,
+    /// wait before the first retry in milliseconds, doubled for every further one, defaults to 500
+    pub retry_backoff_millis: Option<u64>,
+    /// proxy for all requests, `HTTPS_PROXY` and friends are used if not set
+    pub proxy: Option<String>,
+    /// requests sent at the same time, defaults to 8
+    pub max_concurrent_requests: Option<usize>,
+}
+
+/// the http client of an environment, all requests to vault go through it.
+///
+/// clones share the connection pool and the concurrency limit
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    namespace: Option<String>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    limit: Arc<Semaphore>,
+}
+
+impl HttpClient {
+    pub fn for_environment(env: &EnvironmentConfig) -> Result<HttpClient, CliError> {
+        let settings = env.http.clone().unwrap_or_default();
+
+        let mut builder = Client::builder()
+            .connect_timeout(Duration::from_secs(
+                settings.connect_timeout_seconds.unwrap_or(10),
+            ))
+            .timeout(Duration::from_secs(settings.timeout_seconds.unwrap_or(60)));
+
+        if let Some(proxy) = &settings.proxy {
+            builder = builder.proxy(
+                Proxy::all(proxy)
+                    .map_err(|e| CliError::CommandError(format!("invalid proxy {proxy}: {e}")))?,
+            );
+        }
+
+        Ok(HttpClient {
+            client: env
+                .tls_settings()
+                .configure(builder, &env.vault_address)?
+                .build()?,
+            namespace: env.namespace.clone(),
+            max_retries: settings.max_retries.unwrap_or(3),
+            retry_backoff: Duration::from_millis(settings.retry_backoff_millis.unwrap_or(500)),
+            limit: Arc::new(Semaphore::new(
+                settings.max_concurrent_requests.unwrap_or(8).max(1),
+            )),
+        })
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn namespace(&self) -> Option<&String> {
+        self.namespace.as_ref()
+    }
+
+    /// the same client sending another namespace
+    pub fn with_namespace(&self, namespace: Option<String>) -> HttpClient {
+        HttpClient {
+            namespace,
+            ..self.clone()
+        }
+    }
+
+    /// a request with the namespace header
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.namespace {
+            Some(namespace) => request.header("X-Vault-Namespace", namespace),
+            None => request,
+        }
+    }
+
+    /// runs the operation within the concurrency limit and retries it with exponential
+    /// backoff as long as the outcome is retryable. requests with other methods than GET,
+    /// LIST, HEAD and DELETE may have changed data when they failed, they are only retried if
+    /// vault did not process them. the vault api path is only used for logging, each attempt
+    /// is logged with its status and latency
+    pub async fn execute<R, F, Fut>(&self, method: &str, path: &str, mut operation: F) -> R
+    where
+        R: Retryable,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = R>,
+    {
+        let mut attempt: u32 = 0;
+        loop {
+            let started = Instant::now();
+            let outcome = {
+                let _permit = self.limit.acquire().await.ok();
+                operation().await
+            };
+            tracing::info!(
+                method,
+                path,
+                status = outcome.status(),
+                latency_ms = started.elapsed().as_millis() as u64,
+                attempt = attempt + 1,
+                "vault request"
+            );
+
+            let retryable = match is_idempotent(method) {
+                true => outcome.is_retryable(),
+                false => outcome.is_retryable_write(),
+            };
+            if attempt >= self.max_retries || !retryable {
+                return outcome;
+            }
+
+            let backoff = self.retry_backoff.saturating_mul(1 << attempt.min(16));
+            attempt += 1;
+            tracing::warn!(
+                method,
+                path,
+                "request to vault failed, retrying in {}ms ({attempt}/{})",
+                backoff.as_millis(),
+                self.max_retries
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
+}
+
+/// whether the outcome of a request is worth another attempt:
+/// connection errors, timeouts and 5xx or 429 responses, never other 4xx responses
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+
+    /// like [`Retryable::is_retryable`] for requests which change data, only if the request
+    /// can't have been processed: connection errors and 429 or 503 responses
+    fn is_retryable_write(&self) -> bool;
+
+    /// the http status for the log, or what went wrong if there is none
+    fn status(&self) -> String;
+}
+
+fn is_retryable_status(code: u16) -> bool {
+    code == 429 || code >= 500
+}
+
+fn is_retryable_write_status(code: u16) -> bool {
+    code == 429 || code == 503
+}
+
+fn is_idempotent(method: &str) -> bool {
+    matches!(method, "GET" | "LIST" | "HEAD" | "DELETE")
+}
+
+impl<T> Retryable for Result<T, ClientError> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Err(ClientError::APIError { code, .. }) => is_retryable_status(*code),
+            Err(ClientError::RestClientError { source }) => match source {
+                RestError::RequestError { .. } | RestError::ResponseError { .. } => true,
+                RestError::ServerResponseError { code, .. } => is_retryable_status(*code),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn is_retryable_write(&self) -> bool {
+        match self {
+            Err(ClientError::APIError { code, .. }) => is_retryable_write_status(*code),
+            Err(ClientError::RestClientError { source }) => match source {
+                RestError::RequestError { source, .. } => source
+                    .downcast_ref::<reqwest::Error>()
+                    .is_some_and(reqwest::Error::is_connect),
+                RestError::ServerResponseError { code, .. } => is_retryable_write_status(*code),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    // vaultrs doesn't return the status of successful responses
+    fn status(&self) -> String {
+        match self {
+            Ok(_) => "ok".into(),
+            Err(ClientError::APIError { code, .. }) => code.to_string(),
+            Err(ClientError::RestClientError { source }) => match source {
+                RestError::ServerResponseError { code, .. } => code.to_string(),
+                RestError::RequestError { .. } => "request failed".into(),
+                RestError::ResponseError { .. } => "response failed".into(),
+                _ => "error".into(),
+            },
+            Err(_) => "error".into(),
+        }
+    }
+}
+
+impl Retryable for Result<Response, reqwest::Error> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Ok(response) => is_retryable_status(response.status().as_u16()),
+            Err(error) => error.is_connect() || error.is_timeout() || error.is_request(),
+        }
+    }
+
+    fn is_retryable_write(&self) -> bool {
+        match self {
+            Ok(response) => is_retryable_write_status(response.status().as_u16()),
+            Err(error) => error.is_connect(),
+        }
+    }
+
+    fn status(&self) -> String {
+        match self {
+            Ok(response) => response.status().as_u16().to_string(),
+            Err(error) if error.is_timeout() => "timeout".into(),
+            Err(error) if error.is_connect() => "connection failed".into(),
+            Err(_) => "request failed".into(),
+        }
+    }
+}
+
+fn parse_address(vault_address: &str) -> Result<Url, CliError> {
+    Url::parse(vault_address)
+        .map_err(|e| CliError::CommandError(format!("invalid vault address {vault_address}: {e}")))
+}
+
+fn read_file(file: &str, description: &str) -> Result<Vec<u8>, CliError> {
+    std::fs::read(Path::new(file))
+        .map_err(|e| CliError::CommandError(format!("unable to read {description} {file}: {e}")))
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::{Client, Method};
+    use serde_json::Value;
+
+    use crate::{
+        config::EnvironmentConfig,
+        error::CliError,
+        http::{HttpSettings, TlsSettings},
+        mock_server::mock_server,
+    };
+
+    #[tokio::test]
+    async fn test_write_retries() -> Result<(), CliError> {
+        let env = EnvironmentConfig {
+            http: Some(HttpSettings {
+                timeout_seconds: Some(1),
+                max_retries: Some(2),
+                retry_backoff_millis: Some(1),
+                ..HttpSettings::default()
+            }),
+            ..EnvironmentConfig::default()
+        };
+        let http = env.http()?;
+
+        // a write which may have reached vault is not repeated, 0 is a timeout
+        for (method, status, attempts) in [
+            (Method::GET, 500, 3),
+            (Method::POST, 500, 1),
+            (Method::POST, 0, 1),
+            (Method::POST, 503, 3),
+            (Method::POST, 429, 3),
+        ] {
+            let (address, requests) = mock_server(vec![(status, Value::Null)]).await;
+            let url = format!("{address}/v1/secret/data/app");
+            let _ = http
+                .execute(method.as_str(), "secret/data/app", || {
+                    http.request(method.clone(), &url).send()
+                })
+                .await;
+            assert_eq!(
+                requests.lock().unwrap().len(),
+                attempts,
+                "{method} {status}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls_settings() -> Result<(), CliError> {
+        let env = EnvironmentConfig {
+            vault_address: "https://127.0.0.1:8200".into(),
+            ca_cert: Some("config-ca.pem".into()),
+            ..EnvironmentConfig::default()
+        };
+
+        let settings = TlsSettings::with_fallback(&env, |name| match name {
+            "VAULT_CACERT" => Some("env-ca.pem".into()),
+            "VAULT_TLS_SERVER_NAME" => Some("vault.internal".into()),
+            "VAULT_SKIP_VERIFY" => Some("true".into()),
+            _ => None,
+        });
+
+        assert_eq!(settings.ca_cert, Some("config-ca.pem".into()));
+        assert_eq!(settings.tls_server_name, Some("vault.internal".into()));
+        assert!(settings.insecure_skip_verify);
+        assert_eq!(
+            settings.request_address(&env.vault_address)?,
+            "https://vault.internal:8200"
+        );
+
+        // the ca file does not exist
+        assert!(settings
+            .configure(Client::builder(), &env.vault_address)
+            .is_err());
+
+        let settings = TlsSettings {
+            tls_server_name: Some("vault.internal".into()),
+            ..TlsSettings::default()
+        };
+        assert!(settings
+            .configure(Client::builder(), &env.vault_address)
+            .is_ok());
+
+        let settings = TlsSettings {
+            client_cert: Some("cert.pem".into()),
+            ..TlsSettings::default()
+        };
+        assert!(settings
+            .configure(Client::builder(), &env.vault_address)
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! reading secret values from files, stdin and `key=value` arguments
+
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+use serde_json::Value;
+use tokio::io::AsyncReadExt;
+
+use crate::error::CliError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputFormat {
+    Json,
+    Yaml,
+    Dotenv,
+}
+
+impl FromStr for InputFormat {
+    type Err = CliError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(InputFormat::Json),
+            "yaml" | "yml" => Ok(InputFormat::Yaml),
+            "dotenv" | "env" => Ok(InputFormat::Dotenv),
+            other => Err(CliError::CommandError(format!(
+                "unknown input format {other}, must be one of json, yaml or dotenv"
+            ))),
+        }
+    }
+}
+
+/// reads and merges the values of all inputs, later inputs overwrite earlier ones.
+///
+/// an input can be
+/// - `-` to read from stdin
+/// - `key=value` for a single value
+/// - `key=@file` to use the content of a file as value, e.g. for certificates
+/// - a path to a json, yaml or dotenv file
+pub async fn read_inputs(
+    inputs: &[String],
+    format: Option<InputFormat>,
+) -> Result<HashMap<String, Value>, CliError> {
+    let mut values: HashMap<String, Value> = HashMap::new();
+
+    for input in inputs {
+        if input == "-" {
+            let mut content = String::new();
+            tokio::io::stdin().read_to_string(&mut content).await?;
+            values.extend(parse_input(
+                &content,
+                &format.clone().unwrap_or(InputFormat::Yaml),
+            )?);
+            continue;
+        }
+
+        match input.split_once('=') {
+            Some((key, value)) if !key.is_empty() && !Path::new(input).exists() => {
+                let value = match value.strip_prefix('@') {
+                    Some(file) => tokio::fs::read_to_string(file).await.map_err(|e| {
+                        CliError::from(e).prefixed(&format!("unable to read value file {file}"))
+                    })?,
+                    None => value.to_string(),
+                };
+                values.insert(key.to_string(), Value::String(value));
+            }
+            _ => {
+                let content = tokio::fs::read_to_string(input).await.map_err(|e| {
+                    CliError::from(e).prefixed(&format!("unable to read input file {input}"))
+                })?;
+                let file_format = format
+                    .clone()
+                    .unwrap_or_else(|| format_from_extension(Path::new(input)));
+                values.extend(parse_input(&content, &file_format)?);
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+fn format_from_extension(path: &Path) -> InputFormat {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => InputFormat::Json,
+        Some("env") => InputFormat::Dotenv,
+        _ if file_name == ".env" => InputFormat::Dotenv,
+        // yaml is a superset of json
+        _ => InputFormat::Yaml,
+    }
+}
+
+pub fn parse_input(
+    content: &str,
+    format: &InputFormat,
+) -> Result<HashMap<String, Value>, CliError> {
+    match format {
+        InputFormat::Json => Ok(serde_json::from_str(content)?),
+        InputFormat::Yaml => Ok(serde_yaml::from_str(content)?),
+        InputFormat::Dotenv => parse_dotenv(content),
+    }
+}
+
+fn parse_dotenv(content: &str) -> Result<HashMap<String, Value>, CliError> {
+    let mut values: HashMap<String, Value> = HashMap::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=').ok_or(CliError::CommandError(format!(
+            "invalid dotenv line {}, expected KEY=value",
+            index + 1
+        )))?;
+
+        let value = value.trim();
+        let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            value[1..value.len() - 1]
+                .replace("\\n", "\n")
+                .replace("\\\"", "\"")
+        } else if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+            value[1..value.len() - 1].to_string()
+        } else {
+            value.to_string()
+        };
+
+        values.insert(key.trim().to_string(), Value::String(value));
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::{
+        error::CliError,
+        input::{parse_input, read_inputs, InputFormat},
+    };
+
+    #[test]
+    fn test_parse_input() -> Result<(), CliError> {
+        let dotenv = parse_input(
+            "# comment\nexport KEY1=value1\nKEY2=\"multi\\nline\"\n\nKEY3='a=b'\n",
+            &InputFormat::Dotenv,
+        )?;
+        assert_eq!(
+            serde_json::to_value(dotenv)?,
+            json!({"KEY1": "value1", "KEY2": "multi\nline", "KEY3": "a=b"})
+        );
+
+        let yaml = parse_input("port: 8080\nnested:\n  key: value\n", &InputFormat::Yaml)?;
+        assert_eq!(
+            serde_json::to_value(yaml)?,
+            json!({"port": 8080, "nested": {"key": "value"}})
+        );
+
+        assert!(parse_input("[1, 2]", &InputFormat::Json).is_err());
+        assert!(parse_input("NO_SEPARATOR", &InputFormat::Dotenv).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_input_file() {
+        let error = read_inputs(&["missing-input.yaml".into()], None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.exit_code(), 6);
+
+        let error = read_inputs(&["key=@missing-value.pem".into()], None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.exit_code(), 6);
+    }
+}
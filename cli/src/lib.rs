@@ -0,0 +1,52 @@
+//! tresor, access and sync Vault KV secrets via templated locations
+//!
+//! the library contains everything the `tresor` cli is built on:
+//!
+//! - [`config`]: the config model and loading it from `~/.config/tresor/config.yaml`
+//! - [`location`]: resolving environment, context and templates to a mount and path
+//! - [`backend`]: versioned secrets with check-and-set, implemented by [`vault::Vault`] for the
+//!   kv1 and kv2 engines and by [`memory::MemoryBackend`] for tests and local development
+//! - [`sync`]: the engine applying the value mappings of the config
+//! - [`validate`]: the json schema and the checks of `tresor config validate`
+//! - [`audit`]: the local log of all writes, read by `tresor audit show`
+//! - [`permissions`]: checking the capabilities of the token, used by `sync --apply` and
+//!   `tresor can`
+//!
+//! ```no_run
+//! use tresor::{
+//!     backend::SecretBackend, config::load_or_create_config, error::CliError, location::Location,
+//! };
+//!
+//! # async fn example() -> Result<(), CliError> {
+//! let config = load_or_create_config().await?;
+//! let location: Location = "staging:prod1:some-service".parse()?;
+//! let resolved = location.resolve(&config).await?;
+//!
+//! let backend = resolved.env.backend()?;
+//! let (values, version) = backend.read(&resolved.mount, &resolved.path).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod audit;
+pub mod auth;
+pub mod backend;
+pub mod config;
+pub mod config_file;
+pub mod console;
+pub mod crypto;
+pub mod diff;
+pub mod error;
+pub mod http;
+pub mod input;
+pub mod loader;
+pub mod location;
+pub mod logging;
+pub mod memory;
+#[cfg(test)]
+mod mock_server;
+pub mod permissions;
+pub mod sync;
+mod template;
+pub mod validate;
+pub mod vault;
@@ -0,0 +1,761 @@
+//! finds the config files and merges them into one config.
+//!
+//! the global config in the home directory holds the environments and tokens of the user, a
+//! `.tresor.yaml` found in the current directory or above adds the templates and mappings of
+//! the project. the project file is merged over the global one: maps are merged key by key,
+//! lists of entries with a `name` (environments, contexts) are merged by name and all other
+//! values are replaced. a project can't change how environments connect, its environments may
+//! only contain a name, variables and contexts, so a cloned repository can't send the token of
+//! the user to another server.
+//!
+//! both files can `include` globs of further files, relative to the including file, which add
+//! mappings, templates and contexts. included mappings are appended and an included template
+//! must not be defined already.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::{Mapping, Value};
+
+use crate::{
+    config::{config_file_path, Config},
+    error::CliError,
+};
+
+pub const PROJECT_CONFIG_FILE: &str = ".tresor.yaml";
+
+/// the settings of an environment a project config may add to
+const PROJECT_ENVIRONMENT_KEYS: [&str; 3] = ["name", "variables", "contexts"];
+
+/// the files a config was loaded from and the file each setting came from
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    pub global: PathBuf,
+    pub project: Option<PathBuf>,
+    /// setting like `environments[staging].vaultAddress` to the file it came from
+    pub settings: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedConfig {
+    pub config: Config,
+    pub sources: ConfigSources,
+}
+
+/// the closest `.tresor.yaml` in the directory or one of its parents
+pub fn find_project_config(directory: &Path) -> Option<PathBuf> {
+    directory
+        .ancestors()
+        .map(|directory| directory.join(PROJECT_CONFIG_FILE))
+        .find(|file| file.is_file())
+}
+
+/// loads the global config, creating it if it doesn't exist, merged with the project config.
+/// the given project file is used instead of searching one from the current directory
+pub async fn load_config(project_file: Option<PathBuf>) -> Result<LoadedConfig, CliError> {
+    let (value, sources) = load_config_value(project_file).await?;
+    let config = serde_yaml::from_value(value)
+        .map_err(|e| CliError::ConfigError(format!("invalid config: {e}")))?;
+
+    Ok(LoadedConfig { config, sources })
+}
+
+/// like [`load_config`] but returns the merged yaml, even if it isn't a valid config
+pub async fn load_config_value(
+    project_file: Option<PathBuf>,
+) -> Result<(Value, ConfigSources), CliError> {
+    let (global, project) = config_files(project_file).await?;
+
+    let mut files = vec![global.clone()];
+    files.extend(project.clone());
+    let (value, settings) = load_files(&files)?;
+
+    Ok((
+        value,
+        ConfigSources {
+            global,
+            project,
+            settings,
+        },
+    ))
+}
+
+/// the global config, created if it doesn't exist, and the given project file or else the
+/// closest `.tresor.yaml`
+pub async fn config_files(
+    project_file: Option<PathBuf>,
+) -> Result<(PathBuf, Option<PathBuf>), CliError> {
+    let global = config_file_path().await?;
+    create_global_if_missing(&global).await?;
+
+    let project = match project_file {
+        Some(file) => Some(file),
+        None => find_project_config(&std::env::current_dir()?),
+    };
+    Ok((global, project))
+}
+
+async fn create_global_if_missing(file: &Path) -> Result<(), CliError> {
+    if !file.exists() {
+        println!(
+            "# no existing config found, creating default in {}, run 'tresor config init' to set it up",
+            file.display()
+        );
+        tokio::fs::write(file, serde_yaml::to_string(&Config::default())?).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn read_yaml(file: &Path) -> Result<Value, CliError> {
+    let data = tokio::fs::read(file).await.map_err(|e| {
+        CliError::ConfigError(format!("unable to read config {}: {e}", file.display()))
+    })?;
+
+    match serde_yaml::from_slice(&data) {
+        Ok(Value::Null) => Ok(Value::Mapping(Mapping::new())),
+        Ok(value) => Ok(value),
+        Err(e) => Err(CliError::ConfigError(format!(
+            "invalid config {}: {e}",
+            file.display()
+        ))),
+    }
+}
+
+/// merges the files in order, later ones win, the includes of a file are merged right after it
+fn load_files(files: &[PathBuf]) -> Result<(Value, BTreeMap<String, PathBuf>), CliError> {
+    let mut texts: Vec<(PathBuf, String)> = Vec::new();
+    for file in files {
+        tracing::debug!(file = %file.display(), "loading config");
+        let text = std::fs::read_to_string(file)
+            .map_err(|e| config_error(file, format!("unable to read: {e}")))?;
+        texts.push((file.clone(), text));
+    }
+    load_texts(&texts)
+}
+
+/// like [`load_files`] with the content of the files given, used to check changes before they
+/// are written. the includes are read from disk. the first file is the global config, the
+/// others are project configs
+pub(crate) fn load_texts(
+    files: &[(PathBuf, String)],
+) -> Result<(Value, BTreeMap<String, PathBuf>), CliError> {
+    let mut merged = Value::Mapping(Mapping::new());
+    let mut settings: BTreeMap<String, PathBuf> = BTreeMap::new();
+
+    for (index, (file, text)) in files.iter().enumerate() {
+        let value = parse_text(file, text)?;
+        if index > 0 {
+            check_project_environments(file, &value)?;
+        }
+        let patterns: Vec<String> = match value.get("include") {
+            Some(include) if !include.is_null() => serde_yaml::from_value(include.clone())
+                .map_err(|e| config_error(file, format!("invalid include: {e}")))?,
+            _ => Vec::new(),
+        };
+        merge_value(&mut merged, value, file, "", &mut settings);
+
+        let directory = match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        for pattern in patterns {
+            let included = glob(directory, &pattern)?;
+            if included.is_empty() && !has_wildcard(&pattern) {
+                return Err(config_error(
+                    file,
+                    format!("included file {pattern} not found"),
+                ));
+            }
+            for include in included {
+                merge_include(&mut merged, &include, &mut settings)?;
+            }
+        }
+    }
+    Ok((merged, settings))
+}
+
+/// a project config can't set the address, tls, auth or token of an environment
+fn check_project_environments(file: &Path, value: &Value) -> Result<(), CliError> {
+    for env in value
+        .get("environments")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+    {
+        let name = item_name(env).unwrap_or_default();
+        for key in env.as_mapping().into_iter().flat_map(Mapping::keys) {
+            let key = key_string(key);
+            if !PROJECT_ENVIRONMENT_KEYS.contains(&key.as_str()) {
+                return Err(config_error(
+                    file,
+                    format!("environments[{name}].{key} can only be set in the global config, a project config may only add variables and contexts to environments"),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// reads the file and sets the `file:line` of each mapping as its origin
+fn parse_file(file: &Path) -> Result<Value, CliError> {
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| config_error(file, format!("unable to read: {e}")))?;
+    parse_text(file, &text)
+}
+
+fn parse_text(file: &Path, text: &str) -> Result<Value, CliError> {
+    let mut value = match serde_yaml::from_str(text) {
+        Ok(Value::Null) => Value::Mapping(Mapping::new()),
+        Ok(value) => value,
+        Err(e) => return Err(config_error(file, e.to_string())),
+    };
+
+    if let Some(Value::Sequence(mappings)) = value.get_mut("mappings") {
+        let lines = mapping_lines(text);
+        for (index, mapping) in mappings.iter_mut().enumerate() {
+            let origin = match lines.get(index) {
+                Some(line) => format!("{}:{line}", file.display()),
+                None => file.display().to_string(),
+            };
+            if let Value::Mapping(mapping) = mapping {
+                mapping.insert("_origin".into(), origin.into());
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// line numbers of the entries of the top level `mappings` list in block style
+fn mapping_lines(text: &str) -> Vec<usize> {
+    let lines: Vec<&str> = text.lines().collect();
+    let Some(start) = lines.iter().position(|line| {
+        line.strip_prefix("mappings:")
+            .is_some_and(|rest| rest.trim().is_empty() || rest.trim().starts_with('#'))
+    }) else {
+        return Vec::new();
+    };
+
+    let mut item_indent: Option<usize> = None;
+    let mut result: Vec<usize> = Vec::new();
+    for (index, line) in lines.iter().enumerate().skip(start + 1) {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let indent = line.len() - trimmed.len();
+        let is_item = trimmed.starts_with('-');
+        match item_indent {
+            None if is_item => {
+                item_indent = Some(indent);
+                result.push(index + 1);
+            }
+            Some(item) if indent == item && is_item => result.push(index + 1),
+            Some(item) if indent > item => (),
+            _ => break,
+        }
+    }
+    result
+}
+
+/// merges an included file, which may only contain mappings, templates and contexts
+fn merge_include(
+    merged: &mut Value,
+    file: &Path,
+    settings: &mut BTreeMap<String, PathBuf>,
+) -> Result<(), CliError> {
+    let Value::Mapping(mut include) = parse_file(file)? else {
+        return Err(config_error(file, "an included file must be a map".into()));
+    };
+
+    for key in include.keys() {
+        let key = key_string(key);
+        if ![
+            "mappings",
+            "mountTemplates",
+            "pathTemplates",
+            "environments",
+        ]
+        .contains(&key.as_str())
+        {
+            return Err(config_error(
+                file,
+                format!("'{key}' can not be included, only mappings, mountTemplates, pathTemplates and environments with contexts"),
+            ));
+        }
+    }
+
+    for env in include
+        .get("environments")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+    {
+        let only_contexts = env.as_mapping().is_some_and(|env| {
+            env.keys()
+                .all(|key| key.as_str() == Some("name") || key.as_str() == Some("contexts"))
+        });
+        if item_name(env).is_none() || !only_contexts {
+            return Err(config_error(
+                file,
+                "included environments may only contain a name and contexts".into(),
+            ));
+        }
+    }
+
+    for kind in ["mountTemplates", "pathTemplates"] {
+        let Some(Value::Mapping(templates)) = include.get(kind) else {
+            continue;
+        };
+        for name in templates.keys() {
+            let name = key_string(name);
+            if merged
+                .get(kind)
+                .and_then(|defined| defined.get(&name))
+                .is_some()
+            {
+                let defined_in = settings
+                    .get(&format!("{kind}.{name}"))
+                    .map(|file| file.display().to_string())
+                    .unwrap_or_default();
+                return Err(config_error(
+                    file,
+                    format!("{kind} '{name}' is already defined in {defined_in}"),
+                ));
+            }
+        }
+    }
+
+    let mappings = include.remove("mappings");
+    merge_value(merged, Value::Mapping(include), file, "", settings);
+
+    if let Some(Value::Sequence(mappings)) = mappings {
+        let merged = merged
+            .as_mapping_mut()
+            .expect("the merged config is always a map");
+        let list = merged.entry("mappings".into()).or_insert(Value::Null);
+        if !list.is_sequence() {
+            *list = Value::Sequence(Vec::new());
+        }
+        let list = list.as_sequence_mut().expect("mappings is a list");
+
+        for mapping in mappings {
+            settings.insert(format!("mappings[{}]", list.len()), file.to_path_buf());
+            list.push(mapping);
+        }
+    }
+    Ok(())
+}
+
+fn config_error(file: &Path, reason: String) -> CliError {
+    CliError::ConfigError(format!("invalid config {}: {reason}", file.display()))
+}
+
+fn has_wildcard(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// files matching the pattern, relative patterns are resolved from the directory.
+/// `*` and `?` match within a path component, `**` matches any number of directories
+fn glob(directory: &Path, pattern: &str) -> Result<Vec<PathBuf>, CliError> {
+    let pattern = Path::new(pattern);
+    let mut candidates = vec![if pattern.is_absolute() {
+        PathBuf::from("/")
+    } else {
+        directory.to_path_buf()
+    }];
+
+    for component in pattern.components() {
+        let component = component.as_os_str().to_string_lossy();
+        if component == "/" {
+            continue;
+        }
+
+        let mut next: Vec<PathBuf> = Vec::new();
+        for candidate in candidates {
+            if component == "**" {
+                next.extend(directories_below(&candidate)?);
+            } else if has_wildcard(&component) {
+                let pattern: Vec<char> = component.chars().collect();
+                for entry in read_sorted(&candidate)? {
+                    let name: Vec<char> = entry
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .chars()
+                        .collect();
+                    if wildcard_match(&pattern, &name) {
+                        next.push(entry);
+                    }
+                }
+            } else {
+                let path = candidate.join(component.as_ref());
+                if path.exists() {
+                    next.push(path);
+                }
+            }
+        }
+        candidates = next;
+    }
+
+    candidates.retain(|candidate| candidate.is_file());
+    candidates.sort();
+    candidates.dedup();
+    Ok(candidates)
+}
+
+fn read_sorted(directory: &Path) -> Result<Vec<PathBuf>, CliError> {
+    if !directory.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(directory)?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+/// the directory itself and all directories below it
+fn directories_below(directory: &Path) -> Result<Vec<PathBuf>, CliError> {
+    let mut directories = vec![directory.to_path_buf()];
+    let mut index = 0;
+    while index < directories.len() {
+        let children: Vec<PathBuf> = read_sorted(&directories[index])?
+            .into_iter()
+            .filter(|entry| entry.is_dir())
+            .collect();
+        directories.extend(children);
+        index += 1;
+    }
+    Ok(directories)
+}
+
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            wildcard_match(&pattern[1..], name)
+                || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(expected), Some(actual)) if expected == actual => {
+            wildcard_match(&pattern[1..], &name[1..])
+        }
+        _ => false,
+    }
+}
+
+fn merge_value(
+    base: &mut Value,
+    overlay: Value,
+    file: &Path,
+    path: &str,
+    settings: &mut BTreeMap<String, PathBuf>,
+) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                let child = child_path(path, &key_string(&key));
+                if is_item_name(&child) {
+                    base.insert(key, value);
+                    continue;
+                }
+                match base.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value, file, &child, settings),
+                    None => {
+                        record(&value, file, &child, settings);
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Sequence(base), Value::Sequence(overlay))
+            if all_named(base) && all_named(&overlay) =>
+        {
+            // an empty list was recorded as a single setting
+            settings.remove(path);
+            for item in overlay {
+                let name = item_name(&item).unwrap_or_default();
+                let child = format!("{path}[{name}]");
+                match base
+                    .iter_mut()
+                    .find(|existing| item_name(existing) == Some(name.clone()))
+                {
+                    Some(existing) => merge_value(existing, item, file, &child, settings),
+                    None => {
+                        record(&item, file, &child, settings);
+                        base.push(item);
+                    }
+                }
+            }
+        }
+        (base, overlay) => {
+            settings.retain(|setting, _| !is_within(setting, path));
+            record(&overlay, file, path, settings);
+            *base = overlay;
+        }
+    }
+}
+
+/// records the file for all settings in the value, lists without names count as one setting
+fn record(value: &Value, file: &Path, path: &str, settings: &mut BTreeMap<String, PathBuf>) {
+    match value {
+        Value::Mapping(mapping)
+            if mapping
+                .keys()
+                .any(|key| !is_item_name(&child_path(path, &key_string(key)))) =>
+        {
+            for (key, value) in mapping {
+                let child = child_path(path, &key_string(key));
+                if !is_item_name(&child) {
+                    record(value, file, &child, settings);
+                }
+            }
+        }
+        Value::Sequence(items) if !items.is_empty() && all_named(items) => {
+            for item in items {
+                let name = item_name(item).unwrap_or_default();
+                record(item, file, &format!("{path}[{name}]"), settings);
+            }
+        }
+        _ => {
+            settings.insert(path.to_string(), file.to_path_buf());
+        }
+    }
+}
+
+fn all_named(items: &[Value]) -> bool {
+    items.iter().all(|item| item_name(item).is_some())
+}
+
+fn item_name(item: &Value) -> Option<String> {
+    item.get("name")?.as_str().map(String::from)
+}
+
+fn key_string(key: &Value) -> String {
+    match key.as_str() {
+        Some(key) => key.to_string(),
+        None => serde_yaml::to_string(key)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+/// the name of an entry in a named list identifies it and is no setting of its own
+fn is_item_name(path: &str) -> bool {
+    path.ends_with("].name")
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_string(),
+        path => format!("{path}.{key}"),
+    }
+}
+
+fn is_within(setting: &str, path: &str) -> bool {
+    path.is_empty()
+        || setting == path
+        || setting
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.starts_with('.') || rest.starts_with('['))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::Config,
+        error::CliError,
+        loader::{find_project_config, load_files, PROJECT_CONFIG_FILE},
+    };
+
+    #[test]
+    fn test_load_files() -> Result<(), CliError> {
+        let root = tempfile::tempdir()?;
+        let global_file = root.path().join("global.yaml");
+        let project_file = root.path().join("project/.tresor.yaml");
+        let include_file = root.path().join("project/mappings/payments.yaml");
+        std::fs::create_dir_all(include_file.parent().unwrap())?;
+
+        std::fs::write(
+            &global_file,
+            r#"
+defaultOwner: user
+mountTemplates:
+  default: kv2/{{service}}
+environments:
+  - name: staging
+    vaultAddress: https://vault.staging
+    token: secret-token
+    contexts:
+      - name: a
+        variables:
+          foo: global
+mappings:
+  - value: global
+    target: { mount: default, path: default, key: KEY }
+"#,
+        )?;
+        std::fs::write(
+            &project_file,
+            r#"
+include:
+  - mappings/*.yaml
+mountTemplates:
+  other: kv2/other/{{service}}
+environments:
+  - name: staging
+    contexts:
+      - name: a
+        variables:
+          foo: project
+mappings:
+  # the first mapping
+  - value: project
+    target: { mount: other, path: default, key: KEY }
+"#,
+        )?;
+        std::fs::write(
+            &include_file,
+            r#"pathTemplates:
+  payments: "{{path}}/payments"
+environments:
+  - name: staging
+    contexts:
+      - name: b
+mappings:
+  - value: included
+    target:
+      mount: other
+      path: payments
+      key: KEY
+  - value: included
+    target: { mount: other, path: payments, key: OTHER }
+"#,
+        )?;
+
+        let (value, settings) = load_files(&[global_file.clone(), project_file.clone()])?;
+        let config: Config = serde_yaml::from_value(value)?;
+
+        assert_eq!(config.mount_templates.unwrap_or_default().len(), 2);
+        assert_eq!(config.path_templates.unwrap_or_default().len(), 1);
+        let env = &config.environments[0];
+        assert_eq!(env.token, Some("secret-token".into()));
+        assert_eq!(env.contexts.len(), 2);
+        assert_eq!(
+            env.contexts[0].variables.clone().unwrap_or_default()["foo"],
+            "project"
+        );
+
+        let mappings = config.mappings.unwrap_or_default();
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(mappings[0].value, Some("project".into()));
+        assert_eq!(
+            mappings[0].origin,
+            Some(format!("{}:14", project_file.display()))
+        );
+        assert_eq!(
+            mappings[2].origin,
+            Some(format!("{}:13", include_file.display()))
+        );
+
+        assert_eq!(settings["defaultOwner"], global_file);
+        assert_eq!(settings["mountTemplates.other"], project_file);
+        assert_eq!(settings["pathTemplates.payments"], include_file);
+        assert_eq!(settings["environments[staging].token"], global_file);
+        assert_eq!(
+            settings["environments[staging].contexts[a].variables.foo"],
+            project_file
+        );
+        assert_eq!(settings["environments[staging].contexts[b]"], include_file);
+        assert_eq!(settings["mappings"], project_file);
+        assert_eq!(settings["mappings[2]"], include_file);
+        assert!(!settings.contains_key("environments[staging].name"));
+
+        // templates must not be defined twice
+        std::fs::write(
+            include_file.with_file_name("duplicate.yaml"),
+            "mountTemplates:\n  other: kv2/duplicate",
+        )?;
+        let error = load_files(&[global_file, project_file.clone()])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("duplicate.yaml"));
+        assert!(error.contains(&format!(
+            "mountTemplates 'other' is already defined in {}",
+            project_file.display()
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_cannot_change_connection() -> Result<(), CliError> {
+        let root = tempfile::tempdir()?;
+        let global_file = root.path().join("global.yaml");
+        let project_file = root.path().join(PROJECT_CONFIG_FILE);
+        std::fs::write(
+            &global_file,
+            r#"
+defaultOwner: user
+environments:
+  - name: production
+    vaultAddress: https://vault.production
+    token: secret-token
+    contexts: []
+"#,
+        )?;
+
+        for setting in [
+            "vaultAddress: http://127.0.0.1:9",
+            "insecureSkipVerify: true",
+            "caCert: /tmp/attacker.pem",
+            "authMount: oidc/other",
+        ] {
+            std::fs::write(
+                &project_file,
+                format!("environments:\n  - name: production\n    {setting}\n"),
+            )?;
+            let error = load_files(&[global_file.clone(), project_file.clone()]).unwrap_err();
+            assert_eq!(error.exit_code(), 4);
+            assert!(error
+                .to_string()
+                .contains("can only be set in the global config"));
+        }
+
+        std::fs::write(
+            &project_file,
+            "environments:\n  - name: production\n    variables: { team: payments }\n    contexts:\n      - name: eu\n",
+        )?;
+        let (value, _) = load_files(&[global_file, project_file])?;
+        let config: Config = serde_yaml::from_value(value)?;
+        assert_eq!(
+            config.environments[0].vault_address,
+            "https://vault.production"
+        );
+        assert_eq!(config.environments[0].contexts[0].name, "eu");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_project_config() -> Result<(), CliError> {
+        let root = tempfile::tempdir()?;
+        let nested = root.path().join("service/src");
+        std::fs::create_dir_all(&nested)?;
+
+        assert_eq!(find_project_config(&nested), None);
+
+        std::fs::write(root.path().join(PROJECT_CONFIG_FILE), "defaultOwner: team")?;
+        assert_eq!(
+            find_project_config(&nested),
+            Some(root.path().join(PROJECT_CONFIG_FILE))
+        );
+
+        Ok(())
+    }
+}
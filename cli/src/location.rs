@@ -0,0 +1,232 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use crate::{
+    config::{get_env, get_env_for_write, Config, EnvironmentConfig},
+    error::CliError,
+};
+
+/// everything needed to resolve a mount and path via the templates of the config.
+///
+/// can be parsed from a single argument:
+///
+/// `environment:context[:service[:path]][?mount-template=name&path-template=name&var=value][@version]`
+///
+/// query parameters other than the templates are passed as variables
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Location {
+    /// vault environment, like staging or production
+    pub environment: String,
+    /// context within the environment, '*' stands for all contexts in the sync engine
+    pub context: String,
+    /// service used in the templates
+    pub service: Option<String>,
+    /// path used in the templates
+    pub path: Option<String>,
+    /// name of the mount template, the configured default if not set
+    pub mount_template: Option<String>,
+    /// name of the path template, the configured default if not set
+    pub path_template: Option<String>,
+    /// additional variables used in the templates
+    pub variables: HashMap<String, String>,
+    /// specific version of the secret, the current one if not set
+    pub version: Option<u64>,
+}
+
+/// parses variables passed as `key=value`, entries without `=` are ignored
+pub fn parse_variables(variables: &[String]) -> HashMap<String, String> {
+    variables
+        .iter()
+        .filter_map(|variable| variable.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+impl FromStr for Location {
+    type Err = CliError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (location, version) = match value.rsplit_once('@') {
+            Some((location, version))
+                if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                let version = version.parse::<u64>().map_err(|e| {
+                    CliError::CommandError(format!("invalid version in location {value}: {e}"))
+                })?;
+                (location, Some(version))
+            }
+            _ => (value, None),
+        };
+
+        let (location, query) = location.split_once('?').unwrap_or((location, ""));
+
+        let mut parts = location
+            .splitn(4, ':')
+            .map(|part| Some(part.to_string()).filter(|part| !part.is_empty()));
+
+        let (Some(Some(environment)), Some(Some(context))) = (parts.next(), parts.next()) else {
+            return Err(CliError::CommandError(format!(
+                "location {value} must at least contain environment and context: 'environment:context[:service[:path]]'"
+            )));
+        };
+        let service = parts.next().flatten();
+        let path = parts.next().flatten();
+
+        let mut mount_template = None;
+        let mut path_template = None;
+        let mut variables: HashMap<String, String> = HashMap::new();
+
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            match parameter.split_once('=') {
+                Some(("mount-template", template)) => mount_template = Some(template.to_string()),
+                Some(("path-template", template)) => path_template = Some(template.to_string()),
+                Some((key, value)) => {
+                    variables.insert(key.to_string(), value.to_string());
+                }
+                None => {
+                    return Err(CliError::CommandError(format!(
+                        "invalid parameter '{parameter}' in location {value}, expected key=value"
+                    )))
+                }
+            }
+        }
+
+        Ok(Location {
+            environment,
+            context,
+            service,
+            path,
+            mount_template,
+            path_template,
+            variables,
+            version,
+        })
+    }
+}
+
+impl Location {
+    /// the same location within another context and with other templates, used for the mappings
+    pub fn with_templates(&self, context: &str, mount_template: &str, path_template: &str) -> Self {
+        Location {
+            context: context.to_string(),
+            mount_template: Some(mount_template.to_string()),
+            path_template: Some(path_template.to_string()),
+            ..self.clone()
+        }
+    }
+
+    pub async fn resolve(&self, config: &Config) -> Result<ResolvedLocation, CliError> {
+        let env = get_env(config, &self.environment).await?;
+        self.resolve_in(env, config)
+    }
+
+    /// like [`Location::resolve`] for locations that are written to, protected environments
+    /// require the exact environment and context names
+    pub async fn resolve_for_write(&self, config: &Config) -> Result<ResolvedLocation, CliError> {
+        let env = get_env_for_write(config, &self.environment, &self.context)?;
+        self.resolve_in(env, config)
+    }
+
+    fn resolve_in(
+        &self,
+        env: EnvironmentConfig,
+        config: &Config,
+    ) -> Result<ResolvedLocation, CliError> {
+        let context = env.get_context(&self.context, config)?;
+        let (mount, path) = context.mount_and_path(&env, self, config)?;
+        let env = env.for_context(&context, self, config)?;
+        tracing::debug!(
+            environment = env.name,
+            context = context.name,
+            mount,
+            path,
+            namespace = env.namespace,
+            "resolved location"
+        );
+
+        Ok(ResolvedLocation {
+            env,
+            mount,
+            path,
+            version: self.version,
+        })
+    }
+}
+
+/// a location after applying the templates, the namespace of the env is the one of the context
+#[derive(Debug, Clone)]
+pub struct ResolvedLocation {
+    pub env: EnvironmentConfig,
+    pub mount: String,
+    pub path: String,
+    pub version: Option<u64>,
+}
+
+impl ResolvedLocation {
+    pub fn is_same_secret(&self, other: &ResolvedLocation) -> bool {
+        self.env.name == other.env.name
+            && self.env.namespace == other.env.namespace
+            && self.mount == other.mount
+            && self.path == other.path
+    }
+
+    /// `mount/path` with the namespace if there is one, used in headers of the output
+    pub fn secret_path(&self) -> String {
+        match &self.env.namespace {
+            Some(namespace) => format!("{}/{} (namespace: {namespace})", self.mount, self.path),
+            None => format!("{}/{}", self.mount, self.path),
+        }
+    }
+}
+
+impl Display for ResolvedLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}/{}", self.env.name, self.mount, self.path)?;
+        if let Some(version) = self.version {
+            write!(f, "@{version}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, str::FromStr};
+
+    use crate::{
+        error::CliError,
+        location::{parse_variables, Location},
+    };
+
+    #[test]
+    fn test_parse_location() -> Result<(), CliError> {
+        let location = Location::from_str(
+            "staging:prod1:some-service:some/path?mount-template=other&var=value@3",
+        )?;
+        assert_eq!(location.environment, "staging");
+        assert_eq!(location.context, "prod1");
+        assert_eq!(location.service, Some("some-service".into()));
+        assert_eq!(location.path, Some("some/path".into()));
+        assert_eq!(location.mount_template, Some("other".into()));
+        assert_eq!(location.path_template, None);
+        assert_eq!(
+            location.variables,
+            HashMap::from([("var".to_string(), "value".to_string())])
+        );
+        assert_eq!(location.version, Some(3));
+
+        let location = Location::from_str("staging:prod1")?;
+        assert_eq!(location.service, None);
+        assert_eq!(location.path, None);
+        assert_eq!(location.version, None);
+
+        assert!(Location::from_str("staging").is_err());
+        assert!(Location::from_str("staging:prod1?invalid").is_err());
+
+        assert_eq!(
+            parse_variables(&["a=b=c".into(), "invalid".into()]),
+            HashMap::from([("a".to_string(), "b=c".to_string())])
+        );
+
+        Ok(())
+    }
+}
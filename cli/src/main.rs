@@ -1,23 +1,61 @@
-use std::{collections::HashMap, path::PathBuf};
-
-use clap::{command, Args, Parser, Subcommand};
-use config::{config_file_path, get_env, load_or_create_config, Config};
-use console::Console;
-use error::CliError;
-
-use crate::console::json_to_table_string;
-mod config;
-mod console;
-mod error;
-mod sync;
-mod template;
-mod vault;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use tresor::{
+    audit::AuditOperation,
+    config::{get_env, MetadataSettings},
+    console::{json_to_table_string, Console},
+    error::CliError,
+    input::InputFormat,
+    loader::{load_config, LoadedConfig},
+    location::{parse_variables, Location},
+    logging::{init_logging, LogFormat},
+    permissions::{permission_error, Operation, PermissionCheck},
+};
+
+use crate::commands::{
+    audit::show_audit_log,
+    config::{config_command, config_file_command},
+    confirm::confirm_secret_write,
+    metadata::{merge_metadata_from_args, set_metadata_from_args},
+};
+
+mod commands;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct TresorArgs {
     #[command(subcommand)]
     command: Commands,
+
+    /// project config merged over the global one, instead of the closest .tresor.yaml
+    #[clap(long, global = true, env = "TRESOR_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// don't ask for confirmations, writes to protected environments also need
+    /// TRESOR_CONFIRM_ENVIRONMENT set to the name of the environment
+    #[clap(short, long, global = true, default_value_t = false)]
+    yes: bool,
+
+    /// log to stderr, -v for info like the vault requests, -vv for debug and -vvv for trace.
+    /// secret data and tokens are never logged
+    #[clap(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// format of the log: text or json
+    #[clap(long, global = true, env = "TRESOR_LOG_FORMAT", default_value = "text")]
+    log_format: LogFormat,
+
+    /// format of errors on stderr, json prints an object with kind, exit code, message and the
+    /// status and errors returned by vault
+    #[clap(long, global = true, value_enum, env = "TRESOR_OUTPUT", default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
 #[derive(Debug, Clone, Args)]
@@ -54,19 +92,16 @@ struct VaultContextArgs {
 }
 
 impl VaultContextArgs {
-    fn variables_as_map(&self) -> Option<HashMap<String, String>> {
-        match self.variables.clone() {
-            Some(vars) => {
-                let mut map: HashMap<String, String> = HashMap::new();
-                for variable in vars {
-                    let mut split = variable.split("=");
-                    if let (Some(key), Some(value)) = (split.next(), split.next()) {
-                        map.insert(key.to_string(), value.to_string());
-                    }
-                }
-                Some(map)
-            }
-            None => None,
+    fn location(&self) -> Location {
+        Location {
+            environment: self.env.environment.clone(),
+            context: self.context.clone(),
+            service: self.service.clone(),
+            path: self.path.clone(),
+            mount_template: self.mount_template.clone(),
+            path_template: self.path_template.clone(),
+            variables: parse_variables(&self.variables.clone().unwrap_or_default()),
+            version: None,
         }
     }
 }
@@ -87,12 +122,154 @@ enum Commands {
     Set(SetCommandArgs),
     /// set values using the patch method
     Patch(SetCommandArgs),
-    /// show current config without tokens
-    Config,
+    /// edit values in $EDITOR and write them back after confirmation
+    Edit(EditCommandArgs),
+    /// copy secrets including custom metadata to another location
+    Cp(CopyCommandArgs),
+    /// move secrets with all their readable versions to another location, the source is
+    /// deleted after a successful write
+    Mv(CopyCommandArgs),
+    /// compare secrets of two locations, exits with 1 if differences are found
+    Diff(DiffCommandArgs),
+    /// export data, custom metadata and settings below a location into an encrypted archive
+    Backup(BackupCommandArgs),
+    /// restore an encrypted archive into a location
+    Restore(RestoreCommandArgs),
+    /// show the merged config without tokens and the file each setting came from
+    Config(ConfigArgs),
     /// print the current token of the environment
     Token(VaultEnvArgs),
     /// sync the value mappings in the environment configuration
     Sync(SyncCommandArgs),
+    /// get or set metadata and settings like max versions
+    Metadata {
+        #[command(subcommand)]
+        command: MetadataCommands,
+    },
+    /// the local log of all writes, in ~/.config/tresor/audit.jsonl or TRESOR_AUDIT_LOG
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+    /// check if the token may read, write, patch, list, delete or set metadata at a location
+    Can(CanCommandArgs),
+}
+
+#[derive(Debug, Args)]
+struct CanCommandArgs {
+    /// the operation: read, write, patch, list, delete or metadata
+    operation: Operation,
+
+    /// location: 'environment:context[:service[:path]]', templates and variables can be added
+    /// as query, like '?mount-template=other&var=value'
+    location: Location,
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditCommands {
+    /// show the recorded writes, oldest first
+    Show(AuditShowArgs),
+}
+
+#[derive(Debug, Args)]
+struct AuditShowArgs {
+    /// only writes to this environment
+    #[clap(short, long)]
+    environment: Option<String>,
+    /// only writes to secrets whose mount/path contains this
+    #[clap(short, long)]
+    path: Option<String>,
+    /// only writes by this os user
+    #[clap(short, long)]
+    user: Option<String>,
+    /// only this operation: write, patch, metadata or delete
+    #[clap(short, long)]
+    operation: Option<AuditOperation>,
+    /// only writes at or after this time, like 2024-05-01 or 2024-05-01T12:00
+    #[clap(short, long)]
+    since: Option<String>,
+    /// only show the last n matching writes
+    #[clap(short = 'n', long)]
+    limit: Option<usize>,
+    /// print the records as json lines
+    #[clap(long, default_value_t = false)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct ConfigArgs {
+    #[command(subcommand)]
+    command: Option<ConfigCommands>,
+
+    /// show the variables of a context and where each one comes from instead of the config
+    #[clap(long, num_args = 2, value_names = ["ENVIRONMENT", "CONTEXT"])]
+    resolved: Option<Vec<String>>,
+
+    /// command variables for --resolved, passed as key value pairs, example: '--variables VAR1=foo'
+    #[clap(long)]
+    variables: Option<Vec<String>>,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    /// check the config against its schema, the templates of every context and the mappings
+    Validate {
+        /// variables that are passed on the command line, example: '--variables VAR1=foo'
+        #[clap(long)]
+        variables: Option<Vec<String>>,
+    },
+    /// print the json schema of the config files
+    Schema,
+    /// set up the config interactively
+    Init(ConfigFileArgs),
+    /// add an environment
+    AddEnv {
+        /// name of the environment, like staging or production
+        name: String,
+        /// address of the vault server
+        #[clap(long)]
+        address: String,
+        /// mount of the oidc auth method used by login
+        #[clap(long)]
+        auth_mount: Option<String>,
+        /// vault enterprise namespace of the environment
+        #[clap(long)]
+        namespace: Option<String>,
+        #[command(flatten)]
+        file: ConfigFileArgs,
+    },
+    /// add a context to an environment
+    AddContext {
+        environment: String,
+        name: String,
+        /// context whose variables and namespace are inherited
+        #[clap(long)]
+        extends: Option<String>,
+        /// vault enterprise namespace of the context
+        #[clap(long)]
+        namespace: Option<String>,
+        /// variables of the context, passed as key value pairs, example: '--variables VAR1=foo'
+        #[clap(long)]
+        variables: Option<Vec<String>>,
+        #[command(flatten)]
+        file: ConfigFileArgs,
+    },
+    /// set a value, keys are like the sources of 'tresor config': environments[staging].vaultAddress
+    Set {
+        key: String,
+        value: String,
+        #[command(flatten)]
+        file: ConfigFileArgs,
+    },
+    /// print a value of the merged config and the file it comes from
+    Get { key: String },
+}
+
+#[derive(Debug, Clone, Args)]
+struct ConfigFileArgs {
+    /// change the project config, the closest .tresor.yaml or --config, instead of the global one
+    #[clap(long)]
+    project: bool,
 }
 
 #[derive(Debug, Args)]
@@ -111,6 +288,10 @@ struct SyncCommandArgs {
     /// only metadata will be set
     #[clap(long, env = "TRESOR_METADATA_ONLY", default_value_t = false)]
     metadata_only: bool,
+
+    /// mappings synced at the same time across contexts and target secrets
+    #[clap(long, env = "TRESOR_SYNC_PARALLELISM", default_value_t = 4)]
+    parallelism: usize,
 }
 
 #[derive(Debug, Args)]
@@ -128,8 +309,20 @@ struct SetCommandArgs {
     #[command(flatten)]
     context: VaultContextArgs,
 
-    /// input with json object data
-    input_file: PathBuf,
+    /// inputs to read the values from: json, yaml or dotenv files, '-' for stdin,
+    /// 'key=value' pairs or 'key=@file' to use the file content as value
+    #[clap(required_unless_present = "metadata_only")]
+    inputs: Vec<String>,
+
+    /// format of the input files and stdin: json, yaml or dotenv, detected by file extension
+    /// if not set, stdin defaults to yaml which also accepts json
+    #[clap(long, env = "TRESOR_INPUT_FORMAT")]
+    input_format: Option<InputFormat>,
+
+    /// check-and-set version the secret must have for the write to succeed,
+    /// defaults to the current version read before writing
+    #[clap(long, env = "TRESOR_CAS")]
+    cas: Option<u64>,
 
     #[command(flatten)]
     metadata: MetadataArgs,
@@ -140,6 +333,128 @@ struct SetCommandArgs {
 }
 
 #[derive(Debug, Args)]
+struct CopyCommandArgs {
+    /// source location: 'environment:context[:service[:path]]', templates and variables
+    /// can be added as query, like '?mount-template=other&path-template=default&var=value',
+    /// a specific version can be selected with '@version'
+    source: Location,
+
+    /// target location, same format as the source without version
+    target: Location,
+
+    /// copy all secrets below the source path
+    #[clap(short, long, default_value_t = false)]
+    recursive: bool,
+
+    /// copy all versions which are not deleted instead of only the current one, mv always does
+    #[clap(long, default_value_t = false)]
+    all_versions: bool,
+
+    /// write a new version if the target already exists
+    #[clap(long, default_value_t = false)]
+    overwrite: bool,
+
+    /// only show what would be copied
+    #[clap(long, default_value_t = false)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+struct DiffCommandArgs {
+    /// left location: 'environment:context[:service[:path]]', templates and variables
+    /// can be added as query, like '?mount-template=other&path-template=default&var=value',
+    /// a specific version can be selected with '@version'
+    left: Location,
+
+    /// right location, same format as the left one
+    right: Location,
+
+    /// show the values, default is false, only the first characters are shown
+    #[clap(long, env = "DIFF_SHOW_VALUES", default_value_t = false)]
+    show_values: bool,
+
+    /// only compare the data, not the custom metadata and settings
+    #[clap(long, default_value_t = false)]
+    ignore_metadata: bool,
+}
+
+#[derive(Debug, Args)]
+struct BackupKeyArgs {
+    /// file containing a 32 byte key (raw or hex encoded) for AES-256-GCM, if not set
+    /// the passphrase is read from TRESOR_BACKUP_PASSPHRASE or asked for
+    #[clap(long, env = "TRESOR_BACKUP_KEY_FILE")]
+    key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct BackupCommandArgs {
+    /// location to export recursively: 'environment:context[:service[:path]]', templates
+    /// and variables can be added as query, like '?mount-template=other&var=value'
+    location: Location,
+
+    /// archive file to write
+    // not named output, that is the global error format
+    #[clap(value_name = "OUTPUT")]
+    archive: PathBuf,
+
+    #[command(flatten)]
+    key: BackupKeyArgs,
+}
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+enum ConflictPolicy {
+    /// keep existing secrets
+    Skip,
+    /// write a new version for existing secrets
+    Overwrite,
+    /// abort before writing anything if a secret exists
+    Fail,
+}
+
+#[derive(Debug, Args)]
+struct RestoreCommandArgs {
+    /// archive file created by the backup command
+    input: PathBuf,
+
+    /// location to restore into, same format as in the backup command
+    location: Location,
+
+    #[command(flatten)]
+    key: BackupKeyArgs,
+
+    /// how to handle secrets which already exist at the target
+    #[clap(long, value_enum, default_value_t = ConflictPolicy::Fail)]
+    conflict: ConflictPolicy,
+
+    /// only show what would be restored
+    #[clap(long, default_value_t = false)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum EditFormat {
+    Yaml,
+    Json,
+}
+
+#[derive(Debug, Args)]
+struct EditCommandArgs {
+    #[command(flatten)]
+    context: VaultContextArgs,
+
+    /// format of the values in the editor
+    #[clap(long, value_enum, env = "TRESOR_EDIT_FORMAT", default_value_t = EditFormat::Yaml)]
+    format: EditFormat,
+
+    /// show the values in the diff, default is false, only the first characters are shown
+    #[clap(long, env = "EDIT_SHOW_VALUES", default_value_t = false)]
+    show_values: bool,
+
+    #[command(flatten)]
+    metadata: MetadataArgs,
+}
+
+#[derive(Debug, Default, Args)]
 struct MetadataArgs {
     /// set to true to set rotation related metadata fields
     #[clap(long, env = "TRESOR_METADATA_ROTATION")]
@@ -152,54 +467,122 @@ struct MetadataArgs {
     /// if not set current date time will be used
     #[clap(long, env = "TRESOR_METADATA_ROTATION_DATE")]
     metadata_rotation_date: Option<String>,
+    /// maximum number of versions kept for the secret
+    #[clap(long, env = "TRESOR_METADATA_MAX_VERSIONS")]
+    metadata_max_versions: Option<u64>,
+    /// require check-and-set for all writes to the secret
+    #[clap(long, env = "TRESOR_METADATA_CAS_REQUIRED")]
+    metadata_cas_required: Option<bool>,
+    /// delete versions after this duration, like '768h', '0s' disables deletion
+    #[clap(long, env = "TRESOR_METADATA_DELETE_VERSION_AFTER")]
+    metadata_delete_version_after: Option<String>,
+}
+
+impl MetadataArgs {
+    fn settings(&self) -> MetadataSettings {
+        MetadataSettings {
+            max_versions: self.metadata_max_versions,
+            cas_required: self.metadata_cas_required,
+            delete_version_after: self.metadata_delete_version_after.clone(),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum MetadataCommands {
+    /// show custom metadata and settings of a secret
+    Get(VaultContextArgs),
+    /// set custom metadata and settings of a secret, only the given ones are changed
+    Set(MetadataSetCommandArgs),
+}
+
+#[derive(Debug, Args)]
+struct MetadataSetCommandArgs {
+    #[command(flatten)]
+    context: VaultContextArgs,
+
+    #[command(flatten)]
+    metadata: MetadataArgs,
 }
 
 #[tokio::main]
-async fn main() -> Result<(), CliError> {
+async fn main() {
     let args = &TresorArgs::parse();
-    let config = load_or_create_config().await?;
-    let run = run_command(args, config).await?;
-    Ok(run)
+    init_logging(args.verbose, args.log_format);
+
+    if let Err(error) = run(args).await {
+        match args.output {
+            OutputFormat::Text => eprintln!("Error: {error}"),
+            OutputFormat::Json => eprintln!("{}", error.to_json()),
+        }
+        std::process::exit(error.exit_code());
+    }
 }
 
-async fn run_command(args: &TresorArgs, config: Config) -> Result<(), CliError> {
+async fn run(args: &TresorArgs) -> Result<(), CliError> {
+    // these work on the config files, so they run even if the config can't be loaded
+    if let Commands::Config(ConfigArgs {
+        command: Some(command),
+        ..
+    }) = &args.command
+    {
+        return config_file_command(command, args.config.clone()).await;
+    }
+
+    let loaded = load_config(args.config.clone()).await?;
+    run_command(args, loaded).await?;
+    Ok(())
+}
+
+async fn run_command(args: &TresorArgs, loaded: LoadedConfig) -> Result<(), CliError> {
+    let config = loaded.config.clone();
+
     match &args.command {
         Commands::Login { vault, role } => {
-            vault::login(&config, &vault.environment, role.to_owned()).await?;
+            tresor::auth::login(&config, &vault.environment, role.to_owned()).await?;
             Ok(())
         }
         Commands::Token(vault) => {
-            let env = get_env(&config, &vault.environment).await?;
+            let env = get_env(&config, &vault.environment)
+                .await?
+                .without_context(&config)?;
             let token = env.valid_token()?;
             println!("export VAULT_TOKEN={}", token);
             println!("export VAULT_ADDR={}", env.vault_address);
             println!("export VAULT_ADDRESS={}", env.vault_address);
-            Ok(())
-        }
-        Commands::Config => {
-            let config_file = config_file_path().await?;
-            let mut clean_config = config.clone();
 
-            clean_config.environments.iter_mut().for_each(|env| {
-                env.token = None;
-                env.token_valid_until = None
-            });
+            if let Some(namespace) = &env.namespace {
+                println!("export VAULT_NAMESPACE={namespace}");
+            }
 
-            println!(
-                "config {}:\n{}",
-                Console::highlight(config_file.display()),
-                serde_yaml::to_string(&clean_config)?
-            );
+            let tls = env.tls_settings();
+            let tls_variables = [
+                ("VAULT_CACERT", tls.ca_cert),
+                ("VAULT_CAPATH", tls.ca_path),
+                ("VAULT_CLIENT_CERT", tls.client_cert),
+                ("VAULT_CLIENT_KEY", tls.client_key),
+                ("VAULT_TLS_SERVER_NAME", tls.tls_server_name),
+            ];
+            for (name, value) in tls_variables {
+                if let Some(value) = value {
+                    println!("export {name}={value}");
+                }
+            }
+            if tls.insecure_skip_verify {
+                println!("export VAULT_SKIP_VERIFY=true");
+            }
             Ok(())
         }
+        Commands::Config(config_args) => {
+            config_command(config_args, &config, &loaded.sources).await
+        }
         Commands::List(args) => {
-            let env = get_env(&config, &args.env.environment).await?;
-            let context = env.get_context(&args.context)?;
-            let (mount, path) = context.mount_and_path(&env, args, &config)?;
+            let location = args.location().resolve(&config).await?;
+            let (mount, path) = (&location.mount, &location.path);
 
-            println!("listing secrets in {mount}/{path}:");
+            println!("listing secrets in {}:", location.secret_path());
 
-            let list = vaultrs::kv2::list(&env.vault_client()?, &mount, &path).await?;
+            let list = location.env.backend()?.list(mount, path).await?;
 
             for entry in list {
                 println!("{}", entry)
@@ -208,94 +591,236 @@ async fn run_command(args: &TresorArgs, config: Config) -> Result<(), CliError>
             Ok(())
         }
         Commands::Get(args) => {
-            let env = get_env(&config, &args.context.env.environment).await?;
-            let context = env.get_context(&args.context.context)?;
-            let (mount, path) = context.mount_and_path(&env, &args.context, &config)?;
-            println!("{mount}/{path}:");
-
-            let value =
-                vaultrs::kv2::read::<serde_json::Value>(&env.vault_client()?, &mount, &path)
-                    .await?;
+            let location = args.context.location().resolve(&config).await?;
+            let (mount, path) = (&location.mount, &location.path);
+            let backend = location.env.backend()?;
+            println!("{}:", location.secret_path());
 
-            println!("{}", json_to_table_string(&value, !args.show_values)?);
-
-            let mut metadata =
-                vaultrs::kv2::read_metadata(&env.vault_client()?, &mount, &path).await?;
-            metadata.versions = HashMap::new();
+            let (value, _) = backend.read(mount, path).await?;
+            let value = value.ok_or_else(|| {
+                CliError::NotFoundError(format!("no value found at {mount}/{path}"))
+            })?;
 
             println!(
-                "metadata:\n{}",
-                json_to_table_string(&serde_json::to_value(metadata)?, false)?
+                "{}",
+                json_to_table_string(&serde_json::to_value(value)?, !args.show_values)?
             );
 
+            if let Some(mut metadata) = backend.read_metadata(mount, path).await? {
+                metadata.versions.clear();
+
+                println!(
+                    "metadata:\n{}",
+                    json_to_table_string(&serde_json::to_value(metadata)?, false)?
+                );
+            }
+
             Ok(())
         }
         Commands::Set(set_args) => {
-            let env = get_env(&config, &set_args.context.env.environment).await?;
-            let context = env.get_context(&set_args.context.context)?;
-            let (mount, path) = context.mount_and_path(&env, &set_args.context, &config)?;
-
-            // read json from file
-            let data = tokio::fs::read(&set_args.input_file).await?;
-            let value: HashMap<String, String> = serde_json::from_slice(&data)?;
-
-            if !set_args.metadata_only {
-                let set_response = env.vault()?.set_data(&mount, &path, value).await?;
+            let location = set_args
+                .context
+                .location()
+                .resolve_for_write(&config)
+                .await?;
+            let (mount, path) = (&location.mount, &location.path);
+            let backend = location.env.backend()?;
+
+            let value = match set_args.metadata_only {
+                true => None,
+                false => Some(
+                    tresor::input::read_inputs(&set_args.inputs, set_args.input_format.clone())
+                        .await?,
+                ),
+            };
+            confirm_secret_write(&location, backend.as_ref(), value.as_ref(), false, args.yes)
+                .await?;
+
+            if let Some(value) = value {
+                let cas = match set_args.cas {
+                    Some(cas) => cas,
+                    None => backend.current_version(mount, path).await?,
+                };
+                let version = backend.write(mount, path, value, Some(cas)).await?;
                 println!(
-                    "set response: {}",
-                    json_to_table_string(&serde_json::to_value(set_response)?, false)?
+                    "{} {mount}/{path}, version: {version}",
+                    Console::success("updated data")
                 );
             } else {
                 println!("{}", Console::warning("only updating metadata"));
             };
 
-            crate::vault::set_metadata_from_args(
-                &env.vault_client()?,
-                &set_args.metadata,
-                &config.default_owner,
-                &mount,
-                &path,
-            )
-            .await?;
+            set_metadata_from_args(backend.as_ref(), &set_args.metadata, &config, mount, path)
+                .await?;
 
             println!("{}", Console::success("metadata updated"));
 
             Ok(())
         }
         Commands::Patch(patch_args) => {
-            let env = get_env(&config, &patch_args.context.env.environment).await?;
-            let context = env.get_context(&patch_args.context.context)?;
-            let (mount, path) = context.mount_and_path(&env, &patch_args.context, &config)?;
-
-            // read json from file
-            let data = tokio::fs::read(&patch_args.input_file).await?;
-            let value: HashMap<String, String> = serde_json::from_slice(&data)?;
-
-            let set_response = env.vault()?.patch_data(&mount, &path, value).await?;
-            crate::vault::set_metadata_from_args(
-                &env.vault_client()?,
-                &patch_args.metadata,
-                &config.default_owner,
-                &mount,
-                &path,
-            )
-            .await?;
+            let location = patch_args
+                .context
+                .location()
+                .resolve_for_write(&config)
+                .await?;
+            let (mount, path) = (&location.mount, &location.path);
+            let backend = location.env.backend()?;
+
+            let value = match patch_args.metadata_only {
+                true => None,
+                false => Some(
+                    tresor::input::read_inputs(&patch_args.inputs, patch_args.input_format.clone())
+                        .await?,
+                ),
+            };
+            confirm_secret_write(&location, backend.as_ref(), value.as_ref(), true, args.yes)
+                .await?;
+
+            if let Some(value) = value {
+                let cas = match patch_args.cas {
+                    Some(cas) => cas,
+                    None => backend.current_version(mount, path).await?,
+                };
+                let version = backend.patch(mount, path, value, Some(cas)).await?;
+                println!(
+                    "{} {mount}/{path}, version: {version}",
+                    Console::success("updated data")
+                );
+            } else {
+                println!("{}", Console::warning("only updating metadata"));
+            };
 
-            println!(
-                "set response: {}",
-                json_to_table_string(&serde_json::to_value(set_response)?, false)?
-            );
+            set_metadata_from_args(backend.as_ref(), &patch_args.metadata, &config, mount, path)
+                .await?;
+
+            println!("{}", Console::success("metadata updated"));
+
+            Ok(())
+        }
+        Commands::Edit(edit_args) => {
+            commands::edit::edit_secret(edit_args, &config, args.yes).await
+        }
+        Commands::Cp(copy_args) => {
+            commands::copy::copy_secrets(copy_args, &config, false, args.yes).await
+        }
+        Commands::Mv(copy_args) => {
+            commands::copy::copy_secrets(copy_args, &config, true, args.yes).await
+        }
+        Commands::Backup(backup_args) => {
+            commands::backup::backup_secrets(backup_args, &config).await
+        }
+        Commands::Restore(restore_args) => {
+            commands::backup::restore_secrets(restore_args, &config, args.yes).await
+        }
+        Commands::Diff(diff_args) => {
+            if commands::diff::diff_locations(diff_args, &config).await? {
+                std::process::exit(1);
+            }
             Ok(())
         }
         Commands::Sync(sync_args) => {
             match &config.mappings {
                 Some(_) => {
-                    crate::sync::sync_mappings(&sync_args, &config).await?;
+                    commands::sync::sync_command(sync_args, &config, args.yes).await?;
                 }
                 None => println!("{}", Console::warning("no mappings configured")),
             };
 
             Ok(())
         }
+        Commands::Metadata { command } => match command {
+            MetadataCommands::Get(args) => {
+                let location = args.location().resolve(&config).await?;
+                let (mount, path) = (&location.mount, &location.path);
+                println!("{mount}/{path}:");
+
+                let metadata = location
+                    .env
+                    .backend()?
+                    .read_metadata(mount, path)
+                    .await?
+                    .ok_or_else(|| {
+                        CliError::NotFoundError(format!("no secret found at {mount}/{path}"))
+                    })?;
+
+                println!(
+                    "settings:\n{}",
+                    json_to_table_string(
+                        &serde_json::json!({
+                            "current_version": metadata.current_version,
+                            "max_versions": metadata.settings.max_versions,
+                            "cas_required": metadata.settings.cas_required,
+                            "delete_version_after": metadata.settings.delete_version_after,
+                        }),
+                        false
+                    )?
+                );
+                println!(
+                    "custom metadata:\n{}",
+                    json_to_table_string(&serde_json::to_value(metadata.custom_metadata)?, false)?
+                );
+
+                Ok(())
+            }
+            MetadataCommands::Set(set_args) => {
+                let location = set_args
+                    .context
+                    .location()
+                    .resolve_for_write(&config)
+                    .await?;
+                let (mount, path) = (&location.mount, &location.path);
+                let backend = location.env.backend()?;
+                confirm_secret_write(&location, backend.as_ref(), None, false, args.yes).await?;
+
+                merge_metadata_from_args(
+                    backend.as_ref(),
+                    &set_args.metadata,
+                    &config,
+                    mount,
+                    path,
+                )
+                .await?;
+
+                println!(
+                    "{} for {mount}/{path}",
+                    Console::success("metadata updated")
+                );
+
+                Ok(())
+            }
+        },
+        Commands::Audit { command } => match command {
+            AuditCommands::Show(show_args) => show_audit_log(show_args),
+        },
+        Commands::Can(can_args) => {
+            let location = can_args.location.resolve(&config).await?;
+            let check = PermissionCheck::new(&location.mount, &location.path, can_args.operation);
+            let results = location
+                .env
+                .backend()?
+                .check_permissions(&[check])
+                .await?
+                .ok_or_else(|| {
+                    CliError::CommandError(format!(
+                        "permissions can't be checked in environment {}",
+                        location.env.name
+                    ))
+                })?;
+
+            if let Some(error) = permission_error(&results) {
+                return Err(error);
+            }
+            for result in results {
+                println!(
+                    "{} {} {}, granted on {}: {}",
+                    Console::success("yes,"),
+                    result.check.operation,
+                    location.secret_path(),
+                    result.api_path,
+                    result.granted.join(", ")
+                );
+            }
+            Ok(())
+        }
     }
 }
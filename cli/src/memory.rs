@@ -0,0 +1,417 @@
+//! in-memory secret backend, optionally persisted as plain json for tests and local development
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+};
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{SecretBackend, SecretData, SecretMetadata, SecretVersion},
+    config::MetadataSettings,
+    diff::patch_values,
+    error::CliError,
+    vault::{cas_conflict_for_path, join_path, now_date_string},
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredSecret {
+    created_time: String,
+    updated_time: String,
+    custom_metadata: HashMap<String, String>,
+    settings: MetadataSettings,
+    /// version n is at index n - 1, data is `None` for deleted versions
+    versions: Vec<StoredVersion>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredVersion {
+    created_time: String,
+    data: Option<SecretData>,
+}
+
+impl StoredSecret {
+    fn new() -> StoredSecret {
+        StoredSecret {
+            created_time: now_date_string(),
+            updated_time: now_date_string(),
+            ..StoredSecret::default()
+        }
+    }
+
+    fn current_version(&self) -> u64 {
+        self.versions.len() as u64
+    }
+
+    fn add_version(&mut self, data: SecretData) -> u64 {
+        self.updated_time = now_date_string();
+        self.versions.push(StoredVersion {
+            created_time: self.updated_time.clone(),
+            data: Some(data),
+        });
+
+        // like vault, only the last max versions are kept, 0 keeps all
+        let max_versions = self.settings.max_versions.unwrap_or(0) as usize;
+        if max_versions > 0 && self.versions.len() > max_versions {
+            let outdated = self.versions.len() - max_versions;
+            self.versions[..outdated]
+                .iter_mut()
+                .for_each(|version| version.data = None);
+        }
+
+        self.current_version()
+    }
+}
+
+type Secrets = Mutex<BTreeMap<String, StoredSecret>>;
+
+/// the secrets of the files backends are open for, by absolute path. backends of the same file
+/// share them, otherwise each would write its own copy and the last write wins
+static OPEN_FILES: Lazy<Mutex<HashMap<PathBuf, Weak<Secrets>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// keeps all secrets in memory, with a file every change is written to it
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    secrets: Arc<Secrets>,
+    file: Option<PathBuf>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    /// loads the secrets from the file if it exists, changes are written back to it.
+    /// backends open for the same file at the same time share the secrets
+    pub fn from_file(file: impl AsRef<Path>) -> Result<MemoryBackend, CliError> {
+        let file = std::path::absolute(file.as_ref())?;
+        let mut open_files = OPEN_FILES.lock().unwrap();
+        if let Some(secrets) = open_files.get(&file).and_then(Weak::upgrade) {
+            return Ok(MemoryBackend {
+                secrets,
+                file: Some(file),
+            });
+        }
+
+        let secrets = match std::fs::read(&file) {
+            Ok(content) => serde_json::from_slice(&content).map_err(|e| {
+                CliError::RuntimeError(format!(
+                    "unable to read secrets file {}: {e}",
+                    file.display()
+                ))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let secrets = Arc::new(Mutex::new(secrets));
+        open_files.retain(|_, secrets| secrets.strong_count() > 0);
+        open_files.insert(file.clone(), Arc::downgrade(&secrets));
+        Ok(MemoryBackend {
+            secrets,
+            file: Some(file),
+        })
+    }
+
+    fn update<T>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, StoredSecret>) -> Result<T, CliError>,
+    ) -> Result<T, CliError> {
+        let mut secrets = self.secrets.lock().unwrap();
+        let result = change(&mut secrets)?;
+
+        if let Some(file) = &self.file {
+            std::fs::write(file, serde_json::to_vec_pretty(&*secrets)?)?;
+        }
+        Ok(result)
+    }
+
+    fn get(&self, mount: &str, path: &str) -> Option<StoredSecret> {
+        self.secrets.lock().unwrap().get(&key(mount, path)).cloned()
+    }
+}
+
+fn key(mount: &str, path: &str) -> String {
+    join_path(mount, path).trim_end_matches('/').to_string()
+}
+
+fn check_cas(
+    secret: Option<&StoredSecret>,
+    mount: &str,
+    path: &str,
+    cas: Option<u64>,
+) -> Result<(), CliError> {
+    let current_version = secret.map(StoredSecret::current_version).unwrap_or(0);
+    let cas_required = secret
+        .and_then(|secret| secret.settings.cas_required)
+        .unwrap_or(false);
+
+    match cas {
+        Some(cas) if cas != current_version => Err(cas_conflict_for_path(
+            CliError::CasConflictError(String::new()),
+            mount,
+            path,
+            Some(cas),
+        )),
+        None if cas_required => Err(cas_conflict_for_path(
+            CliError::CasConflictError(String::new()),
+            mount,
+            path,
+            None,
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[async_trait]
+impl SecretBackend for MemoryBackend {
+    async fn read(&self, mount: &str, path: &str) -> Result<(Option<SecretData>, u64), CliError> {
+        Ok(match self.get(mount, path) {
+            Some(secret) => (
+                secret
+                    .versions
+                    .last()
+                    .and_then(|version| version.data.clone()),
+                secret.current_version(),
+            ),
+            None => (None, 0),
+        })
+    }
+
+    async fn read_version(
+        &self,
+        mount: &str,
+        path: &str,
+        version: u64,
+    ) -> Result<Option<SecretData>, CliError> {
+        Ok(self.get(mount, path).and_then(|secret| {
+            secret
+                .versions
+                .get((version as usize).checked_sub(1)?)
+                .and_then(|version| version.data.clone())
+        }))
+    }
+
+    async fn write(
+        &self,
+        mount: &str,
+        path: &str,
+        data: SecretData,
+        cas: Option<u64>,
+    ) -> Result<u64, CliError> {
+        self.update(|secrets| {
+            check_cas(secrets.get(&key(mount, path)), mount, path, cas)?;
+            Ok(secrets
+                .entry(key(mount, path))
+                .or_insert_with(StoredSecret::new)
+                .add_version(data))
+        })
+    }
+
+    async fn patch(
+        &self,
+        mount: &str,
+        path: &str,
+        data: SecretData,
+        cas: Option<u64>,
+    ) -> Result<u64, CliError> {
+        self.update(|secrets| {
+            let not_found =
+                || CliError::NotFoundError(format!("no secret found at {mount}/{path} to patch"));
+
+            let secret = secrets.get_mut(&key(mount, path)).ok_or_else(not_found)?;
+            check_cas(Some(secret), mount, path, cas)?;
+
+            let current = secret
+                .versions
+                .last()
+                .and_then(|version| version.data.clone())
+                .ok_or_else(not_found)?;
+
+            Ok(secret.add_version(patch_values(&current, &data)))
+        })
+    }
+
+    async fn list(&self, mount: &str, path: &str) -> Result<Vec<String>, CliError> {
+        let prefix = format!("{}/", key(mount, path));
+        let entries: BTreeSet<String> = self
+            .secrets
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .map(|relative| match relative.split_once('/') {
+                Some((folder, _)) => format!("{folder}/"),
+                None => relative.to_string(),
+            })
+            .collect();
+        Ok(entries.into_iter().collect())
+    }
+
+    async fn read_metadata(
+        &self,
+        mount: &str,
+        path: &str,
+    ) -> Result<Option<SecretMetadata>, CliError> {
+        Ok(self.get(mount, path).map(|secret| SecretMetadata {
+            current_version: secret.current_version(),
+            created_time: secret.created_time.clone(),
+            updated_time: secret.updated_time.clone(),
+            custom_metadata: secret.custom_metadata.clone(),
+            settings: MetadataSettings {
+                max_versions: Some(0),
+                cas_required: Some(false),
+                delete_version_after: Some("0s".into()),
+            }
+            .merge(&secret.settings),
+            versions: secret
+                .versions
+                .iter()
+                .enumerate()
+                .map(|(index, version)| SecretVersion {
+                    version: index as u64 + 1,
+                    created_time: version.created_time.clone(),
+                    deleted: version.data.is_none(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn write_metadata(
+        &self,
+        mount: &str,
+        path: &str,
+        custom_metadata: HashMap<String, String>,
+        settings: &MetadataSettings,
+    ) -> Result<(), CliError> {
+        self.update(|secrets| {
+            let secret = secrets
+                .entry(key(mount, path))
+                .or_insert_with(StoredSecret::new);
+            secret.custom_metadata = custom_metadata;
+            secret.settings = secret.settings.merge(settings);
+            secret.updated_time = now_date_string();
+            Ok(())
+        })
+    }
+
+    async fn delete(&self, mount: &str, path: &str) -> Result<(), CliError> {
+        self.update(|secrets| {
+            secrets.remove(&key(mount, path));
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use crate::{
+        backend::{SecretBackend, SecretData},
+        config::MetadataSettings,
+        error::CliError,
+        memory::MemoryBackend,
+    };
+
+    fn data(value: serde_json::Value) -> SecretData {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend() -> Result<(), CliError> {
+        let backend = MemoryBackend::new();
+
+        assert_eq!(backend.read("secret", "app/config").await?, (None, 0));
+        assert_eq!(
+            backend
+                .write(
+                    "secret",
+                    "app/config",
+                    data(json!({"a": "1", "b": "2"})),
+                    Some(0)
+                )
+                .await?,
+            1
+        );
+        assert!(matches!(
+            backend
+                .write("secret", "app/config", data(json!({})), Some(0))
+                .await,
+            Err(CliError::CasConflictError(_))
+        ));
+
+        backend
+            .patch(
+                "secret",
+                "app/config",
+                data(json!({"b": null, "c": "3"})),
+                Some(1),
+            )
+            .await?;
+        assert_eq!(
+            backend.read("secret", "app/config").await?,
+            (Some(data(json!({"a": "1", "c": "3"}))), 2)
+        );
+        assert_eq!(
+            backend.read_version("secret", "app/config", 1).await?,
+            Some(data(json!({"a": "1", "b": "2"})))
+        );
+
+        backend
+            .write("secret", "app/nested/other", data(json!({})), None)
+            .await?;
+        assert_eq!(
+            backend.list("secret", "app").await?,
+            vec!["config".to_string(), "nested/".to_string()]
+        );
+        assert_eq!(
+            backend.list_recursive("secret", "").await?,
+            vec!["app/config".to_string(), "app/nested/other".to_string()]
+        );
+
+        backend
+            .write_metadata(
+                "secret",
+                "app/config",
+                HashMap::from([("owner".to_string(), "team".to_string())]),
+                &MetadataSettings {
+                    max_versions: Some(1),
+                    cas_required: Some(true),
+                    delete_version_after: None,
+                },
+            )
+            .await?;
+        assert!(matches!(
+            backend
+                .write("secret", "app/config", data(json!({})), None)
+                .await,
+            Err(CliError::CasConflictError(_))
+        ));
+        backend
+            .write("secret", "app/config", data(json!({"d": "4"})), Some(2))
+            .await?;
+
+        let metadata = backend
+            .read_metadata("secret", "app/config")
+            .await?
+            .unwrap();
+        assert_eq!(metadata.current_version, 3);
+        assert_eq!(metadata.readable_versions(), vec![3]);
+        assert_eq!(metadata.custom_metadata.get("owner"), Some(&"team".into()));
+
+        backend.delete("secret", "app/config").await?;
+        assert_eq!(backend.read_metadata("secret", "app/config").await?, None);
+
+        Ok(())
+    }
+}
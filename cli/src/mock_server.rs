@@ -0,0 +1,54 @@
+//! a minimal http server for the tests of the vault client
+
+use std::sync::{Arc, Mutex};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// answers the requests in order with the given status and json body, the last one is repeated.
+/// a status of 0 never answers. returns the address and the recorded, lowercased request heads
+pub async fn mock_server(
+    responses: Vec<(u16, serde_json::Value)>,
+) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let requests: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request: Vec<u8> = Vec::new();
+            let mut buffer = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                match stream.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => request.extend_from_slice(&buffer[..read]),
+                }
+            }
+            let count = {
+                let mut recorded = recorded.lock().unwrap();
+                recorded.push(String::from_utf8_lossy(&request).to_lowercase());
+                recorded.len()
+            };
+
+            let (status, body) = &responses[count.min(responses.len()) - 1];
+            if *status == 0 {
+                tokio::spawn(async move {
+                    tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+                    drop(stream);
+                });
+                continue;
+            }
+            let body = body.to_string();
+            let answer = format!(
+                "HTTP/1.1 {status} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(answer.as_bytes()).await;
+        }
+    });
+
+    (address, requests)
+}
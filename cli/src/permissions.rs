@@ -0,0 +1,167 @@
+//! checks whether the token may perform operations on secrets before anything is written.
+//!
+//! vault answers with the capabilities of the token for each api path via
+//! `sys/capabilities-self`, the api paths of an operation depend on the kv version of the mount.
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::error::CliError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Read,
+    Write,
+    Patch,
+    List,
+    Delete,
+    Metadata,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Patch => "patch",
+            Operation::List => "list",
+            Operation::Delete => "delete",
+            Operation::Metadata => "metadata",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Operation {
+    type Err = CliError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "read" => Ok(Operation::Read),
+            "write" => Ok(Operation::Write),
+            "patch" => Ok(Operation::Patch),
+            "list" => Ok(Operation::List),
+            "delete" => Ok(Operation::Delete),
+            "metadata" => Ok(Operation::Metadata),
+            _ => Err(CliError::CommandError(format!(
+                "unknown operation {value}, must be one of: read, write, patch, list, delete, metadata"
+            ))),
+        }
+    }
+}
+
+/// an operation on a secret the token needs permission for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PermissionCheck {
+    pub mount: String,
+    pub path: String,
+    pub operation: Operation,
+}
+
+impl PermissionCheck {
+    pub fn new(mount: &str, path: &str, operation: Operation) -> Self {
+        PermissionCheck {
+            mount: mount.to_string(),
+            path: path.to_string(),
+            operation,
+        }
+    }
+}
+
+/// the capabilities of the token for the api path a check needs
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermissionResult {
+    pub check: PermissionCheck,
+    pub api_path: String,
+    pub granted: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl PermissionResult {
+    pub fn is_allowed(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+impl Display for PermissionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let granted = match self.granted.is_empty() {
+            true => "none".to_string(),
+            false => self.granted.join(", "),
+        };
+        write!(
+            f,
+            "{} {}/{} needs {} on {}, granted: {granted}",
+            self.check.operation,
+            self.check.mount,
+            self.check.path,
+            self.missing.join(", "),
+            self.api_path
+        )
+    }
+}
+
+/// the required capabilities which are not granted, `root` grants everything and `deny` nothing
+pub fn missing_capabilities(required: &[&str], granted: &[String]) -> Vec<String> {
+    if granted.iter().any(|capability| capability == "root") {
+        return Vec::new();
+    }
+    required
+        .iter()
+        .filter(|required| !granted.iter().any(|capability| capability == *required))
+        .map(|required| required.to_string())
+        .collect()
+}
+
+/// an error listing every denied check, `None` if all are allowed
+pub fn permission_error(results: &[PermissionResult]) -> Option<CliError> {
+    let denied: Vec<String> = results
+        .iter()
+        .filter(|result| !result.is_allowed())
+        .map(|result| format!("  {result}"))
+        .collect();
+    if denied.is_empty() {
+        return None;
+    }
+    Some(CliError::PermissionError(format!(
+        "the token is missing capabilities:\n{}",
+        denied.join("\n")
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_missing_capabilities() {
+        let granted = vec!["read".to_string(), "update".to_string()];
+        assert_eq!(
+            missing_capabilities(&["create", "update"], &granted),
+            vec!["create".to_string()]
+        );
+        assert!(missing_capabilities(&["read"], &granted).is_empty());
+        assert!(missing_capabilities(&["delete"], &["root".to_string()]).is_empty());
+        assert_eq!(
+            missing_capabilities(&["read"], &["deny".to_string()]),
+            vec!["read".to_string()]
+        );
+
+        let result = PermissionResult {
+            check: PermissionCheck::new("kv2", "app/prod", Operation::Write),
+            api_path: "kv2/data/app/prod".into(),
+            granted: vec!["read".into()],
+            missing: vec!["create".into(), "update".into()],
+        };
+        let error = permission_error(std::slice::from_ref(&result)).unwrap();
+        assert_eq!(error.exit_code(), 7);
+        assert!(error.message().ends_with(
+            "write kv2/app/prod needs create, update on kv2/data/app/prod, granted: read"
+        ));
+
+        let allowed = PermissionResult {
+            missing: Vec::new(),
+            ..result
+        };
+        assert!(permission_error(&[allowed]).is_none());
+    }
+}
@@ -1,267 +1,639 @@
-use std::collections::HashMap;
+//! applies the value mappings of the config to the contexts of an environment
 
-use vaultrs::error::ClientError;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{stream, StreamExt, TryStreamExt};
+use tokio::sync::OnceCell;
 
 use crate::{
-    config::{get_env, Config},
-    console::Console,
+    backend::{SecretBackend, SecretData},
+    config::{
+        get_env, get_env_for_write, Config, ContextConfig, EnvironmentConfig, MetadataSettings,
+        ValueMapping,
+    },
+    console::value_to_string,
     error::CliError,
-    SyncCommandArgs, VaultEnvArgs,
+    location::Location,
+    permissions::{permission_error, Operation, PermissionCheck, PermissionResult},
 };
 
-pub async fn sync_mappings(sync_args: &SyncCommandArgs, config: &Config) -> Result<(), CliError> {
-    let env = get_env(config, &sync_args.context.env.environment).await?;
+/// what to sync and how
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// environment, context ('*' for all contexts), service, path and variables used in the
+    /// templates of the mappings, the templates of the location itself are not used
+    pub location: Location,
+    /// changes are only written if this is set
+    pub apply: bool,
+    /// only metadata is written
+    pub metadata_only: bool,
+    /// mappings synced at the same time, across contexts and target secrets, at least 1
+    pub parallelism: usize,
+}
 
-    println!(
-        "syncing environment {}, apply: {}",
-        Console::highlight(&env.name),
-        Console::warning(sync_args.apply)
-    );
+#[derive(Debug, Clone, PartialEq)]
+pub enum MappingStatus {
+    /// the `when` expression of the mapping is false
+    Skipped,
+    /// there is no value for the key in the source
+    NoSourceValue,
+    /// the target would be updated if the sync was applied
+    WouldUpdate,
+    /// the target was updated, data is not written with `metadata_only`
+    Updated,
+}
 
-    let vault_client = &env.vault_client()?;
-    let vault = &env.vault()?;
+/// outcome of a single mapping in a context
+#[derive(Debug, Clone)]
+pub struct MappingResult {
+    pub context: String,
+    pub mapping: ValueMapping,
+    pub status: MappingStatus,
+    /// `mount/path#key` of the source, `None` for values from the config
+    pub source: Option<String>,
+    /// `mount/path#key` of the target
+    pub target: Option<String>,
+    /// value written to the target with the variables replaced
+    pub value: Option<String>,
+    /// value of the target key before the sync
+    pub previous: Option<String>,
+    /// the target secret did not exist before
+    pub created: bool,
+    pub metadata: HashMap<String, String>,
+    pub metadata_settings: MetadataSettings,
+}
 
-    let contexts = match sync_args.context.context.as_str() {
-        "*" => env.contexts.clone(),
-        context_name => vec![env.get_context(context_name)?],
-    };
+#[derive(Debug, Clone)]
+pub struct SyncReport {
+    pub environment: String,
+    pub results: Vec<MappingResult>,
+    /// the first error of a mapping, the results are the mappings synced without one
+    pub error: Option<CliError>,
+}
 
-    for context in contexts {
-        println!(
-            "syncing mappings for context {}",
-            Console::highlight(&context.name)
-        );
+impl SyncReport {
+    pub fn updated(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.status == MappingStatus::Updated)
+            .count()
+    }
+}
 
-        for mapping in config.mappings.clone().unwrap_or_default() {
-            match mapping.when.clone() {
-                Some(expression) => {
-                    let result = context.eval_with_variables(
-                        &expression,
-                        &env.name,
-                        sync_args.context.path.clone(),
-                        sync_args.context.service.clone(),
-                        sync_args.context.variables_as_map(),
-                    )?;
-                    if !result {
-                        println!(
-                            "{}",
-                            Console::highlight(format!(
-                                "skipping mapping: {mapping}, 'when' expression is false"
-                            ))
-                        );
-                        continue;
-                    }
-                }
-                None => (),
+/// syncs all mappings for the selected contexts. a failing mapping stops the mappings after it
+/// which share secrets with it, the others are synced and reported with the first error.
+/// each context uses its own backend with the namespace of the context, all share one http client
+/// or the secrets of the secrets file.
+/// with `apply` the permissions for all sources and targets are checked before the first write
+pub async fn sync_mappings(options: &SyncOptions, config: &Config) -> Result<SyncReport, CliError> {
+    let env = sync_env(options, config).await?;
+
+    let http = env.http()?;
+
+    let mut backends: Vec<(ContextConfig, Option<String>, Box<dyn SecretBackend>)> = Vec::new();
+    for context in selected_contexts(options, config, &env)? {
+        let context_env = env.for_context(&context, &options.location, config)?;
+        let backend = context_env.backend_with(&http)?;
+        backends.push((context, context_env.namespace, backend));
+    }
+
+    let contexts: Vec<ContextSync> = backends
+        .iter()
+        .map(|(context, namespace, backend)| ContextSync {
+            context,
+            namespace: namespace.clone(),
+            backend: backend.as_ref(),
+        })
+        .collect();
+
+    let (results, error) = sync_contexts(options, config, &env, &contexts).await?;
+    Ok(SyncReport {
+        environment: env.name,
+        results,
+        error,
+    })
+}
+
+/// like [`sync_mappings`] but with the given backend for all contexts instead of the ones
+/// of the environment
+pub async fn sync_mappings_with(
+    options: &SyncOptions,
+    config: &Config,
+    backend: &dyn SecretBackend,
+) -> Result<SyncReport, CliError> {
+    let env = sync_env(options, config).await?;
+
+    let selected = selected_contexts(options, config, &env)?;
+    let contexts: Vec<ContextSync> = selected
+        .iter()
+        .map(|context| ContextSync {
+            context,
+            namespace: None,
+            backend,
+        })
+        .collect();
+
+    let (results, error) = sync_contexts(options, config, &env, &contexts).await?;
+    Ok(SyncReport {
+        environment: env.name,
+        results,
+        error,
+    })
+}
+
+/// the environment to sync, applying to a protected environment requires the exact names
+async fn sync_env(options: &SyncOptions, config: &Config) -> Result<EnvironmentConfig, CliError> {
+    let location = &options.location;
+    if options.apply {
+        get_env_for_write(config, &location.environment, &location.context)
+    } else {
+        get_env(config, &location.environment).await
+    }
+}
+
+fn selected_contexts(
+    options: &SyncOptions,
+    config: &Config,
+    env: &EnvironmentConfig,
+) -> Result<Vec<ContextConfig>, CliError> {
+    match options.location.context.as_str() {
+        "*" => Ok(env.contexts.clone()),
+        context_name => Ok(vec![env.get_context(context_name, config)?]),
+    }
+}
+
+/// a context to sync with its backend, the namespace separates the secrets in the caches
+struct ContextSync<'a> {
+    context: &'a ContextConfig,
+    namespace: Option<String>,
+    backend: &'a dyn SecretBackend,
+}
+
+/// namespace, mount and path of a secret
+type SecretKey = (Option<String>, String, String);
+
+/// a source read once, the generation counts the writes to the secret during the run
+type SourceCache = (u64, Arc<OnceCell<Option<SecretData>>>);
+
+/// the reads and writes shared by all mappings of a run
+#[derive(Default)]
+struct SyncState {
+    /// sources are read once per generation, a write to a secret starts a new one
+    sources: Mutex<HashMap<SecretKey, SourceCache>>,
+    /// writes to the same secret from different contexts are serialized
+    targets: Mutex<HashMap<SecretKey, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl SyncState {
+    /// the values of the secret, read again if it was written while it was read
+    async fn read_source(
+        &self,
+        backend: &dyn SecretBackend,
+        key: &SecretKey,
+    ) -> Result<Option<SecretData>, CliError> {
+        let (_, mount, path) = key;
+        loop {
+            let (generation, cell) = self.source(key);
+            let values = cell
+                .get_or_try_init(|| async { Ok::<_, CliError>(backend.read(mount, path).await?.0) })
+                .await?
+                .clone();
+            if self.source(key).0 == generation {
+                return Ok(values);
             }
+        }
+    }
+
+    fn source(&self, key: &SecretKey) -> SourceCache {
+        self.sources
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone()
+    }
+
+    fn target_lock(&self, key: &SecretKey) -> Arc<tokio::sync::Mutex<()>> {
+        self.targets
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// called after the secret was written, later and running reads of it read it again
+    fn written(&self, key: &SecretKey) {
+        let mut sources = self.sources.lock().unwrap();
+        let (generation, cell) = sources.entry(key.clone()).or_default();
+        *generation += 1;
+        *cell = Arc::default();
+    }
+}
+
+/// context index, mapping index and what came of the mapping
+type Indexed<T> = (usize, usize, T);
+
+/// the results of a group of mappings and the error it stopped at
+type GroupOutcome = (Vec<Indexed<MappingResult>>, Option<Indexed<CliError>>);
+
+/// mappings of a context which have to run in order, because they write the same secret or
+/// one reads what another writes
+struct MappingGroup<'a> {
+    context: &'a ContextSync<'a>,
+    context_index: usize,
+    secrets: HashSet<SecretKey>,
+    mappings: Vec<(usize, ValueMapping)>,
+}
+
+/// syncs the groups of mappings of all contexts concurrently, up to `parallelism` at a time.
+/// the results are ordered by context and mapping, independent of the order they finished in,
+/// the error is the first one in that order
+async fn sync_contexts(
+    options: &SyncOptions,
+    config: &Config,
+    env: &EnvironmentConfig,
+    contexts: &[ContextSync<'_>],
+) -> Result<(Vec<MappingResult>, Option<CliError>), CliError> {
+    let parallelism = options.parallelism.max(1);
+
+    if options.apply {
+        let permissions: Vec<Vec<PermissionResult>> = stream::iter(contexts)
+            .map(|context| {
+                check_permissions(options, config, env, context.backend, context.context)
+            })
+            .buffered(parallelism)
+            .try_collect()
+            .await?;
+        if let Some(error) = permission_error(&permissions.concat()) {
+            return Err(error.prefixed("nothing was synced"));
+        }
+    }
+
+    let mut groups: Vec<MappingGroup> = Vec::new();
+    for (context_index, context) in contexts.iter().enumerate() {
+        groups.extend(mapping_groups(options, config, env, context, context_index));
+    }
+
+    let state = SyncState::default();
+    let outcomes: Vec<GroupOutcome> = stream::iter(groups)
+        .map(|group| sync_group(options, config, env, &state, group))
+        .buffer_unordered(parallelism)
+        .collect()
+        .await;
+
+    let mut results: Vec<Indexed<MappingResult>> = Vec::new();
+    let mut errors: Vec<Indexed<CliError>> = Vec::new();
+    for (group_results, error) in outcomes {
+        results.extend(group_results);
+        errors.extend(error);
+    }
+    results.sort_by_key(|(context_index, mapping_index, _)| (*context_index, *mapping_index));
+    errors.sort_by_key(|(context_index, mapping_index, _)| (*context_index, *mapping_index));
+
+    Ok((
+        results.into_iter().map(|(_, _, result)| result).collect(),
+        errors.into_iter().next().map(|(_, _, error)| error),
+    ))
+}
+
+/// splits the mappings of the context into groups without shared secrets, a mapping whose
+/// templates can't be rendered gets its own group and fails when it is synced
+fn mapping_groups<'a>(
+    options: &SyncOptions,
+    config: &Config,
+    env: &EnvironmentConfig,
+    context: &'a ContextSync<'a>,
+    context_index: usize,
+) -> Vec<MappingGroup<'a>> {
+    let location = &options.location;
+    let mut groups: Vec<MappingGroup> = Vec::new();
+
+    for (index, mapping) in config
+        .mappings
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+    {
+        let secrets: Result<HashSet<SecretKey>, CliError> = mapping
+            .source
+            .iter()
+            .chain([&mapping.target])
+            .map(|reference| {
+                let (mount, path) = context.context.mount_and_path(
+                    env,
+                    &location.with_templates(
+                        &context.context.name,
+                        &reference.mount,
+                        &reference.path,
+                    ),
+                    config,
+                )?;
+                Ok((context.namespace.clone(), mount, path))
+            })
+            .collect();
+        let secrets = secrets.unwrap_or_default();
+
+        let mut group = MappingGroup {
+            context,
+            context_index,
+            secrets: HashSet::new(),
+            mappings: Vec::new(),
+        };
+        let (related, unrelated): (Vec<MappingGroup>, Vec<MappingGroup>) = groups
+            .into_iter()
+            .partition(|group| !group.secrets.is_disjoint(&secrets));
+        for related in related {
+            group.secrets.extend(related.secrets);
+            group.mappings.extend(related.mappings);
+        }
+        group.secrets.extend(secrets);
+        group.mappings.sort_by_key(|(index, _)| *index);
+        group.mappings.push((index, mapping));
+
+        groups = unrelated;
+        groups.push(group);
+    }
+    groups
+}
 
-            let (source_value, source_message_part) =
-                match (mapping.source.clone(), mapping.value.clone()) {
-                    (None, value) => (value, None),
-                    (Some(source_ref), None) => {
-                        let (source_mount, source_path) = context.mount_and_path(
-                            &env,
-                            &crate::VaultContextArgs {
-                                env: VaultEnvArgs {
-                                    environment: env.name.to_string(),
-                                },
-                                context: context.name.clone(),
-                                service: sync_args.context.service.clone(),
-                                path: sync_args.context.path.clone(),
-                                variables: sync_args.context.variables.clone(),
-                                mount_template: Some(source_ref.mount.clone()),
-                                path_template: Some(source_ref.path.clone()),
-                            },
-                            config,
-                        )?;
-                        let read_source_values = vaultrs::kv2::read::<
-                            HashMap<String, Option<String>>,
-                        >(
-                            vault_client, &source_mount, &source_path
-                        )
-                        .await;
-
-                        let source_values = read_source_values.map_err(|e| {
-                            CliError::RuntimeError(format!(
-                                "unable to read source: {source_ref}: {}",
-                                e
-                            ))
-                        })?;
-
-                        let source_value = source_values.get(&source_ref.key).unwrap_or(&None);
-                        let source_message_part =
-                            format!("{source_mount}/{source_path}#{}", source_ref.key.clone());
-                        (source_value.clone(), Some(source_message_part))
-                    }
-                    _ => {
-                        return Err(CliError::RuntimeError(Console::error(
-                            "invalid source mapping",
-                        )))
-                    }
+/// syncs the mappings of the group in order, stops at the first error and returns it with the
+/// results before it
+async fn sync_group(
+    options: &SyncOptions,
+    config: &Config,
+    env: &EnvironmentConfig,
+    state: &SyncState,
+    group: MappingGroup<'_>,
+) -> GroupOutcome {
+    let mut results: Vec<Indexed<MappingResult>> = Vec::new();
+    for (index, mapping) in group.mappings {
+        let origin = mapping.origin.clone();
+        match sync_mapping(options, config, env, state, group.context, mapping).await {
+            Ok(result) => results.push((group.context_index, index, result)),
+            Err(e) => {
+                let error = match origin {
+                    Some(origin) => e.prefixed(&format!("mapping at {origin}")),
+                    None => e,
                 };
+                return (results, Some((group.context_index, index, error)));
+            }
+        }
+    }
+    (results, None)
+}
 
-            match source_value {
-                Some(source_value) => {
-                    let target = mapping.target.clone();
-
-                    let (target_mount, target_path) = context.mount_and_path(
-                        &env,
-                        &crate::VaultContextArgs {
-                            env: VaultEnvArgs {
-                                environment: env.name.to_string(),
-                            },
-                            context: context.name.clone(),
-                            service: sync_args.context.service.clone(),
-                            variables: sync_args.context.variables.clone(),
-                            path: sync_args.context.path.clone(),
-                            mount_template: Some(target.mount.clone()),
-                            path_template: Some(target.path.clone()),
-                        },
-                        config,
-                    )?;
-
-                    let target_key = target.key.clone();
-
-                    let target_message_part = format!("{target_mount}/{target_path}#{target_key}");
-
-                    let read_target_values = vaultrs::kv2::read::<HashMap<String, String>>(
-                        vault_client,
-                        &target_mount,
-                        &target_path,
-                    )
-                    .await;
-
-                    let mut target_values = match read_target_values {
-                        Ok(values) => values,
-                        Err(ClientError::APIError { code, errors: _ }) if code == 404 => {
-                            println!(
-                                "{}",
-                                Console::warning(
-                                    "no value found at target, this will be a create operation"
-                                )
-                            );
-                            HashMap::new()
-                        }
-                        Err(err) => {
-                            return Err(CliError::RuntimeError(format!(
-                                "unable to read target: {target_message_part}: {}",
-                                err
-                            )))
-                        }
-                    };
-
-                    let source_value_with_variables = context.replace_variables(
-                        &source_value,
-                        &env.name,
-                        sync_args.context.path.clone(),
-                        sync_args.context.service.clone(),
-                        sync_args.context.variables_as_map(),
-                    )?;
-
-                    target_values.insert(target.key.clone(), source_value_with_variables.clone());
-
-                    let source_value_message_part = if sync_args.show_values {
-                        format!("{}", Console::emph(source_value_with_variables.clone()))
-                    } else {
-                        format!(
-                            "{}XXXX",
-                            Console::highlight(
-                                source_value_with_variables
-                                    .chars()
-                                    .into_iter()
-                                    .take(4)
-                                    .collect::<String>()
-                            )
-                        )
-                    };
-
-                    let message =
-                        format!("{target_message_part}, with value: {source_value_message_part} from source: {}", source_message_part.unwrap_or("config value".into()));
-
-                    if sync_args.apply {
-                        if !sync_args.metadata_only {
-                            let set_response = vault
-                                .set_data(&target_mount, &target_path, target_values.clone())
-                                .await;
-
-                            set_response.map_err(|e| {
-                                CliError::RuntimeError(format!(
-                                    "unable to set target: {target_message_part}: {}",
-                                    e
-                                ))
-                            })?;
-
-                            println!("{} {message}", Console::success("updated data"))
-                        } else {
-                            println!("{}", Console::warning("not setting data, only metadata"))
-                        }
-
-                        let mut metadata = config.default_metadata.clone().unwrap_or_default();
-                        metadata.extend(mapping.metadata.clone().unwrap_or_default());
-
-                        for (_, value) in metadata.iter_mut() {
-                            *value = context.replace_variables(
-                                &value,
-                                &env.name,
-                                sync_args.context.path.clone(),
-                                sync_args.context.service.clone(),
-                                sync_args.context.variables_as_map(),
-                            )?;
-                        }
-
-                        let metadata_response = crate::vault::set_metadata(
-                            vault_client,
-                            metadata.clone(),
-                            &target_mount,
-                            &target_path,
-                        )
-                        .await;
-
-                        metadata_response.map_err(|e| {
-                            CliError::RuntimeError(format!(
-                                "unable to set metadata for target: {target_message_part}: {}",
-                                e
-                            ))
-                        })?;
-
-                        println!(
-                            "{} for {target_message_part} with {:?}",
-                            Console::success("updated metadata"),
-                            metadata
-                        )
-                    } else {
-                        println!(
-                            "{} (metadata only: {}) {message}",
-                            Console::warning("would update"),
-                            sync_args.metadata_only
-                        )
-                    }
-                }
-                None => println!("no source value found for {mapping}"),
+/// the permissions for the sources and targets of the mappings in the context, empty if the
+/// backend can't check them
+async fn check_permissions(
+    options: &SyncOptions,
+    config: &Config,
+    env: &EnvironmentConfig,
+    backend: &dyn SecretBackend,
+    context: &ContextConfig,
+) -> Result<Vec<PermissionResult>, CliError> {
+    let location = &options.location;
+    let mut checks: Vec<PermissionCheck> = Vec::new();
+    for mapping in config.mappings.clone().unwrap_or_default() {
+        if let Some(expression) = mapping.when.clone() {
+            let when = context.eval_with_variables(
+                &expression,
+                config,
+                env,
+                location.path.clone(),
+                location.service.clone(),
+                Some(location.variables.clone()),
+            )?;
+            if !when {
+                continue;
+            }
+        }
+
+        let mut operations = vec![(mapping.target.clone(), Operation::Read)];
+        if !options.metadata_only {
+            operations.push((mapping.target.clone(), Operation::Write));
+        }
+        operations.push((mapping.target.clone(), Operation::Metadata));
+        if let Some(source) = mapping.source.clone() {
+            operations.push((source, Operation::Read));
+        }
+
+        for (reference, operation) in operations {
+            let (mount, path) = context.mount_and_path(
+                env,
+                &location.with_templates(&context.name, &reference.mount, &reference.path),
+                config,
+            )?;
+            let check = PermissionCheck::new(&mount, &path, operation);
+            if !checks.contains(&check) {
+                checks.push(check);
             }
         }
     }
 
-    Ok(())
+    if checks.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(backend
+        .check_permissions(&checks)
+        .await?
+        .unwrap_or_default())
+}
+
+async fn sync_mapping(
+    options: &SyncOptions,
+    config: &Config,
+    env: &EnvironmentConfig,
+    state: &SyncState,
+    sync: &ContextSync<'_>,
+    mapping: ValueMapping,
+) -> Result<MappingResult, CliError> {
+    let (context, backend) = (sync.context, sync.backend);
+    let location = &options.location;
+    let mut result = MappingResult {
+        context: context.name.clone(),
+        mapping: mapping.clone(),
+        status: MappingStatus::Skipped,
+        source: None,
+        target: None,
+        value: None,
+        previous: None,
+        created: false,
+        metadata: HashMap::new(),
+        metadata_settings: MetadataSettings::default(),
+    };
+
+    if let Some(expression) = mapping.when.clone() {
+        let when = context.eval_with_variables(
+            &expression,
+            config,
+            env,
+            location.path.clone(),
+            location.service.clone(),
+            Some(location.variables.clone()),
+        )?;
+        if !when {
+            return Ok(result);
+        }
+    }
+
+    let source_value = match (mapping.source.clone(), mapping.value.clone()) {
+        (None, value) => value,
+        (Some(source_ref), None) => {
+            let (source_mount, source_path) = context.mount_and_path(
+                env,
+                &location.with_templates(&context.name, &source_ref.mount, &source_ref.path),
+                config,
+            )?;
+
+            let source_key = (
+                sync.namespace.clone(),
+                source_mount.clone(),
+                source_path.clone(),
+            );
+            let source_values = state
+                .read_source(backend, &source_key)
+                .await
+                .map_err(|e| e.prefixed(&format!("unable to read source: {source_ref}")))?;
+            let source_values = source_values.ok_or_else(|| {
+                CliError::NotFoundError(format!(
+                    "unable to read source: {source_ref}: no value at {source_mount}/{source_path}"
+                ))
+            })?;
+
+            result.source = Some(format!("{source_mount}/{source_path}#{}", source_ref.key));
+            source_values
+                .get(&source_ref.key)
+                .filter(|value| !value.is_null())
+                .map(value_to_string)
+        }
+        _ => return Err(CliError::RuntimeError("invalid source mapping".into())),
+    };
+
+    let Some(source_value) = source_value else {
+        result.status = MappingStatus::NoSourceValue;
+        return Ok(result);
+    };
+
+    let target = mapping.target.clone();
+    let (target_mount, target_path) = context.mount_and_path(
+        env,
+        &location.with_templates(&context.name, &target.mount, &target.path),
+        config,
+    )?;
+    let target_message_part = format!("{target_mount}/{target_path}#{}", target.key);
+
+    // held until the target is written, other contexts may write the same secret
+    let target_key = (
+        sync.namespace.clone(),
+        target_mount.clone(),
+        target_path.clone(),
+    );
+    let target_lock = state.target_lock(&target_key);
+    let _target_guard = target_lock.lock().await;
+
+    let (target_values, target_version) = backend
+        .read(&target_mount, &target_path)
+        .await
+        .map_err(|e| e.prefixed(&format!("unable to read target: {target_message_part}")))?;
+
+    result.created = target_values.is_none();
+    let mut target_values = target_values.unwrap_or_default();
+    result.previous = target_values.get(&target.key).map(value_to_string);
+
+    let source_value_with_variables = context.replace_variables(
+        &source_value,
+        config,
+        env,
+        location.path.clone(),
+        location.service.clone(),
+        Some(location.variables.clone()),
+    )?;
+
+    target_values.insert(
+        target.key.clone(),
+        serde_json::Value::String(source_value_with_variables.clone()),
+    );
+
+    let mut metadata = config.default_metadata.clone().unwrap_or_default();
+    metadata.extend(mapping.metadata.clone().unwrap_or_default());
+
+    for (_, value) in metadata.iter_mut() {
+        *value = context.replace_variables(
+            value,
+            config,
+            env,
+            location.path.clone(),
+            location.service.clone(),
+            Some(location.variables.clone()),
+        )?;
+    }
+
+    let metadata_settings = config
+        .metadata_settings_or_default()
+        .merge(&mapping.metadata_settings.clone().unwrap_or_default());
+
+    result.target = Some(target_message_part.clone());
+    result.value = Some(source_value_with_variables);
+    result.metadata = metadata.clone();
+    result.metadata_settings = metadata_settings.clone();
+
+    if !options.apply {
+        result.status = MappingStatus::WouldUpdate;
+        return Ok(result);
+    }
+
+    if !options.metadata_only {
+        backend
+            .write(
+                &target_mount,
+                &target_path,
+                target_values,
+                Some(target_version),
+            )
+            .await
+            .map_err(|e| match e {
+                CliError::CasConflictError(_) => e,
+                e => e.prefixed(&format!("unable to set target: {target_message_part}")),
+            })?;
+        state.written(&target_key);
+    }
+
+    backend
+        .write_metadata(&target_mount, &target_path, metadata, &metadata_settings)
+        .await
+        .map_err(|e| {
+            e.prefixed(&format!(
+                "unable to set metadata for target: {target_message_part}"
+            ))
+        })?;
+
+    result.status = MappingStatus::Updated;
+    Ok(result)
 }
 
-// the tests depend on the docker-compose setup in the project root
 #[cfg(test)]
 mod test {
     use std::{collections::HashMap, vec};
 
+    use async_trait::async_trait;
     use serde_json::json;
 
     use crate::{
-        config::{Config, ContextConfig, ValueMapping, ValueRef},
+        backend::{SecretBackend, SecretData, SecretMetadata},
+        config::{Config, ContextConfig, MetadataSettings, MountTemplate, ValueMapping, ValueRef},
         error::CliError,
-        sync::sync_mappings,
+        location::Location,
+        memory::MemoryBackend,
+        sync::{sync_mappings, sync_mappings_with, SecretKey, SyncOptions, SyncReport, SyncState},
         vault::now_date_string,
-        SyncCommandArgs, VaultContextArgs, VaultEnvArgs,
     };
 
-    #[tokio::test]
-    async fn test_sync_mappings() -> Result<(), CliError> {
+    fn test_config() -> Config {
         let mut variables: HashMap<String, String> = HashMap::new();
         variables.insert("var".into(), "path-from-var".into());
 
@@ -276,6 +648,8 @@ mod test {
                 },
                 when: None,
                 metadata: None,
+                metadata_settings: None,
+                origin: None,
             },
             ValueMapping {
                 value: None,
@@ -291,6 +665,12 @@ mod test {
                 },
                 when: None,
                 metadata: Some(variables.clone()),
+                metadata_settings: Some(MetadataSettings {
+                    max_versions: Some(5),
+                    cas_required: None,
+                    delete_version_after: None,
+                }),
+                origin: None,
             },
         ];
 
@@ -302,28 +682,13 @@ mod test {
             contexts: vec![ContextConfig {
                 name: "context".into(),
                 variables: Some(variables),
+                ..ContextConfig::default()
             }],
             auth_mount: None,
+            ..Default::default()
         };
 
-        let sync_args = SyncCommandArgs {
-            apply: true,
-            show_values: true,
-            metadata_only: false,
-            context: VaultContextArgs {
-                env: VaultEnvArgs {
-                    environment: "test".into(),
-                },
-                context: "*".into(),
-                service: Some("secret".into()),
-                path: Some("test-path".into()),
-                mount_template: None,
-                path_template: None,
-                variables: None,
-            },
-        };
-
-        let mut mount_templates: HashMap<String, String> = HashMap::new();
+        let mut mount_templates: HashMap<String, MountTemplate> = HashMap::new();
         mount_templates.insert("default".into(), "{{service}}".into());
 
         let mut path_templates: HashMap<String, String> = HashMap::new();
@@ -333,41 +698,68 @@ mod test {
         let mut metadata: HashMap<String, String> = HashMap::new();
         metadata.insert("metadata-now".into(), "{{now}}".into());
 
-        sync_mappings(
-            &sync_args,
-            &Config {
-                default_owner: "test-owner".into(),
-                default_mount_template: Some("default".into()),
-                default_path_template: Some("default".into()),
-                default_metadata: Some(metadata),
-                mount_templates: Some(mount_templates),
-                path_templates: Some(path_templates),
-                environments: vec![env.clone()],
-                mappings: Some(mappings.clone()),
+        Config {
+            default_owner: "test-owner".into(),
+            variables: None,
+            default_mount_template: Some("default".into()),
+            default_path_template: Some("default".into()),
+            default_metadata: Some(metadata),
+            default_metadata_settings: None,
+            mount_templates: Some(mount_templates),
+            path_templates: Some(path_templates),
+            environments: vec![env],
+            mappings: Some(mappings),
+            include: None,
+            strict_matching: None,
+        }
+    }
+
+    fn test_options() -> SyncOptions {
+        SyncOptions {
+            apply: true,
+            metadata_only: false,
+            parallelism: 4,
+            location: Location {
+                environment: "test".into(),
+                context: "*".into(),
+                service: Some("secret".into()),
+                path: Some("test-path".into()),
+                ..Location::default()
             },
-        )
-        .await?;
+        }
+    }
 
-        let value =
-            vaultrs::kv2::read::<serde_json::Value>(&env.vault_client()?, "secret", "test-path")
-                .await?;
+    async fn assert_synced(
+        report: SyncReport,
+        backend: &dyn SecretBackend,
+    ) -> Result<(), CliError> {
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.updated(), 2);
+        assert_eq!(
+            report.results[1].target,
+            Some("secret/test-path/path-from-var#mapped-field".into())
+        );
 
-        assert_eq!(value, json!({ "test-field": "source value" }));
+        let (value, _) = backend.read("secret", "test-path").await?;
+        assert_eq!(
+            serde_json::to_value(value)?,
+            json!({ "test-field": "source value" })
+        );
 
-        let value = vaultrs::kv2::read::<serde_json::Value>(
-            &env.vault_client()?,
-            "secret",
-            "test-path/path-from-var",
-        )
-        .await?;
+        let (value, _) = backend.read("secret", "test-path/path-from-var").await?;
+        assert_eq!(
+            serde_json::to_value(value)?,
+            json!({ "mapped-field": "source value" })
+        );
 
-        assert_eq!(value, json!({ "mapped-field": "source value" }));
+        let metadata = backend
+            .read_metadata("secret", "test-path/path-from-var")
+            .await?
+            .unwrap_or_default();
 
-        let metadata =
-            vaultrs::kv2::read_metadata(&env.vault_client()?, "secret", "test-path/path-from-var")
-                .await?;
+        assert_eq!(metadata.settings.max_versions, Some(5));
 
-        let current_metadata = metadata.custom_metadata.unwrap_or_default();
+        let current_metadata = metadata.custom_metadata;
         assert_eq!(
             current_metadata.get("var"),
             Some(&"path-from-var".to_string())
@@ -384,4 +776,291 @@ mod test {
 
         Ok(())
     }
+
+    // depends on the docker-compose setup in the project root
+    #[tokio::test]
+    async fn test_sync_mappings() -> Result<(), CliError> {
+        let config = test_config();
+        let report = sync_mappings(&test_options(), &config).await?;
+
+        assert_synced(report, config.environments[0].backend()?.as_ref()).await
+    }
+
+    #[tokio::test]
+    async fn test_sync_mappings_in_memory() -> Result<(), CliError> {
+        let backend = MemoryBackend::new();
+        let report = sync_mappings_with(&test_options(), &test_config(), &backend).await?;
+
+        assert_synced(report, &backend).await
+    }
+
+    #[tokio::test]
+    async fn test_sync_parallel() -> Result<(), CliError> {
+        let mut config = test_config();
+        let context = config.environments[0].contexts[0].clone();
+        config.environments[0].contexts = (0..6)
+            .map(|index| {
+                let mut variables = context.variables.clone().unwrap_or_default();
+                variables.insert("var".into(), format!("path-{index}"));
+                ContextConfig {
+                    name: format!("context-{index}"),
+                    variables: Some(variables),
+                    ..context.clone()
+                }
+            })
+            .collect();
+
+        // all contexts write the first target, the second mapping reads it as its source
+        let backend = MemoryBackend::new();
+        let report = sync_mappings_with(&test_options(), &config, &backend).await?;
+
+        assert_eq!(report.updated(), 12);
+        let order: Vec<(String, Option<String>)> = report
+            .results
+            .iter()
+            .map(|result| (result.context.clone(), result.target.clone()))
+            .collect();
+        let expected: Vec<(String, Option<String>)> = (0..6)
+            .flat_map(|index| {
+                [
+                    (
+                        format!("context-{index}"),
+                        Some("secret/test-path#test-field".into()),
+                    ),
+                    (
+                        format!("context-{index}"),
+                        Some(format!("secret/test-path/path-{index}#mapped-field")),
+                    ),
+                ]
+            })
+            .collect();
+        assert_eq!(order, expected);
+
+        let (value, _) = backend.read("secret", "test-path/path-5").await?;
+        assert_eq!(
+            serde_json::to_value(value)?,
+            json!({ "mapped-field": "source value" })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_secrets_file() -> Result<(), CliError> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("secrets.json");
+        let mut config = test_config();
+        let env = &mut config.environments[0];
+        env.secrets_file = Some(file.to_string_lossy().into());
+        let context = env.contexts[0].clone();
+        env.contexts = (0..2)
+            .map(|index| {
+                let mut variables = context.variables.clone().unwrap_or_default();
+                variables.insert("var".into(), format!("path-{index}"));
+                ContextConfig {
+                    name: format!("context-{index}"),
+                    variables: Some(variables),
+                    ..context.clone()
+                }
+            })
+            .collect();
+
+        // the contexts write concurrently to one file, none of the writes is lost
+        let report = sync_mappings(&test_options(), &config).await?;
+        assert!(report.error.is_none());
+        assert_eq!(report.updated(), 4);
+
+        let backend = MemoryBackend::from_file(&file)?;
+        for index in 0..2 {
+            let (value, _) = backend
+                .read("secret", &format!("test-path/path-{index}"))
+                .await?;
+            assert_eq!(
+                serde_json::to_value(value)?,
+                json!({ "mapped-field": "source value" })
+            );
+        }
+        assert_eq!(backend.read("secret", "test-path").await?.1, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_partial_report() -> Result<(), CliError> {
+        let mut config = test_config();
+        config
+            .path_templates
+            .get_or_insert_with(HashMap::new)
+            .insert("missing".into(), "{{path}}/missing".into());
+        let mut mappings = config.mappings.take().unwrap_or_default();
+        let reference = ValueRef {
+            mount: "default".into(),
+            path: "missing".into(),
+            key: "key".into(),
+        };
+        mappings.push(ValueMapping {
+            source: Some(reference.clone()),
+            target: reference,
+            ..ValueMapping::default()
+        });
+        config.mappings = Some(mappings);
+
+        // the mappings written before the failing one are reported with the error
+        let backend = MemoryBackend::new();
+        let report = sync_mappings_with(&test_options(), &config, &backend).await?;
+        assert_eq!(report.updated(), 2);
+        assert_eq!(report.error.unwrap().exit_code(), 6);
+        assert_eq!(backend.read("secret", "test-path").await?.1, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_source_cache() -> Result<(), CliError> {
+        let backend = MemoryBackend::new();
+        let state = SyncState::default();
+        let key: SecretKey = (None, "secret".into(), "app".into());
+        let value = |value: &str| HashMap::from([("key".to_string(), json!(value))]);
+
+        backend.write("secret", "app", value("first"), None).await?;
+        assert_eq!(
+            state.read_source(&backend, &key).await?,
+            Some(value("first"))
+        );
+
+        // cached until the secret is written during the run
+        backend
+            .write("secret", "app", value("second"), None)
+            .await?;
+        assert_eq!(
+            state.read_source(&backend, &key).await?,
+            Some(value("first"))
+        );
+        state.written(&key);
+        assert_eq!(
+            state.read_source(&backend, &key).await?,
+            Some(value("second"))
+        );
+
+        // a read holding the cell while the secret is written sees the new generation and
+        // reads again instead of returning the stale values
+        let (generation, cell) = state.source(&key);
+        backend.write("secret", "app", value("third"), None).await?;
+        state.written(&key);
+        assert_eq!(cell.get().cloned().flatten(), Some(value("second")));
+        assert_ne!(state.source(&key).0, generation);
+        assert_eq!(
+            state.read_source(&backend, &key).await?,
+            Some(value("third"))
+        );
+
+        Ok(())
+    }
+
+    /// the in-memory backend with all writes denied like by a vault policy
+    struct ForbiddenWrites(MemoryBackend);
+
+    #[async_trait]
+    impl SecretBackend for ForbiddenWrites {
+        async fn read(
+            &self,
+            mount: &str,
+            path: &str,
+        ) -> Result<(Option<SecretData>, u64), CliError> {
+            self.0.read(mount, path).await
+        }
+
+        async fn read_version(
+            &self,
+            mount: &str,
+            path: &str,
+            version: u64,
+        ) -> Result<Option<SecretData>, CliError> {
+            self.0.read_version(mount, path, version).await
+        }
+
+        async fn write(
+            &self,
+            _mount: &str,
+            _path: &str,
+            _data: SecretData,
+            _cas: Option<u64>,
+        ) -> Result<u64, CliError> {
+            Err(CliError::vault(403, vec!["permission denied".into()]))
+        }
+
+        async fn patch(
+            &self,
+            _mount: &str,
+            _path: &str,
+            _data: SecretData,
+            _cas: Option<u64>,
+        ) -> Result<u64, CliError> {
+            Err(CliError::vault(403, vec!["permission denied".into()]))
+        }
+
+        async fn list(&self, mount: &str, path: &str) -> Result<Vec<String>, CliError> {
+            self.0.list(mount, path).await
+        }
+
+        async fn read_metadata(
+            &self,
+            mount: &str,
+            path: &str,
+        ) -> Result<Option<SecretMetadata>, CliError> {
+            self.0.read_metadata(mount, path).await
+        }
+
+        async fn write_metadata(
+            &self,
+            _mount: &str,
+            _path: &str,
+            _custom_metadata: HashMap<String, String>,
+            _settings: &MetadataSettings,
+        ) -> Result<(), CliError> {
+            Err(CliError::vault(403, vec!["permission denied".into()]))
+        }
+
+        async fn delete(&self, _mount: &str, _path: &str) -> Result<(), CliError> {
+            Err(CliError::vault(403, vec!["permission denied".into()]))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_forbidden() -> Result<(), CliError> {
+        let backend = ForbiddenWrites(MemoryBackend::new());
+        let report = sync_mappings_with(&test_options(), &test_config(), &backend).await?;
+        assert!(report.results.is_empty());
+        let error = report.error.unwrap();
+
+        assert_eq!(error.exit_code(), 7);
+        assert!(error.message().contains(
+            "unable to set target: secret/test-path#test-field: Vault returned an error: 403"
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_error_origin() -> Result<(), CliError> {
+        let mut config = test_config();
+        let mut mappings = config.mappings.take().unwrap_or_default();
+        mappings.remove(0);
+        mappings[0].origin = Some("mappings/payments.yaml:12".into());
+        config.mappings = Some(mappings);
+
+        // the source of the mapping doesn't exist
+        let error = sync_mappings_with(&test_options(), &config, &MemoryBackend::new())
+            .await?
+            .error
+            .unwrap();
+        assert!(error
+            .to_string()
+            .contains("mapping at mappings/payments.yaml:12: unable to read source"));
+        // the kind of the backend error is kept for the exit code
+        assert_eq!(error.exit_code(), 6);
+        assert!(!error.message().contains('\u{1b}'));
+
+        Ok(())
+    }
 }
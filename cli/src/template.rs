@@ -40,7 +40,7 @@ impl StructObject for TrackedContext {
     }
 }
 
-pub fn track_context(ctx: Value) -> (Value, Arc<Mutex<HashSet<String>>>) {
+pub(crate) fn track_context(ctx: Value) -> (Value, Arc<Mutex<HashSet<String>>>) {
     let undefined = Arc::new(Mutex::default());
     (
         Value::from_struct_object(TrackedContext {
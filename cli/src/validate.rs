@@ -0,0 +1,487 @@
+//! checks a config before it is used.
+//!
+//! the merged yaml is checked against the json schema of [`Config`], which catches unknown
+//! keys and wrong types. a config that parses is then checked semantically: the templates
+//! referenced by the mappings exist, every template renders for every context without undefined
+//! variables, the `when` expressions compile, each mapping has either a source or a value and
+//! names of environments and of the contexts of an environment are unique. duplicate template
+//! names are already rejected when the yaml is parsed.
+
+use std::collections::{HashMap, HashSet};
+
+use jsonschema::JSONSchema;
+use minijinja::Environment;
+use serde_json::Value;
+
+use crate::{
+    config::{Config, ValueMapping},
+    error::CliError,
+    template::track_context,
+};
+
+/// a problem found in the config, the location is the path of the setting
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    pub location: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(location: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigIssue {
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// the json schema of the config files
+pub fn config_schema() -> Result<Value, CliError> {
+    Ok(serde_json::to_value(schemars::schema_for!(Config))?)
+}
+
+/// checks the merged yaml of the config files against the schema and if it parses, the
+/// semantics. the variables are the ones passed on the command line
+pub fn validate_config(
+    value: &serde_yaml::Value,
+    variables: &HashMap<String, String>,
+) -> Result<Vec<ConfigIssue>, CliError> {
+    let value = serde_json::to_value(value)?;
+    let mut without_origins = value.clone();
+    remove_origins(&mut without_origins);
+
+    let mut issues = check_schema(&without_origins)?;
+    match serde_json::from_value::<Config>(value) {
+        Ok(config) => issues.extend(check_config(&config, variables)),
+        Err(e) if issues.is_empty() => issues.push(ConfigIssue::new("config", e.to_string())),
+        Err(_) => {}
+    }
+    Ok(issues)
+}
+
+pub fn check_schema(value: &Value) -> Result<Vec<ConfigIssue>, CliError> {
+    let schema = config_schema()?;
+    let schema = JSONSchema::compile(&schema)
+        .map_err(|e| CliError::RuntimeError(format!("invalid config schema: {e}")))?;
+
+    let issues = match schema.validate(value) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .map(|error| {
+                ConfigIssue::new(
+                    setting_path(&error.instance_path.to_string()),
+                    error.to_string(),
+                )
+            })
+            .collect(),
+    };
+    Ok(issues)
+}
+
+/// the semantic checks of a parsed config
+pub fn check_config(config: &Config, variables: &HashMap<String, String>) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    let mount_templates: HashMap<String, String> = config
+        .mount_templates
+        .iter()
+        .flatten()
+        .map(|(name, template)| (name.clone(), template.template().to_string()))
+        .collect();
+    let path_templates = config.path_templates.clone().unwrap_or_default();
+    let mappings = config.mappings.clone().unwrap_or_default();
+
+    let defaults = [
+        (
+            "defaultMountTemplate",
+            &config.default_mount_template,
+            &mount_templates,
+        ),
+        (
+            "defaultPathTemplate",
+            &config.default_path_template,
+            &path_templates,
+        ),
+    ];
+    for (setting, default, templates) in defaults {
+        if let Some(name) = default
+            .as_ref()
+            .filter(|name| !templates.contains_key(*name))
+        {
+            issues.push(ConfigIssue::new(
+                setting,
+                format!("template '{name}' not found"),
+            ));
+        }
+    }
+
+    for name in duplicates(config.environments.iter().map(|env| env.name.as_str())) {
+        issues.push(ConfigIssue::new(
+            format!("environments[{name}]"),
+            "the name is used by more than one environment",
+        ));
+    }
+    for env in &config.environments {
+        for name in duplicates(env.contexts.iter().map(|context| context.name.as_str())) {
+            issues.push(ConfigIssue::new(
+                format!("environments[{}].contexts[{name}]", env.name),
+                "the name is used by more than one context of the environment",
+            ));
+        }
+    }
+
+    for (index, mapping) in mappings.iter().enumerate() {
+        let location = mapping_location(index, mapping);
+
+        match (&mapping.source, &mapping.value) {
+            (Some(_), Some(_)) => issues.push(ConfigIssue::new(
+                &location,
+                "has both source and value, only one is allowed",
+            )),
+            (None, None) => issues.push(ConfigIssue::new(&location, "needs a source or a value")),
+            _ => {}
+        }
+
+        for reference in mapping.source.iter().chain([&mapping.target]) {
+            if !mount_templates.contains_key(&reference.mount) {
+                issues.push(ConfigIssue::new(
+                    &location,
+                    format!(
+                        "mount template '{}' of {reference} not found",
+                        reference.mount
+                    ),
+                ));
+            }
+            if !path_templates.contains_key(&reference.path) {
+                issues.push(ConfigIssue::new(
+                    &location,
+                    format!(
+                        "path template '{}' of {reference} not found",
+                        reference.path
+                    ),
+                ));
+            }
+        }
+
+        if let Some(when) = &mapping.when {
+            if let Err(e) = Environment::new().compile_expression(when) {
+                issues.push(ConfigIssue::new(
+                    &location,
+                    format!("invalid when expression: {e}"),
+                ));
+            }
+        }
+    }
+
+    for env in &config.environments {
+        for context in &env.contexts {
+            let location = format!("environments[{}].contexts[{}]", env.name, context.name);
+
+            // service and path are only known when running a command
+            let values = match context.variables_map(
+                config,
+                env,
+                Some("path".into()),
+                Some("service".into()),
+                Some(variables.clone()),
+            ) {
+                Ok(values) => values,
+                Err(e) => {
+                    issues.push(ConfigIssue::new(&location, e.to_string()));
+                    continue;
+                }
+            };
+
+            let mut templates: Vec<(String, String)> = Vec::new();
+            templates.extend(
+                mount_templates
+                    .iter()
+                    .map(|(name, template)| (format!("mount template '{name}'"), template.clone())),
+            );
+            templates.extend(
+                path_templates
+                    .iter()
+                    .map(|(name, template)| (format!("path template '{name}'"), template.clone())),
+            );
+            templates.extend(env.namespace.iter().map(|namespace| {
+                (
+                    format!("namespace of environment {}", env.name),
+                    namespace.clone(),
+                )
+            }));
+            templates.extend(
+                context
+                    .namespace
+                    .iter()
+                    .map(|namespace| ("namespace".to_string(), namespace.clone())),
+            );
+            templates.extend(
+                config
+                    .default_metadata
+                    .iter()
+                    .flatten()
+                    .map(|(key, value)| (format!("defaultMetadata.{key}"), value.clone())),
+            );
+
+            for (index, mapping) in mappings.iter().enumerate() {
+                let mapping_location = mapping_location(index, mapping);
+                if let Some(when) = &mapping.when {
+                    // invalid expressions are already reported once for the mapping
+                    if Environment::new().compile_expression(when).is_err() {
+                        continue;
+                    }
+                    match evaluate(when, &values) {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        Err(reason) => {
+                            issues.push(ConfigIssue::new(
+                                &location,
+                                format!("when of {mapping_location}: {reason}"),
+                            ));
+                            continue;
+                        }
+                    }
+                }
+                templates.extend(
+                    mapping
+                        .value
+                        .iter()
+                        .map(|value| (format!("value of {mapping_location}"), value.clone())),
+                );
+                templates.extend(mapping.metadata.iter().flatten().map(|(key, value)| {
+                    (
+                        format!("metadata.{key} of {mapping_location}"),
+                        value.clone(),
+                    )
+                }));
+            }
+
+            for (name, template) in templates {
+                if let Err(reason) = render(&template, &values) {
+                    issues.push(ConfigIssue::new(&location, format!("{name}: {reason}")));
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+/// the names occurring more than once, each once in the order they first occur
+fn duplicates<'a>(names: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut duplicates: Vec<&str> = Vec::new();
+    for name in names {
+        if !seen.insert(name) && !duplicates.contains(&name) {
+            duplicates.push(name);
+        }
+    }
+    duplicates
+}
+
+/// renders the template and fails on syntax errors and undefined variables
+fn render(template: &str, values: &HashMap<String, String>) -> Result<(), String> {
+    let (context, undefined) = track_context(values.clone().into());
+    Environment::new()
+        .render_str(template, context)
+        .map_err(|e| e.to_string())?;
+    let undefined = undefined.lock().unwrap();
+    undefined_error(&undefined)
+}
+
+/// evaluates the expression and fails on undefined variables
+fn evaluate(expression: &str, values: &HashMap<String, String>) -> Result<bool, String> {
+    let environment = Environment::new();
+    let expression = environment
+        .compile_expression(expression)
+        .map_err(|e| e.to_string())?;
+    let (context, undefined) = track_context(values.clone().into());
+    let result = expression.eval(context).map_err(|e| e.to_string())?;
+    let undefined = undefined.lock().unwrap();
+    undefined_error(&undefined)?;
+    Ok(result.is_true())
+}
+
+fn undefined_error(undefined: &HashSet<String>) -> Result<(), String> {
+    if undefined.is_empty() {
+        return Ok(());
+    }
+    let mut names: Vec<&String> = undefined.iter().collect();
+    names.sort();
+    Err(format!("undefined variables {names:?}"))
+}
+
+fn mapping_location(index: usize, mapping: &ValueMapping) -> String {
+    match &mapping.origin {
+        Some(origin) => format!("mappings[{index}] ({origin})"),
+        None => format!("mappings[{index}]"),
+    }
+}
+
+/// the origins of the mappings are added by the loader, they are not part of the schema
+fn remove_origins(value: &mut Value) {
+    if let Some(Value::Array(mappings)) = value.get_mut("mappings") {
+        mappings
+            .iter_mut()
+            .filter_map(Value::as_object_mut)
+            .for_each(|mapping| {
+                mapping.remove("_origin");
+            });
+    }
+}
+
+/// `/environments/0/contexts/1` to `environments[0].contexts[1]`
+fn setting_path(pointer: &str) -> String {
+    let mut path = String::new();
+    for part in pointer.split('/').filter(|part| !part.is_empty()) {
+        if part.chars().all(|c| c.is_ascii_digit()) {
+            path.push_str(&format!("[{part}]"));
+        } else {
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(part);
+        }
+    }
+    if path.is_empty() {
+        path.push_str("config");
+    }
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use crate::{
+        error::CliError,
+        loader::load_texts,
+        validate::{check_config, config_schema, validate_config},
+    };
+
+    const CONFIG: &str = r#"
+defaultOwner: owner
+defaultMountTemplate: default
+defaultPathTemplate: default
+mountTemplates:
+  default: "kv2/{{service}}"
+pathTemplates:
+  default: "{{environment}}/{{foo}}/{{path}}"
+environments:
+  - name: staging
+    vaultAddress: http://localhost:8200
+    contexts:
+      - name: prod
+        variables:
+          foo: bar
+      - name: dev
+mappings:
+  - value: "{{foo}}"
+    target: {mount: default, path: default, key: A}
+    when: "context == 'prod'"
+"#;
+
+    fn issues(yaml: &str, variables: &[(&str, &str)]) -> Result<Vec<String>, CliError> {
+        let value: serde_yaml::Value = serde_yaml::from_str(yaml)?;
+        let variables: HashMap<String, String> = variables
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Ok(validate_config(&value, &variables)?
+            .into_iter()
+            .map(|issue| format!("{}: {}", issue.location, issue.message))
+            .collect())
+    }
+
+    #[test]
+    fn test_validate_templates() -> Result<(), CliError> {
+        // the mapping is skipped for dev by its when expression, the path template is not
+        assert_eq!(
+            issues(CONFIG, &[])?,
+            vec![
+                "environments[staging].contexts[dev]: path template 'default': undefined variables [\"foo\"]"
+            ]
+        );
+        assert!(issues(CONFIG, &[("foo", "value")])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_mappings_and_schema() -> Result<(), CliError> {
+        let config = format!(
+            r#"{CONFIG}
+  - source: {{mount: default, path: missing, key: A}}
+    value: "x"
+    target: {{mount: other, path: default, key: B}}
+    when: "context =="
+  - target: {{mount: default, path: default, key: C}}
+    _origin: mappings.yaml:3
+"#
+        );
+        let found = issues(&config, &[("foo", "value")])?;
+        assert_eq!(found.len(), 5, "{found:?}");
+        assert!(found[0].starts_with("mappings[1]: has both source and value"));
+        assert!(found[1].contains("path template 'missing' of default/missing#A not found"));
+        assert!(found[2].contains("mount template 'other' of other/default#B not found"));
+        assert!(found[3].contains("invalid when expression"));
+        assert_eq!(
+            found[4],
+            "mappings[2] (mappings.yaml:3): needs a source or a value"
+        );
+
+        let found = issues(
+            &CONFIG.replace("    vaultAddress:", "    vaultAdress:"),
+            &[],
+        )?;
+        assert_eq!(found.len(), 2, "{found:?}");
+        assert!(found
+            .iter()
+            .all(|issue| issue.starts_with("environments[0]: ")));
+        assert!(found
+            .iter()
+            .any(|issue| issue.contains("'vaultAdress' was unexpected")));
+
+        let config: crate::config::Config = serde_yaml::from_str(CONFIG)?;
+        assert_eq!(check_config(&config, &HashMap::new()).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_duplicate_names() -> Result<(), CliError> {
+        let config = CONFIG.replace(
+            "      - name: dev\n",
+            "      - name: dev\n      - name: prod\n  - name: staging\n    vaultAddress: http://other\n    contexts: []\n",
+        );
+        let found = issues(&config, &[("foo", "value")])?;
+        assert_eq!(
+            found,
+            vec![
+                "environments[staging]: the name is used by more than one environment",
+                "environments[staging].contexts[prod]: the name is used by more than one context of the environment",
+            ]
+        );
+
+        // templates are maps, the parser refuses keys which occur twice
+        let config = CONFIG.replace(
+            "pathTemplates:\n",
+            "pathTemplates:\n  default: \"{{path}}\"\n",
+        );
+        let error = load_texts(&[(PathBuf::from("config.yaml"), config)]).unwrap_err();
+        assert_eq!(error.exit_code(), 4);
+        assert!(error
+            .message()
+            .contains("duplicate entry with key \"default\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_published_schema() -> Result<(), CliError> {
+        let published: serde_json::Value =
+            serde_json::from_str(include_str!("../config.schema.json"))?;
+        assert_eq!(
+            published,
+            config_schema()?,
+            "config.schema.json is outdated, update it with 'tresor config schema'"
+        );
+        Ok(())
+    }
+}
@@ -1,422 +1,871 @@
-use std::collections::HashMap;
+//! vault implementation of the secret backend for kv version 1 and 2 mounts
+//!
+//! the version of a mount is taken from its mount template or detected with
+//! `sys/internal/ui/mounts` once per run, a mount which can't be read there fails. kv1 keeps no
+//! versions or metadata, secrets read from it have version 1 and metadata is not written.
 
-use actix_web::{dev::Server, get, web, App, HttpResponse, HttpServer};
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
 use chrono::Utc;
 use once_cell::sync::Lazy;
+use reqwest::Method;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use tokio::process::Command;
-use tokio::sync::Mutex;
 use vaultrs::{
-    api::kv2::requests::SetSecretMetadataRequestBuilder,
+    api::kv2::requests::{ReadSecretRequest, SetSecretMetadataRequestBuilder},
     client::{VaultClient, VaultClientSettingsBuilder},
+    error::ClientError,
 };
 
 use crate::{
-    config::{get_env, load_or_create_config, write_token, Config, EnvironmentConfig},
-    console::Console,
+    backend::{SecretBackend, SecretData, SecretMetadata, SecretVersion},
+    config::MetadataSettings,
+    diff::patch_values,
     error::CliError,
-    MetadataArgs,
+    http::HttpClient,
+    permissions::{missing_capabilities, Operation, PermissionCheck, PermissionResult},
 };
 
-static AUTH_RESPONSE: Lazy<Mutex<Option<VaultAuthResponse>>> = Lazy::new(|| Mutex::new(None));
-static SHUTDOWN_SIGNAL: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
-
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-pub struct CallbackParams {
-    state: String,
-    code: String,
-    scope: String,
-    hd: Option<String>,
-    prompt: Option<String>,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-#[allow(dead_code)]
-pub struct VaultAuthResponse {
-    pub auth: AuthInfo,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[allow(dead_code)]
 pub struct VaultSecretResponse {
-    pub lease_duration: Option<u64>,
-    pub lease_id: Option<String>,
     pub data: serde_json::Value,
-    pub renewable: Option<bool>,
-    pub request_id: Option<String>,
 }
 
-#[derive(Deserialize, Clone, Debug, Serialize)]
-pub struct VaultSecretMetadata {
-    pub created_time: String,
-    pub deletion_time: String,
-    pub custom_metadata: Option<HashMap<String, String>>,
-    pub destroyed: bool,
-    pub version: u64,
+/// vault address, namespace and mount
+type MountKey = (String, Option<String>, String);
+
+/// versions detected during this run
+static DETECTED_KV_VERSIONS: Lazy<Mutex<HashMap<MountKey, KvVersion>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// version of the kv secrets engine of a mount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvVersion {
+    V1,
+    V2,
 }
 
-#[derive(Clone, Debug, Deserialize)]
-#[allow(dead_code)]
-pub struct AuthInfo {
-    pub client_token: String,
-    pub identity_policies: Vec<String>,
-    pub lease_duration: u64,
+#[derive(Deserialize)]
+struct MountInfo {
+    #[serde(rename = "type")]
+    engine: Option<String>,
+    options: Option<HashMap<String, String>>,
 }
 
-pub fn create_client(vault_url: &str, token: Option<String>) -> Result<VaultClient, CliError> {
+/// vaultrs client sending its requests with the given http client, so the tls settings of the
+/// environment are used instead of the ones vaultrs reads from the `VAULT_*` variables
+pub fn create_client(
+    vault_url: &str,
+    token: Option<String>,
+    http: &HttpClient,
+) -> Result<VaultClient, CliError> {
     let mut settings = VaultClientSettingsBuilder::default();
-    settings.address(vault_url);
+    settings
+        .address(vault_url)
+        .ca_certs(Vec::new())
+        .identity(None)
+        .verify(true);
 
     if let Some(token) = token {
         settings.token(token);
     }
+    if let Some(namespace) = http.namespace() {
+        settings.set_namespace(namespace.clone());
+    }
 
-    let client = VaultClient::new(settings.build()?)?;
+    let mut client = VaultClient::new(settings.build()?)?;
+    client.http = rustify::clients::reqwest::Client::new(vault_url, http.client().clone());
     Ok(client)
 }
 
+/// client for the kv secrets engines of one vault environment
 pub struct Vault {
     pub vault_url: String,
     pub token: String,
+    client: VaultClient,
+    http: HttpClient,
+    /// versions of mounts which are not detected
+    kv_versions: HashMap<String, KvVersion>,
 }
 
 impl Vault {
-    pub fn create(vault_url: &str, token: &str) -> Vault {
-        Vault {
+    pub fn create(vault_url: &str, token: &str, http: HttpClient) -> Result<Vault, CliError> {
+        Ok(Vault {
             vault_url: vault_url.to_owned(),
             token: token.to_owned(),
+            client: create_client(vault_url, Some(token.to_owned()), &http)?,
+            http,
+            kv_versions: HashMap::new(),
+        })
+    }
+
+    /// uses the given versions for their mounts instead of detecting them
+    pub fn with_kv_versions(self, kv_versions: HashMap<String, KvVersion>) -> Vault {
+        Vault {
+            kv_versions,
+            ..self
+        }
+    }
+
+    /// the underlying vaultrs client for operations not covered by [`SecretBackend`]
+    pub fn client(&self) -> &VaultClient {
+        &self.client
+    }
+
+    /// the configured or detected kv version of the mount, fails if the mount can't be read
+    pub async fn kv_version(&self, mount: &str) -> Result<KvVersion, CliError> {
+        if let Some(version) = self.kv_versions.get(mount) {
+            return Ok(*version);
         }
+        let key = (
+            self.vault_url.clone(),
+            self.http.namespace().cloned(),
+            mount.to_string(),
+        );
+        if let Some(version) = DETECTED_KV_VERSIONS.lock().unwrap().get(&key) {
+            return Ok(*version);
+        }
+
+        let version = self.detect_kv_version(mount).await?;
+        DETECTED_KV_VERSIONS.lock().unwrap().insert(key, version);
+        Ok(version)
+    }
+
+    async fn detect_kv_version(&self, mount: &str) -> Result<KvVersion, CliError> {
+        let info = self
+            .send::<VaultSecretResponse>(
+                Method::GET,
+                &format!("sys/internal/ui/mounts/{mount}"),
+                "application/json",
+                serde_json::Value::Null,
+            )
+            .await
+            .and_then(|response| Ok(serde_json::from_value::<MountInfo>(response.data)?))
+            .map_err(|e| {
+                e.prefixed(&format!(
+                    "unable to detect the kv version of mount {mount}, set kvVersion on its mount template"
+                ))
+            })?;
+
+        let version = match info.engine.as_deref() {
+            Some("kv") | Some("generic") => {
+                let version = info
+                    .options
+                    .and_then(|options| options.get("version").cloned());
+                match version.as_deref() {
+                    Some("2") => KvVersion::V2,
+                    _ => KvVersion::V1,
+                }
+            }
+            engine => {
+                tracing::warn!(mount, engine, "mount is not a kv secrets engine, using kv2");
+                KvVersion::V2
+            }
+        };
+        tracing::debug!(mount, version = ?version, "kv version");
+        Ok(version)
+    }
+
+    /// the api path an operation uses and the capabilities it needs there
+    async fn capability_path(
+        &self,
+        check: &PermissionCheck,
+    ) -> Result<(String, &'static [&'static str]), CliError> {
+        let (mount, path) = (&check.mount, &check.path);
+        let data_path = format!("{mount}/data/{path}");
+        let metadata_path = format!("{mount}/metadata/{path}");
+
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            let required: &[&str] = match check.operation {
+                Operation::Read => &["read"],
+                Operation::Write => &["create", "update"],
+                Operation::Patch => &["read", "update"],
+                Operation::List => &["list"],
+                Operation::Delete => &["delete"],
+                Operation::Metadata => &[],
+            };
+            return Ok((format!("{mount}/{path}"), required));
+        }
+
+        Ok(match check.operation {
+            Operation::Read => (data_path, &["read"]),
+            Operation::Write => (data_path, &["create", "update"]),
+            Operation::Patch => (data_path, &["patch"]),
+            Operation::List => (metadata_path, &["list"]),
+            Operation::Delete => (metadata_path, &["delete"]),
+            Operation::Metadata => (metadata_path, &["create", "update"]),
+        })
+    }
+
+    async fn read_v1(&self, mount: &str, path: &str) -> Result<Option<SecretData>, CliError> {
+        let read = || vaultrs::kv1::get::<SecretData>(&self.client, mount, path);
+        match self
+            .http
+            .execute("GET", &format!("{mount}/{path}"), read)
+            .await
+        {
+            Ok(data) => Ok(Some(data)),
+            Err(ClientError::APIError { code: 404, .. }) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn write_v1(&self, mount: &str, path: &str, data: &SecretData) -> Result<u64, CliError> {
+        let data: HashMap<&str, &serde_json::Value> = data
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+            .collect();
+        let write = || vaultrs::kv1::set(&self.client, mount, path, &data);
+        self.http
+            .execute("POST", &format!("{mount}/{path}"), write)
+            .await?;
+        Ok(1)
     }
 
-    pub async fn set_data(
+    async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        content_type: &str,
+        data: serde_json::Value,
+    ) -> Result<T, CliError> {
+        let url = format!("{}/v1/{}", self.vault_url, path);
+        let response = self
+            .http
+            .execute(method.as_str(), path, || {
+                let request = self
+                    .http
+                    .request(method.clone(), &url)
+                    .header("X-Vault-Token", &self.token)
+                    .header("X-Vault-Request", true.to_string())
+                    .header("Content-Type", content_type);
+                match &data {
+                    serde_json::Value::Null => request,
+                    data => request.json(data),
+                }
+                .send()
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(response_error(response).await?);
+        }
+
+        let status = response.status().as_u16();
+        let body_string = response.text().await?;
+
+        // the body contains secret data, only the position of the error is kept
+        serde_json::from_str::<T>(&body_string).map_err(|e| CliError::VaultError {
+            status: Some(status),
+            errors: Vec::new(),
+            reason: format!(
+                "unable to parse the response of {path} at line {} column {}",
+                e.line(),
+                e.column()
+            ),
+        })
+    }
+}
+
+#[async_trait]
+impl SecretBackend for Vault {
+    async fn read(&self, mount: &str, path: &str) -> Result<(Option<SecretData>, u64), CliError> {
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            let data = self.read_v1(mount, path).await?;
+            let version = if data.is_some() { 1 } else { 0 };
+            return Ok((data, version));
+        }
+
+        let read = || {
+            let endpoint = ReadSecretRequest {
+                mount: mount.to_string(),
+                path: path.to_string(),
+                version: None,
+            };
+            vaultrs::api::exec_with_result(&self.client, endpoint)
+        };
+
+        match self
+            .http
+            .execute("GET", &format!("{mount}/data/{path}"), read)
+            .await
+        {
+            Ok(response) => Ok((
+                Some(serde_json::from_value(response.data)?),
+                response.metadata.version,
+            )),
+            Err(ClientError::APIError { code: 404, .. }) => {
+                Ok((None, self.current_version(mount, path).await?))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn read_version(
         &self,
         mount: &str,
         path: &str,
-        data: HashMap<String, String>,
-    ) -> Result<VaultSecretResponse, CliError> {
-        let path = format!("{mount}/data/{path}");
-        let result = post::<VaultSecretResponse>(
-            &self.vault_url,
-            &self.token,
-            &path,
-            serde_json::json!({ "data": data }),
-        )
-        .await?;
+        version: u64,
+    ) -> Result<Option<SecretData>, CliError> {
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            return match version {
+                1 => self.read_v1(mount, path).await,
+                _ => Ok(None),
+            };
+        }
 
-        Ok(result)
+        let read = || vaultrs::kv2::read_version(&self.client, mount, path, version);
+        let data_path = format!("{mount}/data/{path}?version={version}");
+        match self.http.execute("GET", &data_path, read).await {
+            Ok(data) => Ok(Some(data)),
+            Err(ClientError::APIError { code: 404, .. }) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
     }
 
-    pub async fn patch_data(
+    async fn write(
         &self,
         mount: &str,
         path: &str,
-        data: HashMap<String, String>,
-    ) -> Result<VaultSecretResponse, CliError> {
-        let path = format!("{mount}/data/{path}");
-        let result = patch::<VaultSecretResponse>(
-            &self.vault_url,
-            &self.token,
-            &path,
-            serde_json::json!({ "data": data }),
-        )
-        .await?;
+        data: SecretData,
+        cas: Option<u64>,
+    ) -> Result<u64, CliError> {
+        // kv1 has no check-and-set, the secret is overwritten
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            return self.write_v1(mount, path, &data).await;
+        }
+
+        let data_path = format!("{mount}/data/{path}");
+        let result: VaultSecretResponse = self
+            .send(
+                Method::POST,
+                &data_path,
+                "application/json",
+                data_with_options(data, cas),
+            )
+            .await
+            .map_err(|e| cas_conflict_for_path(e, mount, path, cas))?;
+
+        written_version(&result, mount, path)
+    }
 
-        println!("patch data: {:?}", result);
+    async fn patch(
+        &self,
+        mount: &str,
+        path: &str,
+        data: SecretData,
+        cas: Option<u64>,
+    ) -> Result<u64, CliError> {
+        // kv1 can't patch, the secret is read, merged and written again
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            let current = self.read_v1(mount, path).await?.ok_or_else(|| {
+                CliError::NotFoundError(format!("no secret to patch at {mount}/{path}"))
+            })?;
+            return self
+                .write_v1(mount, path, &patch_values(&current, &data))
+                .await;
+        }
 
-        Ok(result)
+        let data_path = format!("{mount}/data/{path}");
+        let result: VaultSecretResponse = self
+            .send(
+                Method::PATCH,
+                &data_path,
+                "application/merge-patch+json",
+                data_with_options(data, cas),
+            )
+            .await
+            .map_err(|e| cas_conflict_for_path(e, mount, path, cas))?;
+
+        written_version(&result, mount, path)
     }
-}
 
-async fn post<T: DeserializeOwned>(
-    vault_url: &str,
-    token: &str,
-    path: &str,
-    data: serde_json::Value,
-) -> Result<T, CliError> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/v1/{}", vault_url, path);
-    let response = client
-        .post(&url)
-        .header("X-Vault-Token", token)
-        .header("X-Vault-Request", true.to_string())
-        .header("Content-Type", "application/json")
-        .json(&data)
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        return Err(CliError::VaultError(format!(
-            "Vault returned an error: {}, {}",
-            response.status(),
-            response.text().await?
-        )));
-    }
-
-    let body_string = response.text().await?;
-
-    let parsed = serde_json::from_str::<T>(&body_string);
-
-    match parsed {
-        Ok(result) => Ok(result),
-        Err(e) => {
-            println!("error parsing response: {}", body_string);
-            Err(CliError::VaultError(format!(
-                "Error parsing response: {}",
-                e.to_string()
-            )))
+    async fn list(&self, mount: &str, path: &str) -> Result<Vec<String>, CliError> {
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            let list = || vaultrs::kv1::list(&self.client, mount, path);
+            return match self
+                .http
+                .execute("LIST", &format!("{mount}/{path}"), list)
+                .await
+            {
+                Ok(response) => Ok(response.data.keys),
+                Err(ClientError::APIError { code: 404, .. }) => Ok(Vec::new()),
+                Err(err) => Err(err.into()),
+            };
+        }
+
+        let list = || vaultrs::kv2::list(&self.client, mount, path);
+        match self
+            .http
+            .execute("LIST", &format!("{mount}/metadata/{path}"), list)
+            .await
+        {
+            Ok(entries) => Ok(entries),
+            Err(ClientError::APIError { code: 404, .. }) => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
         }
     }
-}
 
-async fn patch<T: DeserializeOwned>(
-    vault_url: &str,
-    token: &str,
-    path: &str,
-    data: serde_json::Value,
-) -> Result<T, CliError> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/v1/{}", vault_url, path);
-    let response = client
-        .patch(url)
-        .header("X-Vault-Token", token)
-        .header("X-Vault-Request", true.to_string())
-        .header("Content-Type", "application/merge-patch+json")
-        .json(&data)
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        return Err(CliError::VaultError(format!(
-            "Vault returned an error: {}, {}",
-            response.status(),
-            response.text().await?
-        )));
-    }
-
-    let body_string = response.text().await?;
-
-    let parsed = serde_json::from_str::<T>(&body_string);
-
-    match parsed {
-        Ok(result) => Ok(result),
-        Err(e) => {
-            println!("error parsing response: {}", body_string);
-            Err(CliError::VaultError(format!(
-                "Error parsing response: {}",
-                e.to_string()
-            )))
+    async fn read_metadata(
+        &self,
+        mount: &str,
+        path: &str,
+    ) -> Result<Option<SecretMetadata>, CliError> {
+        // kv1 has no metadata, an existing secret has a single version
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            let exists = self.read_v1(mount, path).await?.is_some();
+            return Ok(exists.then(|| SecretMetadata {
+                current_version: 1,
+                versions: vec![SecretVersion {
+                    version: 1,
+                    ..SecretVersion::default()
+                }],
+                ..SecretMetadata::default()
+            }));
+        }
+
+        let read = || vaultrs::kv2::read_metadata(&self.client, mount, path);
+        match self
+            .http
+            .execute("GET", &format!("{mount}/metadata/{path}"), read)
+            .await
+        {
+            Ok(metadata) => Ok(Some(SecretMetadata {
+                current_version: metadata.current_version,
+                created_time: metadata.created_time,
+                updated_time: metadata.updated_time,
+                custom_metadata: metadata.custom_metadata.unwrap_or_default(),
+                settings: MetadataSettings {
+                    max_versions: Some(metadata.max_versions),
+                    cas_required: Some(metadata.cas_required),
+                    delete_version_after: Some(metadata.delete_version_after),
+                },
+                versions: metadata
+                    .versions
+                    .into_iter()
+                    .filter_map(|(version, state)| {
+                        Some(SecretVersion {
+                            version: version.parse().ok()?,
+                            deleted: state.destroyed || !state.deletion_time.is_empty(),
+                            created_time: state.created_time,
+                        })
+                    })
+                    .collect(),
+            })),
+            Err(ClientError::APIError { code: 404, .. }) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn write_metadata(
+        &self,
+        mount: &str,
+        path: &str,
+        custom_metadata: HashMap<String, String>,
+        settings: &MetadataSettings,
+    ) -> Result<(), CliError> {
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            tracing::warn!(
+                mount,
+                path,
+                "kv1 mounts have no metadata, it is not written"
+            );
+            return Ok(());
+        }
+
+        let mut metadata_request = SetSecretMetadataRequestBuilder::default();
+        metadata_request.custom_metadata(custom_metadata);
+
+        if let Some(max_versions) = settings.max_versions {
+            metadata_request.max_versions(max_versions);
+        }
+        if let Some(cas_required) = settings.cas_required {
+            metadata_request.cas_required(cas_required);
         }
+        if let Some(delete_version_after) = settings.delete_version_after.clone() {
+            metadata_request.delete_version_after(delete_version_after);
+        }
+
+        self.http
+            .execute("POST", &format!("{mount}/metadata/{path}"), || {
+                let mut metadata_request = metadata_request.clone();
+                async move {
+                    vaultrs::kv2::set_metadata(
+                        &self.client,
+                        mount,
+                        path,
+                        Some(&mut metadata_request),
+                    )
+                    .await
+                }
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, mount: &str, path: &str) -> Result<(), CliError> {
+        if self.kv_version(mount).await? == KvVersion::V1 {
+            let delete = || vaultrs::kv1::delete(&self.client, mount, path);
+            let data_path = format!("{mount}/{path}");
+            return Ok(self.http.execute("DELETE", &data_path, delete).await?);
+        }
+
+        let delete = || vaultrs::kv2::delete_metadata(&self.client, mount, path);
+        let metadata_path = format!("{mount}/metadata/{path}");
+        Ok(self.http.execute("DELETE", &metadata_path, delete).await?)
+    }
+
+    async fn check_permissions(
+        &self,
+        checks: &[PermissionCheck],
+    ) -> Result<Option<Vec<PermissionResult>>, CliError> {
+        let mut paths: Vec<(String, &[&str])> = Vec::new();
+        for check in checks {
+            paths.push(self.capability_path(check).await?);
+        }
+
+        let mut api_paths: Vec<&String> = paths.iter().map(|(path, _)| path).collect();
+        api_paths.sort();
+        api_paths.dedup();
+        let response: VaultSecretResponse = self
+            .send(
+                Method::POST,
+                "sys/capabilities-self",
+                "application/json",
+                serde_json::json!({ "paths": api_paths }),
+            )
+            .await?;
+        let capabilities: HashMap<String, Vec<String>> = serde_json::from_value(response.data)?;
+
+        let results = checks
+            .iter()
+            .zip(paths)
+            .map(|(check, (api_path, required))| {
+                let granted = capabilities.get(&api_path).cloned().unwrap_or_default();
+                PermissionResult {
+                    check: check.clone(),
+                    missing: missing_capabilities(required, &granted),
+                    api_path,
+                    granted,
+                }
+            })
+            .collect();
+        Ok(Some(results))
+    }
+
+    async fn token_accessor(&self) -> Result<Option<String>, CliError> {
+        let lookup = || vaultrs::token::lookup_self(&self.client);
+        let lookup_path = "auth/token/lookup-self";
+        Ok(Some(
+            self.http
+                .execute("GET", lookup_path, lookup)
+                .await?
+                .accessor,
+        ))
     }
 }
 
-pub async fn set_metadata(
-    client: &VaultClient,
-    custom_metadata: HashMap<String, String>,
+/// the version vault created for a write, a response without it is an error
+fn written_version(
+    response: &VaultSecretResponse,
     mount: &str,
     path: &str,
-) -> Result<(), CliError> {
-    let mut metadata_request = SetSecretMetadataRequestBuilder::default();
-    metadata_request.custom_metadata(custom_metadata);
-
-    vaultrs::kv2::set_metadata(client, &mount, &path, Some(&mut metadata_request)).await?;
-    Ok(())
+) -> Result<u64, CliError> {
+    response.data["version"].as_u64().ok_or_else(|| {
+        CliError::RuntimeError(format!(
+            "vault did not return the written version of {mount}/{path}"
+        ))
+    })
 }
 
-pub fn now_date_string() -> String {
-    Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+fn data_with_options(
+    data: HashMap<String, serde_json::Value>,
+    cas: Option<u64>,
+) -> serde_json::Value {
+    match cas {
+        Some(cas) => serde_json::json!({ "options": { "cas": cas }, "data": data }),
+        None => serde_json::json!({ "data": data }),
+    }
 }
 
-pub async fn set_metadata_from_args(
-    client: &VaultClient,
-    metadata: &MetadataArgs,
-    default_owner: &str,
+pub(crate) fn cas_conflict_for_path(
+    error: CliError,
     mount: &str,
     path: &str,
-) -> Result<(), CliError> {
-    let mut custom_metadata: HashMap<String, String> = HashMap::new();
-
-    custom_metadata.insert(
-        "owner".into(),
-        metadata
-            .metadata_owner
-            .clone()
-            .unwrap_or(default_owner.to_string()),
-    );
-
-    if metadata.metadata_rotation.unwrap_or(false) {
-        custom_metadata.insert(
-            "maxTTL".into(),
-            metadata.metadata_max_ttl.clone().unwrap_or("90d".into()),
-        );
-        custom_metadata.insert("mustRotate".into(), "true".into());
-        custom_metadata.insert(
-            "lastRotation".into(),
-            metadata
-                .metadata_rotation_date
-                .clone()
-                .unwrap_or_else(|| now_date_string())
-                .into(),
-        );
-    } else {
-        println!(
-            "{}",
-            Console::warning("not setting rotation metadata (see command options)")
-        )
+    cas: Option<u64>,
+) -> CliError {
+    match (error, cas) {
+        (CliError::CasConflictError(_), Some(cas)) => CliError::CasConflictError(format!(
+            "{mount}/{path} was changed by someone else after version {cas}, read it again and retry"
+        )),
+        (CliError::CasConflictError(_), None) => CliError::CasConflictError(format!(
+            "{mount}/{path} requires check-and-set, pass the current version with --cas"
+        )),
+        (error, _) => error,
     }
-
-    self::set_metadata(client, custom_metadata, mount, path).await
 }
 
-#[get("/oidc/callback")]
-async fn oidc_callback(
-    env: web::Data<EnvironmentConfig>,
-    params: web::Query<CallbackParams>,
-) -> Result<HttpResponse, CliError> {
-    let auth_response = vault_callback(env.get_ref().to_owned(), params.into_inner()).await?;
-    AUTH_RESPONSE.lock().await.replace(auth_response);
-    Ok(HttpResponse::Ok().body("authentication successful"))
+/// the error of a failed response with the `errors` vault sent, the body itself is never
+/// included as it can contain secret data
+async fn response_error(response: reqwest::Response) -> Result<CliError, CliError> {
+    let status = response.status();
+    let errors = response_errors(response).await?;
+
+    if status == reqwest::StatusCode::BAD_REQUEST
+        && errors.iter().any(|error| error.contains("check-and-set"))
+    {
+        return Ok(CliError::CasConflictError(errors.join(", ")));
+    }
+
+    Ok(CliError::vault(status.as_u16(), errors))
 }
 
-async fn vault_callback(
-    env: EnvironmentConfig,
-    params: CallbackParams,
-) -> Result<VaultAuthResponse, CliError> {
-    let url = format!(
-        "{}/v1/auth/{}/oidc/callback",
-        env.vault_address,
-        env.auth_mount_or_default()
-    );
-
-    let client = reqwest::Client::new();
-    let mut query_params = HashMap::<String, String>::new();
-    query_params.insert("code".into(), params.code);
-    query_params.insert("state".into(), params.state);
-
-    let resp = client
-        .get(url)
-        .query(&query_params)
-        .header("X-Vault-Request", "true")
-        .send()
-        .await?;
-
-    if !resp.status().is_success() {
-        return Err(CliError::AuthError(format!(
-            "vault returned an error in auth callback: {}, {}",
-            resp.status(),
-            resp.text().await?
-        )));
-    }
-
-    let auth = resp.json::<VaultAuthResponse>().await?;
-    Ok(auth)
+/// the `errors` of a vault error response, the rest of the body is never used as it can contain
+/// secrets
+pub(crate) async fn response_errors(response: reqwest::Response) -> Result<Vec<String>, CliError> {
+    let body = response.text().await?;
+    Ok(serde_json::from_str::<VaultErrors>(&body)
+        .map(|body| body.errors)
+        .unwrap_or_default())
 }
 
-async fn start_callback_server(env: EnvironmentConfig) -> std::io::Result<Server> {
-    Ok(HttpServer::new(move || {
-        App::new()
-            .service(oidc_callback)
-            .app_data(web::Data::new(env.clone()))
-    })
-    .bind(("0.0.0.0", 8250))?
-    .run())
+#[derive(Deserialize)]
+struct VaultErrors {
+    #[serde(default)]
+    errors: Vec<String>,
 }
 
-async fn execute_open_command(url: impl AsRef<str>, cmd: &str) {
-    let output = Command::new(cmd).args([url.as_ref()]).output().await;
-    match output {
-        Ok(_) => (),
-        Err(_) => {
-            println!("auth url: {}", url.as_ref());
-        }
+pub fn join_path(base: &str, relative: &str) -> String {
+    match (base.trim_end_matches('/'), relative.trim_start_matches('/')) {
+        ("", relative) => relative.to_string(),
+        (base, "") => base.to_string(),
+        (base, relative) => format!("{base}/{relative}"),
     }
 }
 
-#[cfg(target_os = "macos")]
-async fn print_or_open_browser(url: String) {
-    execute_open_command(url, "open").await
+pub fn now_date_string() -> String {
+    Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
 }
 
-#[cfg(target_os = "linux")]
-async fn print_or_open_browser(url: String) {
-    execute_open_command(url, "xdg-open").await
-}
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
 
-// And this function only gets compiled if the target OS is *not* linux
-#[cfg(all(not(target_os = "linux"), not(target_os = "macos")))]
-fn print_or_open_browser(url: String) {
-    println!("auth url: {}", url);
-}
+    use serde_json::json;
 
-pub async fn login(
-    config: &Config,
-    environment: &str,
-    role: Option<String>,
-) -> Result<VaultAuthResponse, CliError> {
-    let env = get_env(&config, &environment).await?;
-
-    let callback_url = "http://localhost:8250/oidc/callback";
-    let auth_url_response = vaultrs::auth::oidc::auth(
-        &create_client(&env.vault_address, None)?,
-        &env.auth_mount_or_default(),
-        callback_url,
-        role.clone(),
-    )
-    .await?;
-
-    let server = start_callback_server(env.clone()).await?;
-    let handle = server.handle();
-    tokio::spawn(server);
-
-    print_or_open_browser(auth_url_response.auth_url).await;
-
-    tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.unwrap();
-        *SHUTDOWN_SIGNAL.lock().await = true;
-    });
-
-    let mut tries = 0;
-    loop {
-        let auth_response: tokio::sync::MutexGuard<'_, Option<VaultAuthResponse>> =
-            AUTH_RESPONSE.lock().await;
-
-        let shutdown_signal = SHUTDOWN_SIGNAL.lock().await;
-        if *shutdown_signal {
-            handle.stop(true).await;
-            return Err(CliError::AuthError(Console::error("canceled")));
-        }
+    use crate::{
+        backend::SecretBackend,
+        config::EnvironmentConfig,
+        error::CliError,
+        http::HttpSettings,
+        mock_server::mock_server,
+        permissions::{Operation, PermissionCheck},
+        vault::{response_errors, KvVersion, Vault},
+    };
 
-        match auth_response.as_ref() {
-            Some(auth) => {
-                println!("{}", Console::success("token received"));
-                let mut config = load_or_create_config().await?;
-                write_token(
-                    &mut config,
-                    &env,
-                    &auth.auth.client_token,
-                    auth.auth.lease_duration,
-                )
-                .await?;
-                return Ok(auth.clone());
+    fn kv2(mount: &str) -> HashMap<String, KvVersion> {
+        [(mount.to_string(), KvVersion::V2)].into()
+    }
+
+    fn secret_response() -> serde_json::Value {
+        json!({
+            "request_id": "request",
+            "lease_id": "",
+            "lease_duration": 0,
+            "renewable": false,
+            "wrap_info": null,
+            "warnings": null,
+            "auth": null,
+            "data": {
+                "data": { "key": "value" },
+                "metadata": {
+                    "created_time": "2024-01-01T00:00:00Z",
+                    "custom_metadata": null,
+                    "deletion_time": "",
+                    "destroyed": false,
+                    "version": 2
+                },
+                "version": 3
             }
-            None => (),
-        }
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-        tries += 1;
+        })
+    }
 
-        if (tries % 10) == 0 {
-            println!(
-                "{}",
-                Console::highlight(format!("waiting for callback for {tries} seconds"))
-            );
-        }
+    #[tokio::test]
+    async fn test_namespace_header() -> Result<(), CliError> {
+        let (address, requests) = mock_server(vec![(200, secret_response())]).await;
+
+        let env = EnvironmentConfig {
+            vault_address: address.clone(),
+            namespace: Some("business-unit/team".into()),
+            ..EnvironmentConfig::default()
+        };
+        let vault = Vault::create(&address, "token", env.http()?)?.with_kv_versions(kv2("secret"));
+
+        let (data, version) = vault.read("secret", "app").await?;
+        assert_eq!(version, 2);
+        assert_eq!(data.unwrap().get("key"), Some(&json!("value")));
+        assert_eq!(
+            vault
+                .write("secret", "app", Default::default(), None)
+                .await?,
+            3
+        );
 
-        if tries > 60 {
-            println!("{}", Console::error("timeout waiting for callback"));
-            break;
-        }
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests
+            .iter()
+            .all(|request| request.contains("x-vault-namespace: business-unit/team")));
+
+        Ok(())
     }
 
-    handle.stop(true).await;
-    Err(CliError::AuthError(Console::error("authentication failed")))
+    #[tokio::test]
+    async fn test_written_version() -> Result<(), CliError> {
+        let (address, _) = mock_server(vec![(200, json!({ "data": {} }))]).await;
+        let vault = Vault::create(&address, "token", EnvironmentConfig::default().http()?)?
+            .with_kv_versions(kv2("secret"));
+
+        let error = vault
+            .write("secret", "app", Default::default(), Some(1))
+            .await
+            .unwrap_err();
+        assert!(error.message().contains("written version"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_response_errors() -> Result<(), CliError> {
+        let (address, _) = mock_server(vec![(
+            400,
+            json!({ "errors": ["invalid state"], "auth": { "client_token": "s.token" } }),
+        )])
+        .await;
+
+        let response = reqwest::get(&address).await?;
+        assert_eq!(response_errors(response).await?, vec!["invalid state"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retries() -> Result<(), CliError> {
+        let env = EnvironmentConfig {
+            http: Some(HttpSettings {
+                retry_backoff_millis: Some(1),
+                ..HttpSettings::default()
+            }),
+            ..EnvironmentConfig::default()
+        };
+
+        let (address, requests) = mock_server(vec![
+            (503, json!({ "errors": ["sealed"] })),
+            (200, secret_response()),
+        ])
+        .await;
+        let vault = Vault::create(&address, "token", env.http()?)?.with_kv_versions(kv2("secret"));
+
+        assert_eq!(vault.read("secret", "app").await?.1, 2);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+
+        let (address, requests) =
+            mock_server(vec![(400, json!({ "errors": ["invalid request"] }))]).await;
+        let vault = Vault::create(&address, "token", env.http()?)?.with_kv_versions(kv2("secret"));
+
+        assert!(vault
+            .write("secret", "app", Default::default(), None)
+            .await
+            .is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_kv1() -> Result<(), CliError> {
+        let kv1_response = json!({
+            "request_id": "request",
+            "lease_id": "",
+            "lease_duration": 2764800,
+            "renewable": false,
+            "auth": null,
+            "data": { "key": "value" }
+        });
+        let (address, requests) = mock_server(vec![
+            (
+                200,
+                json!({ "data": { "type": "kv", "path": "legacy/", "options": { "version": "1" } } }),
+            ),
+            (200, kv1_response.clone()),
+            (200, kv1_response),
+            (200, json!({})),
+        ])
+        .await;
+        let vault = Vault::create(&address, "token", EnvironmentConfig::default().http()?)?;
+
+        let (data, version) = vault.read("legacy", "app").await?;
+        assert_eq!(version, 1);
+        assert_eq!(data.unwrap().get("key"), Some(&json!("value")));
+
+        let patch = [("other".to_string(), json!("patched"))].into();
+        assert_eq!(vault.patch("legacy", "app", patch, Some(1)).await?, 1);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].starts_with("get /v1/sys/internal/ui/mounts/legacy "));
+        assert!(requests[1].starts_with("get /v1/legacy/app "));
+        assert!(requests[3].starts_with("post /v1/legacy/app "));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_kv_version_not_detected() -> Result<(), CliError> {
+        let (address, requests) =
+            mock_server(vec![(403, json!({ "errors": ["permission denied"] }))]).await;
+        let vault = Vault::create(&address, "token", EnvironmentConfig::default().http()?)?;
+
+        let error = vault.read("hidden", "app").await.unwrap_err();
+        assert_eq!(error.exit_code(), 7);
+        assert!(error
+            .message()
+            .contains("unable to detect the kv version of mount hidden"));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_permissions() -> Result<(), CliError> {
+        let (address, requests) = mock_server(vec![(
+            200,
+            json!({
+                "data": {
+                    "team/data/app": ["read", "update"],
+                    "team/metadata/app": ["list"],
+                }
+            }),
+        )])
+        .await;
+        let vault = Vault::create(&address, "token", EnvironmentConfig::default().http()?)?
+            .with_kv_versions(kv2("team"));
+
+        let checks = [
+            PermissionCheck::new("team", "app", Operation::Read),
+            PermissionCheck::new("team", "app", Operation::Write),
+            PermissionCheck::new("team", "app", Operation::List),
+        ];
+        let results = vault.check_permissions(&checks).await?.unwrap();
+        assert!(results[0].is_allowed());
+        assert_eq!(results[1].missing, vec!["create".to_string()]);
+        assert_eq!(results[1].api_path, "team/data/app");
+        assert!(results[2].is_allowed());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("post /v1/sys/capabilities-self "));
+
+        Ok(())
+    }
 }